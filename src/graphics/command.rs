@@ -1,5 +1,15 @@
-use ash::vk;
-use std::default::Default;
+use ash::vk::{self, Handle};
+use std::{
+    cell::{Cell, RefCell},
+    default::Default,
+    fmt,
+    rc::Rc,
+    time::Duration,
+};
+
+use super::{
+    Buffer, GpuScope, Image, ImageView, Pipeline, ProfilerFrame, QueryFrame, ResourceState,
+};
 
 #[repr(usize)]
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -9,28 +19,132 @@ pub enum CommandType {
     Compute = 2,
 }
 
+// Binary semaphore, only used for swapchain acquire/present
 #[derive(Default, Clone, Copy, PartialEq, Debug)]
 pub struct Semaphore {
-    pub counter: u64,
     pub handle: vk::Semaphore,
 }
+define_from!(Semaphore, vk::Semaphore);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WaitError {
+    Timeout,
+    DeviceLost,
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::Timeout => write!(f, "wait timed out"),
+            WaitError::DeviceLost => write!(f, "device lost"),
+            WaitError::Vulkan(result) => write!(f, "Vulkan error: {result}"),
+        }
+    }
+}
+
+impl std::error::Error for WaitError {}
+
+impl From<vk::Result> for WaitError {
+    fn from(value: vk::Result) -> Self {
+        match value {
+            vk::Result::TIMEOUT => WaitError::Timeout,
+            vk::Result::ERROR_DEVICE_LOST => WaitError::DeviceLost,
+            result => WaitError::Vulkan(result),
+        }
+    }
+}
 
-impl Semaphore {
-    pub fn advance(&mut self) {
+// `counter` is the last value handed out by `reserve`, the GPU value trails behind
+// it. Not `Copy` on purpose, every signal value must come from the owner.
+pub struct TimelineSemaphore {
+    counter: u64,
+
+    pub device: ash::Device,
+    pub handle: vk::Semaphore,
+}
+define_from!(TimelineSemaphore, vk::Semaphore);
+
+impl TimelineSemaphore {
+    pub fn new(device: &ash::Device, initial_value: u64) -> Result<Self, vk::Result> {
+        let mut semaphore_type_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value);
+        let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut semaphore_type_info);
+        let semaphore = unsafe { device.create_semaphore(&create_info, None)? };
+
+        Ok(Self {
+            counter: initial_value,
+            device: device.clone(),
+            handle: semaphore,
+        })
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    // Next value to signal, the caller must make sure something signals it
+    pub fn reserve(&mut self) -> u64 {
         self.counter += 1;
+        self.counter
+    }
+
+    pub fn gpu_value(&self) -> Result<u64, vk::Result> {
+        unsafe { self.device.get_semaphore_counter_value(self.handle) }
+    }
+
+    pub fn wait(&self, value: u64, timeout: Duration) -> Result<(), WaitError> {
+        let semaphores = [self.handle];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(&semaphores)
+            .values(&values);
+        let timeout = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+
+        unsafe { self.device.wait_semaphores(&wait_info, timeout)? };
+        Ok(())
+    }
+
+    // Signals `value` from the CPU, values must keep increasing
+    pub fn signal(&mut self, value: u64) -> Result<(), vk::Result> {
+        let signal_info = vk::SemaphoreSignalInfo::default()
+            .semaphore(self.handle)
+            .value(value);
+        unsafe { self.device.signal_semaphore(&signal_info)? };
+        self.counter = self.counter.max(value);
+
+        Ok(())
+    }
+
+    pub fn submit_info(
+        &self,
+        value: u64,
+        stage_mask: vk::PipelineStageFlags2,
+    ) -> vk::SemaphoreSubmitInfo<'static> {
+        vk::SemaphoreSubmitInfo::default()
+            .semaphore(self.handle)
+            .value(value)
+            .stage_mask(stage_mask)
     }
 }
-define_from!(Semaphore, vk::Semaphore);
 
-#[derive(Default, Clone, Copy, PartialEq, Debug)]
 pub struct CommandQueue {
     pub family_index: u32,
-    pub semaphore: Semaphore,
+    pub semaphore: TimelineSemaphore,
 
     pub handle: vk::Queue,
 }
 define_from!(CommandQueue, vk::Queue);
 
+// Timeline value a queue submission signals, can be waited on by the CPU
+// (`Device::wait_for_token`) or by submissions on other queues.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SubmitToken {
+    pub command_type: CommandType,
+    pub value: u64,
+}
+
 pub struct CommandAllocator {
     pub command_type: CommandType,
 
@@ -38,8 +152,194 @@ pub struct CommandAllocator {
 }
 define_from!(CommandAllocator, vk::CommandPool);
 
+// A state change recorded into a command list, `resource` is the raw handle
+// of the image/buffer and `subresource` is `Image::subresource_index` (0 for buffers).
+#[derive(Clone, Copy, Debug)]
+pub struct TrackedTransition {
+    pub resource: u64,
+    pub subresource: u32,
+    pub before: ResourceState,
+    pub after: ResourceState,
+}
+
+pub struct RenderingAttachment<'a> {
+    pub image_view: &'a ImageView,
+    pub load_op: vk::AttachmentLoadOp,
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: vk::ClearValue,
+}
+
+impl RenderingAttachment<'_> {
+    fn info(&self, layout: vk::ImageLayout) -> vk::RenderingAttachmentInfo<'static> {
+        vk::RenderingAttachmentInfo::default()
+            .image_view(self.image_view.into())
+            .image_layout(layout)
+            .load_op(self.load_op)
+            .store_op(self.store_op)
+            .clear_value(self.clear_value)
+    }
+}
+
+// Returned by `CommandList::begin_rendering`, rendering ends when this is
+// dropped or `end_rendering` is called. The list stays mutably borrowed until
+// then, so only the commands below can be recorded in between.
+#[must_use]
+pub struct RenderingScope<'a> {
+    command_list: &'a mut CommandList,
+}
+
+// Barriers and transfers aren't allowed inside a rendering instance, so only
+// state, draw, scope and query commands are exposed here.
+impl RenderingScope<'_> {
+    pub fn end_rendering(self) {}
+
+    pub fn bind_pipeline(&self, pipeline: &Pipeline) -> &Self {
+        self.command_list.bind_pipeline(pipeline);
+        self
+    }
+
+    pub fn push_constants<T: bytemuck::Pod>(&self, data: &T) -> &Self {
+        self.command_list.push_constants(data);
+        self
+    }
+
+    pub fn set_viewport(&self, viewport: vk::Viewport) -> &Self {
+        unsafe {
+            self.command_list
+                .device
+                .cmd_set_viewport(self.command_list.handle, 0, &[viewport])
+        };
+        self
+    }
+
+    pub fn set_scissor(&self, scissor: vk::Rect2D) -> &Self {
+        unsafe {
+            self.command_list
+                .device
+                .cmd_set_scissor(self.command_list.handle, 0, &[scissor])
+        };
+        self
+    }
+
+    pub fn bind_index_buffer(
+        &self,
+        buffer: &Buffer,
+        offset: u64,
+        index_type: vk::IndexType,
+    ) -> &Self {
+        unsafe {
+            self.command_list.device.cmd_bind_index_buffer(
+                self.command_list.handle,
+                buffer.handle,
+                offset,
+                index_type,
+            )
+        };
+        self
+    }
+
+    pub fn draw(
+        &self,
+        vertex_count: u32,
+        instance_count: u32,
+        first_vertex: u32,
+        first_instance: u32,
+    ) -> &Self {
+        unsafe {
+            self.command_list.device.cmd_draw(
+                self.command_list.handle,
+                vertex_count,
+                instance_count,
+                first_vertex,
+                first_instance,
+            )
+        };
+        self
+    }
+
+    pub fn draw_indexed(
+        &self,
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        vertex_offset: i32,
+        first_instance: u32,
+    ) -> &Self {
+        unsafe {
+            self.command_list.device.cmd_draw_indexed(
+                self.command_list.handle,
+                index_count,
+                instance_count,
+                first_index,
+                vertex_offset,
+                first_instance,
+            )
+        };
+        self
+    }
+
+    pub fn begin_scope(&self, name: &str) -> &Self {
+        self.command_list.begin_scope(name);
+        self
+    }
+
+    pub fn end_scope(&self) -> &Self {
+        self.command_list.end_scope();
+        self
+    }
+
+    pub fn begin_occlusion_query(&self, name: &str, precise: bool) -> &Self {
+        self.command_list.begin_occlusion_query(name, precise);
+        self
+    }
+
+    pub fn end_occlusion_query(&self) -> &Self {
+        self.command_list.end_occlusion_query();
+        self
+    }
+
+    pub fn begin_statistics_query(&self, name: &str) -> &Self {
+        self.command_list.begin_statistics_query(name);
+        self
+    }
+
+    pub fn end_statistics_query(&self) -> &Self {
+        self.command_list.end_statistics_query();
+        self
+    }
+}
+
+impl Drop for RenderingScope<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            self.command_list
+                .device
+                .cmd_end_rendering(self.command_list.handle)
+        };
+    }
+}
+
 pub struct CommandList {
     pub command_type: CommandType,
+    pub transitions: RefCell<Vec<TrackedTransition>>,
+    pub descriptor_set: vk::DescriptorSet,
+    // Shared bindless layout, every pipeline is created with it
+    pub pipeline_layout: vk::PipelineLayout,
+    pub max_push_constants_size: u32,
+    // Whether `descriptor_set` is bound for graphics and compute bind points
+    pub descriptor_set_bound: Cell<[bool; 2]>,
+    // GPU PROFILER //
+    // Frame the list is recording for, set by `Device::begin_command_list`
+    pub profiler_frame: RefCell<Option<Rc<RefCell<ProfilerFrame>>>>,
+    pub timestamp_valid_bits: u32,
+    // Scopes that haven't been ended yet, `None` when a scope couldn't be recorded
+    pub open_scopes: RefCell<Vec<Option<usize>>>,
+    // GPU QUERIES //
+    pub query_frame: RefCell<Option<Rc<RefCell<QueryFrame>>>>,
+    pub occlusion_query_precise: bool,
+    // Queries between begin/end, `None` when a query couldn't be allocated
+    pub active_occlusion_query: Cell<Option<Option<u32>>>,
+    pub active_statistics_query: Cell<Option<Option<u32>>>,
 
     pub device: ash::Device,
     pub handle: vk::CommandBuffer,
@@ -56,4 +356,452 @@ impl CommandList {
                 .cmd_pipeline_barrier2(self.into(), &dependency_info)
         };
     }
+
+    pub fn begin_rendering(
+        &mut self,
+        color_attachments: &[RenderingAttachment],
+        depth_attachment: Option<RenderingAttachment>,
+        stencil_attachment: Option<RenderingAttachment>,
+        render_area: vk::Rect2D,
+        layer_count: u32,
+    ) -> RenderingScope<'_> {
+        let color_attachment_infos: Vec<vk::RenderingAttachmentInfo> = color_attachments
+            .iter()
+            .map(|attachment| attachment.info(ResourceState::COLOR_ATTACHMENT.layout))
+            .collect();
+        let depth_attachment_info = depth_attachment
+            .map(|attachment| attachment.info(ResourceState::DEPTH_ATTACHMENT.layout));
+        let stencil_attachment_info = stencil_attachment
+            .map(|attachment| attachment.info(ResourceState::DEPTH_ATTACHMENT.layout));
+
+        let mut rendering_info = vk::RenderingInfo::default()
+            .render_area(render_area)
+            .layer_count(layer_count)
+            .color_attachments(&color_attachment_infos);
+        if let Some(depth_attachment_info) = depth_attachment_info.as_ref() {
+            rendering_info = rendering_info.depth_attachment(depth_attachment_info);
+        }
+        if let Some(stencil_attachment_info) = stencil_attachment_info.as_ref() {
+            rendering_info = rendering_info.stencil_attachment(stencil_attachment_info);
+        }
+
+        unsafe {
+            self.device
+                .cmd_begin_rendering(self.handle, &rendering_info)
+        };
+
+        RenderingScope { command_list: self }
+    }
+
+    pub fn bind_pipeline(&self, pipeline: &Pipeline) {
+        unsafe {
+            self.device
+                .cmd_bind_pipeline(self.into(), pipeline.bind_point, pipeline.into());
+        };
+
+        // Every pipeline shares the bindless layout, so the set stays valid across pipelines
+        let bind_point_index = match pipeline.bind_point {
+            vk::PipelineBindPoint::COMPUTE => 1,
+            _ => 0,
+        };
+        let mut descriptor_set_bound = self.descriptor_set_bound.get();
+        if descriptor_set_bound[bind_point_index] {
+            return;
+        }
+
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                self.into(),
+                pipeline.bind_point,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            )
+        };
+        descriptor_set_bound[bind_point_index] = true;
+        self.descriptor_set_bound.set(descriptor_set_bound);
+    }
+
+    pub fn push_constants<T: bytemuck::Pod>(&self, data: &T) {
+        let size = std::mem::size_of::<T>();
+        assert!(
+            size as u32 <= self.max_push_constants_size,
+            "Push constants of size {} exceed the device limit of {} bytes",
+            size,
+            self.max_push_constants_size
+        );
+
+        unsafe {
+            self.device.cmd_push_constants(
+                self.into(),
+                self.pipeline_layout,
+                vk::ShaderStageFlags::ALL,
+                0,
+                bytemuck::bytes_of(data),
+            )
+        };
+    }
+
+    pub fn begin_scope(&self, name: &str) {
+        let mut open_scopes = self.open_scopes.borrow_mut();
+        let profiler_frame = self.profiler_frame.borrow();
+        let scope = profiler_frame
+            .as_ref()
+            .filter(|_| self.timestamp_valid_bits != 0)
+            .and_then(|profiler_frame| {
+                let mut profiler_frame = profiler_frame.borrow_mut();
+                let begin_query = profiler_frame.allocate_queries(2)?;
+                unsafe {
+                    self.device.cmd_write_timestamp2(
+                        self.into(),
+                        vk::PipelineStageFlags2::TOP_OF_PIPE,
+                        profiler_frame.query_pool,
+                        begin_query,
+                    )
+                };
+
+                profiler_frame.scopes.push(GpuScope {
+                    name: name.to_owned(),
+                    command_type: self.command_type,
+                    parent: open_scopes.iter().rev().find_map(|&scope| scope),
+                    begin_query,
+                    ended: false,
+                });
+                Some(profiler_frame.scopes.len() - 1)
+            });
+
+        open_scopes.push(scope);
+    }
+
+    pub fn end_scope(&self) {
+        let scope = self
+            .open_scopes
+            .borrow_mut()
+            .pop()
+            .expect("end_scope without begin_scope");
+        let (Some(scope), Some(profiler_frame)) = (scope, self.profiler_frame.borrow().clone())
+        else {
+            return;
+        };
+
+        let mut profiler_frame = profiler_frame.borrow_mut();
+        let query_pool = profiler_frame.query_pool;
+        let scope = &mut profiler_frame.scopes[scope];
+        unsafe {
+            self.device.cmd_write_timestamp2(
+                self.into(),
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                query_pool,
+                scope.begin_query + 1,
+            )
+        };
+        scope.ended = true;
+    }
+
+    // Counts samples passing depth/stencil tests, non-precise queries only
+    // guarantee zero vs non-zero. Graphics queue only.
+    pub fn begin_occlusion_query(&self, name: &str, precise: bool) {
+        assert!(
+            self.active_occlusion_query.get().is_none(),
+            "Occlusion queries can't be nested"
+        );
+        let query_frame = self.query_frame.borrow();
+        let query = query_frame.as_ref().and_then(|query_frame| {
+            let mut query_frame = query_frame.borrow_mut();
+            let query = query_frame.allocate_occlusion(name)?;
+            let flags = match precise && self.occlusion_query_precise {
+                true => vk::QueryControlFlags::PRECISE,
+                false => vk::QueryControlFlags::empty(),
+            };
+            unsafe {
+                self.device
+                    .cmd_begin_query(self.into(), query_frame.occlusion_pool, query, flags)
+            };
+            Some(query)
+        });
+
+        self.active_occlusion_query.set(Some(query));
+    }
+
+    pub fn end_occlusion_query(&self) {
+        let query = self
+            .active_occlusion_query
+            .take()
+            .expect("end_occlusion_query without begin_occlusion_query");
+        if let (Some(query), Some(query_frame)) = (query, self.query_frame.borrow().as_ref()) {
+            unsafe {
+                self.device
+                    .cmd_end_query(self.into(), query_frame.borrow().occlusion_pool, query)
+            };
+        }
+    }
+
+    // Graphics queue only, a no-op when the device doesn't support pipeline statistics
+    pub fn begin_statistics_query(&self, name: &str) {
+        assert!(
+            self.active_statistics_query.get().is_none(),
+            "Pipeline statistics queries can't be nested"
+        );
+        let query_frame = self.query_frame.borrow();
+        let query = query_frame.as_ref().and_then(|query_frame| {
+            let mut query_frame = query_frame.borrow_mut();
+            let query = query_frame.allocate_statistics(name)?;
+            unsafe {
+                self.device.cmd_begin_query(
+                    self.into(),
+                    query_frame.statistics_pool,
+                    query,
+                    vk::QueryControlFlags::empty(),
+                )
+            };
+            Some(query)
+        });
+
+        self.active_statistics_query.set(Some(query));
+    }
+
+    pub fn end_statistics_query(&self) {
+        let query = self
+            .active_statistics_query
+            .take()
+            .expect("end_statistics_query without begin_statistics_query");
+        if let (Some(query), Some(query_frame)) = (query, self.query_frame.borrow().as_ref()) {
+            unsafe {
+                self.device
+                    .cmd_end_query(self.into(), query_frame.borrow().statistics_pool, query)
+            };
+        }
+    }
+
+    pub fn dispatch(&self, group_count_x: u32, group_count_y: u32, group_count_z: u32) {
+        unsafe {
+            self.device
+                .cmd_dispatch(self.into(), group_count_x, group_count_y, group_count_z)
+        };
+    }
+
+    pub fn copy_buffer(
+        &self,
+        src_buffer: &Buffer,
+        dst_buffer: &Buffer,
+        regions: &[vk::BufferCopy],
+    ) {
+        unsafe {
+            self.device
+                .cmd_copy_buffer(self.into(), src_buffer.handle, dst_buffer.handle, regions)
+        };
+    }
+
+    // `image` must be in `ResourceState::TRANSFER_DST` for every copied subresource
+    pub fn copy_buffer_to_image(
+        &self,
+        buffer: &Buffer,
+        image: &Image,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        unsafe {
+            self.device.cmd_copy_buffer_to_image(
+                self.into(),
+                buffer.handle,
+                image.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                regions,
+            )
+        };
+    }
+
+    // Source subresources must be in `TRANSFER_SRC`, destination ones in `TRANSFER_DST`
+    pub fn blit_image(
+        &self,
+        src_image: &Image,
+        dst_image: &Image,
+        regions: &[vk::ImageBlit],
+        filter: vk::Filter,
+    ) {
+        unsafe {
+            self.device.cmd_blit_image(
+                self.into(),
+                src_image.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                dst_image.handle,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                regions,
+                filter,
+            )
+        };
+    }
+
+    pub fn transition(&self, image: &Image, new_state: ResourceState) {
+        self.transition_range(image, image.full_range(), new_state);
+    }
+
+    pub fn transition_range(
+        &self,
+        image: &Image,
+        range: vk::ImageSubresourceRange,
+        new_state: ResourceState,
+    ) {
+        let image_barriers: Vec<vk::ImageMemoryBarrier2> = self
+            .track_image_state(image, range, new_state)
+            .into_iter()
+            .map(|(range, old_state)| image_state_barrier(image, range, old_state, new_state))
+            .collect();
+        self.pipeline_barrier(&image_barriers, &[]);
+    }
+
+    pub fn transition_buffer(&self, buffer: &Buffer, new_state: ResourceState) {
+        if let Some(old_state) = self.track_buffer_state(buffer, new_state) {
+            let buffer_barriers = [buffer_state_barrier(buffer, old_state, new_state)];
+            self.pipeline_barrier(&[], &buffer_barriers);
+        }
+    }
+
+    pub fn pipeline_barrier(
+        &self,
+        image_barriers: &[vk::ImageMemoryBarrier2],
+        buffer_barriers: &[vk::BufferMemoryBarrier2],
+    ) {
+        if image_barriers.is_empty() && buffer_barriers.is_empty() {
+            return;
+        }
+
+        let dependency_info = vk::DependencyInfo::default()
+            .image_memory_barriers(image_barriers)
+            .buffer_memory_barriers(buffer_barriers);
+        unsafe {
+            self.device
+                .cmd_pipeline_barrier2(self.into(), &dependency_info)
+        };
+    }
+
+    // Moves `range` of the image into `new_state` without recording anything, returns
+    // the subranges that need a barrier along with their previous state.
+    pub fn track_image_state(
+        &self,
+        image: &Image,
+        range: vk::ImageSubresourceRange,
+        new_state: ResourceState,
+    ) -> Vec<(vk::ImageSubresourceRange, ResourceState)> {
+        let level_count = match range.level_count {
+            vk::REMAINING_MIP_LEVELS => image.levels - range.base_mip_level,
+            count => count,
+        };
+        let layer_count = match range.layer_count {
+            vk::REMAINING_ARRAY_LAYERS => image.slices - range.base_array_layer,
+            count => count,
+        };
+
+        let mut states = image.states.borrow_mut();
+        let mut transitions = self.transitions.borrow_mut();
+        let mut changes = Vec::new();
+        for layer in range.base_array_layer..range.base_array_layer + layer_count {
+            for level in range.base_mip_level..range.base_mip_level + level_count {
+                let index = image.subresource_index(level, layer);
+                let old_state = states[index];
+                states[index] = new_state;
+                transitions.push(TrackedTransition {
+                    resource: image.handle.as_raw(),
+                    subresource: index as u32,
+                    before: old_state,
+                    after: new_state,
+                });
+
+                // Read to read of the same state doesn't need any synchronization
+                if old_state == new_state && new_state.is_read_only() {
+                    continue;
+                }
+
+                changes.push((level, layer, old_state));
+            }
+        }
+
+        if changes.is_empty() {
+            return Vec::new();
+        }
+
+        // Whole range shares the same state, a single barrier is enough
+        let (_, _, first_state) = changes[0];
+        if changes.len() == (level_count * layer_count) as usize
+            && changes.iter().all(|&(_, _, state)| state == first_state)
+        {
+            return vec![(
+                vk::ImageSubresourceRange {
+                    level_count,
+                    layer_count,
+                    ..range
+                },
+                first_state,
+            )];
+        }
+
+        changes
+            .iter()
+            .map(|&(level, layer, old_state)| {
+                (
+                    vk::ImageSubresourceRange {
+                        aspect_mask: range.aspect_mask,
+                        base_mip_level: level,
+                        level_count: 1,
+                        base_array_layer: layer,
+                        layer_count: 1,
+                    },
+                    old_state,
+                )
+            })
+            .collect()
+    }
+
+    // Same as `track_image_state`, returns the previous state if a barrier is needed.
+    pub fn track_buffer_state(
+        &self,
+        buffer: &Buffer,
+        new_state: ResourceState,
+    ) -> Option<ResourceState> {
+        let old_state = buffer.state.replace(new_state);
+        self.transitions.borrow_mut().push(TrackedTransition {
+            resource: buffer.handle.as_raw(),
+            subresource: 0,
+            before: old_state,
+            after: new_state,
+        });
+
+        if old_state == new_state && new_state.is_read_only() {
+            return None;
+        }
+
+        Some(old_state)
+    }
+}
+
+pub fn image_state_barrier(
+    image: &Image,
+    range: vk::ImageSubresourceRange,
+    old_state: ResourceState,
+    new_state: ResourceState,
+) -> vk::ImageMemoryBarrier2<'static> {
+    vk::ImageMemoryBarrier2::default()
+        .src_stage_mask(old_state.stage_mask)
+        .src_access_mask(old_state.access_mask)
+        .dst_stage_mask(new_state.stage_mask)
+        .dst_access_mask(new_state.access_mask)
+        .old_layout(old_state.layout)
+        .new_layout(new_state.layout)
+        .subresource_range(range)
+        .image(image.into())
+}
+
+pub fn buffer_state_barrier(
+    buffer: &Buffer,
+    old_state: ResourceState,
+    new_state: ResourceState,
+) -> vk::BufferMemoryBarrier2<'static> {
+    vk::BufferMemoryBarrier2::default()
+        .src_stage_mask(old_state.stage_mask)
+        .src_access_mask(old_state.access_mask)
+        .dst_stage_mask(new_state.stage_mask)
+        .dst_access_mask(new_state.access_mask)
+        .buffer(buffer.into())
+        .offset(0)
+        .size(vk::WHOLE_SIZE)
 }
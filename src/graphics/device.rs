@@ -1,13 +1,33 @@
-use ash::{khr, vk};
+use ash::{
+    khr,
+    vk::{self, Handle},
+};
 use gpu_allocator::vulkan;
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    ffi::CString,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 use winit::window;
 
 use super::{
-    Buffer, CommandAllocator, CommandList, CommandQueue, CommandType, DescriptorPool,
-    DescriptorSet, DescriptorSetLayout, Image, ImageView, PhysicalDevice, Sampler, Semaphore,
-    SwapChain,
+    mip_level_count, view_aspect_mask, AllocationKind, Buffer, BufferID, CommandAllocator,
+    CommandList, CommandQueue, CommandType, ComputePipelineDesc, CpuScope, DescriptorPool,
+    DescriptorSet, DescriptorSetLayout, GpuProfiler, GpuQueries, GraphicsPipelineDesc, Image,
+    ImageDesc, ImageDimension, ImageError, ImageID, ImageView, MemoryReport, MemoryTracker,
+    PhysicalDevice, Pipeline, PipelineDesc, PipelineID, PipelineLayout, ResourcePool,
+    ResourceState, Sampler, SamplerDesc, SamplerID, Semaphore, Shader, ShaderError, ShaderSource,
+    SubmitToken, SwapChain, TimelineSemaphore, TraceRecorder, TraceTrack, WaitError,
 };
 
+#[cfg(feature = "hot-reload")]
+use super::ShaderWatcher;
+
+// TODO: Replace this amount with ResourcePool size in the future
+const BINDLESS_DESCRIPTOR_COUNT: u32 = 1024;
+
 #[repr(u32)]
 enum Descriptor {
     Samplers(vk::DescriptorType, u32) = 0,
@@ -17,20 +37,93 @@ enum Descriptor {
     BufferDeviceaddress(vk::DescriptorType, u32) = 4,
 }
 
+impl Descriptor {
+    const SAMPLERS_BINDING: u32 = 0;
+    const IMAGES_BINDING: u32 = 1;
+}
+
+// Kept for the last `SUBMISSION_HISTORY_SIZE` submissions, dumped when the device is lost
+#[derive(Clone, Debug)]
+pub struct SubmissionRecord {
+    pub frame: u64,
+    pub time: Instant,
+    pub queue: Option<CommandType>,
+    pub command_list_count: usize,
+    pub waits: Vec<(vk::Semaphore, u64)>,
+    pub signals: Vec<(vk::Semaphore, u64)>,
+}
+
+const SUBMISSION_HISTORY_SIZE: usize = 64;
+
 pub struct Device {
     pub physical_device: PhysicalDevice,
     pub swapchain_loader: khr::swapchain::Device,
 
     pub queues: [CommandQueue; 3],
     pub allocator: vulkan::Allocator,
+    pub memory: MemoryTracker,
     pub handle: ash::Device,
-    pub frame_sema: Semaphore,
+    pub frame_sema: TimelineSemaphore,
     pub frame_count: u32,
 
+    // STATE VALIDATION //
+    // When enabled, `submit` checks the states each command list assumed
+    // against the states left behind by previously submitted lists.
+    pub validate_states: bool,
+    pub submitted_states: RefCell<HashMap<(u64, u32), ResourceState>>,
+
+    // WATCHDOG //
+    // `new_frame` reports stuck queues every time this runs out
+    pub watchdog_timeout: Duration,
+    pub submission_history: RefCell<VecDeque<SubmissionRecord>>,
+
+    // PROFILING //
+    pub profiler: GpuProfiler,
+    pub trace: RefCell<Option<TraceRecorder>>,
+    // Occlusion and pipeline statistics results of the latest finished frame
+    pub queries: GpuQueries,
+
     // BINDLESS DESCRIPTOR SET //
     pub descriptor_pool: DescriptorPool,
     pub descriptor_set_layout: DescriptorSetLayout,
     pub descriptor_set: DescriptorSet,
+    // Descriptor type of each binding, indexed by binding number
+    pub descriptor_types: Vec<vk::DescriptorType>,
+    // Shared by every pipeline, bindless set at 0 + push constants for all stages
+    pub pipeline_layout: PipelineLayout,
+
+    pub images: ResourcePool<Image, ImageID>,
+    pub samplers: ResourcePool<Sampler, SamplerID>,
+    pub sampler_cache: HashMap<SamplerDesc, SamplerID>,
+    pub buffers: ResourcePool<Buffer, BufferID>,
+    pub pipelines: ResourcePool<Pipeline, PipelineID>,
+    pub pipeline_cache: vk::PipelineCache,
+    pub pipeline_cache_path: PathBuf,
+
+    // SHADER HOT RELOAD //
+    #[cfg(feature = "hot-reload")]
+    pub shader_watcher: Option<ShaderWatcher>,
+    // Replaced pipelines and the `frame_sema` value after which they are unused
+    #[cfg(feature = "hot-reload")]
+    pub retired_pipelines: Vec<(u64, vk::Pipeline)>,
+}
+
+// Per-user cache directory that survives reboots, the temp dir is a last resort
+fn user_cache_dir() -> PathBuf {
+    let env_dir = |name| {
+        std::env::var_os(name)
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+    };
+    let cache_dir = if cfg!(windows) {
+        env_dir("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|home| home.join("Library").join("Caches"))
+    } else {
+        env_dir("XDG_CACHE_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".cache")))
+    };
+
+    cache_dir.unwrap_or_else(std::env::temp_dir)
 }
 
 impl Device {
@@ -38,8 +131,6 @@ impl Device {
         let physical_device = PhysicalDevice::new()?;
         let handle = physical_device.create_device()?;
         let swapchain_loader = khr::swapchain::Device::new(&physical_device.instance, &handle);
-        let queues = [CommandQueue::default(); 3];
-
         let allocator = vulkan::Allocator::new(&vulkan::AllocatorCreateDesc {
             instance: physical_device.instance.clone(),
             device: handle.clone(),
@@ -49,6 +140,7 @@ impl Device {
             allocation_sizes: Default::default(),
         })
         .expect("Failed to create allocator");
+        let memory = MemoryTracker::new(physical_device.memory_properties);
 
         println!(
             "Initialized Vulkan for Physicial Device @ 0 ({}-{:?})",
@@ -56,50 +148,69 @@ impl Device {
             &physical_device.properties.device_name_as_c_str().unwrap()
         );
 
+        // Indexed by `CommandType`
+        let queues = [
+            CommandType::Graphics,
+            CommandType::Transfer,
+            CommandType::Compute,
+        ]
+        .map(|command_type| {
+            let family_index = physical_device.queue_type_indices[command_type as usize] as u32;
+            CommandQueue {
+                family_index,
+                semaphore: TimelineSemaphore::new(&handle, 0)
+                    .expect("Failed to create queue semaphore"),
+                handle: unsafe { handle.get_device_queue(family_index, 0) },
+            }
+        });
+        let frame_sema = TimelineSemaphore::new(&handle, 0)?;
+        let profiler = GpuProfiler::new(
+            &handle,
+            frame_count,
+            physical_device.properties.limits.timestamp_period,
+            physical_device.timestamp_valid_bits,
+        )?;
+        let queries = GpuQueries::new(
+            &handle,
+            frame_count,
+            physical_device.features.pipeline_statistics_query == vk::TRUE,
+        )?;
+
         let mut result = Self {
             physical_device,
             swapchain_loader,
             queues,
             allocator,
+            memory,
             handle,
-            frame_sema: Default::default(),
+            frame_sema,
             frame_count,
+            validate_states: cfg!(debug_assertions),
+            submitted_states: RefCell::new(HashMap::new()),
+            watchdog_timeout: Duration::from_secs(5),
+            submission_history: RefCell::new(VecDeque::with_capacity(SUBMISSION_HISTORY_SIZE)),
+            profiler,
+            trace: RefCell::new(None),
+            queries,
             descriptor_pool: DescriptorPool::default(),
             descriptor_set_layout: DescriptorSetLayout::default(),
             descriptor_set: DescriptorSet::default(),
+            descriptor_types: Vec::new(),
+            pipeline_layout: PipelineLayout::default(),
+            images: ResourcePool::new(),
+            samplers: ResourcePool::new(),
+            sampler_cache: HashMap::new(),
+            buffers: ResourcePool::new(),
+            pipelines: ResourcePool::new(),
+            pipeline_cache: vk::PipelineCache::null(),
+            pipeline_cache_path: PathBuf::new(),
+            #[cfg(feature = "hot-reload")]
+            shader_watcher: None,
+            #[cfg(feature = "hot-reload")]
+            retired_pipelines: Vec::new(),
         };
 
-        // Preparation
-        result.frame_sema = result.create_timeline_semaphore()?;
-        let native_queues = unsafe {
-            [
-                result.handle.get_device_queue(
-                    result.physical_device.queue_type_indices[CommandType::Graphics as usize]
-                        as u32,
-                    0,
-                ),
-                result.handle.get_device_queue(
-                    result.physical_device.queue_type_indices[CommandType::Compute as usize] as u32,
-                    0,
-                ),
-                result.handle.get_device_queue(
-                    result.physical_device.queue_type_indices[CommandType::Transfer as usize]
-                        as u32,
-                    0,
-                ),
-            ]
-        };
-
-        (0..3).for_each(|i| {
-            result.queues[i] = CommandQueue {
-                family_index: result.physical_device.queue_type_indices[i] as u32,
-                semaphore: result.create_timeline_semaphore().unwrap(),
-                handle: native_queues[i],
-            }
-        });
-
-        // TODO: Replace this amount with ResourcePool size in the future
-        let fixed_descriptor_count = 1024_u32;
+        let fixed_descriptor_count = BINDLESS_DESCRIPTOR_COUNT;
 
         let descriptor_set_layout_infos = [
             Descriptor::Samplers(vk::DescriptorType::SAMPLER, fixed_descriptor_count),
@@ -131,6 +242,8 @@ impl Device {
                     .descriptor_count(descriptor_count);
                 let binding_info = vk::DescriptorSetLayoutBinding::default()
                     .descriptor_type(descriptor_type)
+                    .descriptor_count(descriptor_count)
+                    .stage_flags(vk::ShaderStageFlags::ALL)
                     .binding(binding as u32);
                 let binding_flags = vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
                     | vk::DescriptorBindingFlags::PARTIALLY_BOUND;
@@ -138,6 +251,7 @@ impl Device {
                 descriptor_pool_sizes.push(pool_size);
                 descriptor_bindings.push(binding_info);
                 descriptor_binding_flags.push(binding_flags);
+                result.descriptor_types.push(descriptor_type);
             });
 
         let mut descriptor_binding_flag_info =
@@ -176,9 +290,105 @@ impl Device {
                 .expect("Failed to allocate bindless descriptor set")[0]
         };
 
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::ALL)
+            .offset(0)
+            .size(
+                result
+                    .physical_device
+                    .properties
+                    .limits
+                    .max_push_constants_size,
+            )];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&descriptor_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        result.pipeline_layout.0 = unsafe {
+            result
+                .handle
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .expect("Failed to create bindless pipeline layout")
+        };
+
+        result.pipeline_cache_path = result.default_pipeline_cache_path();
+        let initial_data = result.load_pipeline_cache_data();
+        let pipeline_cache_info =
+            vk::PipelineCacheCreateInfo::default().initial_data(&initial_data);
+        result.pipeline_cache = unsafe {
+            result
+                .handle
+                .create_pipeline_cache(&pipeline_cache_info, None)
+                .expect("Failed to create pipeline cache")
+        };
+
         Ok(result)
     }
 
+    // Cache files are keyed by the device identity, drivers reject data
+    // produced by other devices or driver versions anyway.
+    fn default_pipeline_cache_path(&self) -> PathBuf {
+        let properties = &self.physical_device.properties;
+        let uuid: String = properties
+            .pipeline_cache_uuid
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        user_cache_dir().join("lorr").join(format!(
+            "pipeline_cache_{:04x}_{:04x}_{}.bin",
+            properties.vendor_id, properties.device_id, uuid
+        ))
+    }
+
+    fn load_pipeline_cache_data(&self) -> Vec<u8> {
+        let Ok(data) = std::fs::read(&self.pipeline_cache_path) else {
+            return Vec::new();
+        };
+
+        // VkPipelineCacheHeaderVersionOne
+        let properties = &self.physical_device.properties;
+        let read_u32 = |offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let header_size = read_u32(0);
+        let header_version = read_u32(4);
+        let vendor_id = read_u32(8);
+        let device_id = read_u32(12);
+        let uuid = data.get(16..32);
+
+        let is_valid = header_size.is_some_and(|size| size >= 32)
+            && header_version == Some(vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32)
+            && vendor_id == Some(properties.vendor_id)
+            && device_id == Some(properties.device_id)
+            && uuid == Some(&properties.pipeline_cache_uuid[..]);
+        if !is_valid {
+            println!(
+                "Ignoring pipeline cache {}, header does not match this device",
+                self.pipeline_cache_path.display()
+            );
+            return Vec::new();
+        }
+
+        data
+    }
+
+    pub fn save_pipeline_cache(&self) -> std::io::Result<()> {
+        let data = unsafe {
+            self.handle
+                .get_pipeline_cache_data(self.pipeline_cache)
+                .map_err(std::io::Error::other)?
+        };
+
+        if let Some(dir) = self.pipeline_cache_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write to a temporary file first so a crash never leaves a truncated cache
+        let temp_path = self.pipeline_cache_path.with_extension("tmp");
+        std::fs::write(&temp_path, data)?;
+        std::fs::rename(&temp_path, &self.pipeline_cache_path)
+    }
+
     pub fn queue_at(&self, command_type: CommandType) -> &CommandQueue {
         &self.queues[command_type as usize]
     }
@@ -189,66 +399,122 @@ impl Device {
         let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut semaphore_type_info);
         let semaphore = unsafe { self.handle.create_semaphore(&create_info, None)? };
 
-        Ok(Semaphore {
-            counter: 0,
-            handle: semaphore,
-        })
+        Ok(Semaphore { handle: semaphore })
     }
 
-    pub fn create_timeline_semaphore(&self) -> Result<Semaphore, vk::Result> {
-        let mut semaphore_type_info = vk::SemaphoreTypeCreateInfo::default()
-            .semaphore_type(vk::SemaphoreType::TIMELINE)
-            .initial_value(0);
-        let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut semaphore_type_info);
-        let semaphore = unsafe { self.handle.create_semaphore(&create_info, None)? };
-
-        Ok(Semaphore {
-            counter: 0,
-            handle: semaphore,
-        })
+    pub fn create_timeline_semaphore(&self) -> Result<TimelineSemaphore, vk::Result> {
+        TimelineSemaphore::new(&self.handle, 0)
     }
 
-    pub fn wait_for_semaphore(&self, semaphore: &Semaphore, value: u64) {
-        let semaphores = [semaphore.into()];
-        let values = [value];
-        let wait_info = vk::SemaphoreWaitInfo::default()
-            .semaphores(&semaphores)
-            .values(&values);
-
-        unsafe { self.handle.wait_semaphores(&wait_info, u64::MAX).unwrap() };
-    }
+    pub fn create_image(&mut self, desc: &ImageDesc) -> Result<Image, ImageError> {
+        self.validate_image_desc(desc)?;
 
-    pub fn create_image(&mut self, create_info: vk::ImageCreateInfo) -> Result<Image, vk::Result> {
+        let name = desc.name.as_deref().unwrap_or_default();
+        let create_info = desc.create_info();
         let image = unsafe { self.handle.create_image(&create_info, None)? };
         let mem_requirements = unsafe { self.handle.get_image_memory_requirements(image) };
 
-        let allocation = self
-            .allocator
-            .allocate(&vulkan::AllocationCreateDesc {
-                name: Default::default(),
-                requirements: mem_requirements,
-                location: gpu_allocator::MemoryLocation::GpuOnly,
-                linear: true,
-                allocation_scheme: vulkan::AllocationScheme::GpuAllocatorManaged,
-            })
-            .expect("Failed to allocate image");
+        // Optimal tiling images must not share pages with linear resources
+        let allocation = self.allocate_memory(
+            name,
+            AllocationKind::Image,
+            mem_requirements,
+            desc.location,
+            desc.tiling == vk::ImageTiling::LINEAR,
+        );
 
-        unsafe {
+        let bind_result = unsafe {
             self.handle
-                .bind_image_memory(image, allocation.memory(), allocation.offset())?
+                .bind_image_memory(image, allocation.memory(), allocation.offset())
+        };
+        let view_result = bind_result.and_then(|_| match desc.create_view {
+            true => {
+                let view_info = vk::ImageViewCreateInfo::default()
+                    .view_type(desc.view_type())
+                    .format(desc.format)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: view_aspect_mask(desc.format),
+                        base_mip_level: 0,
+                        level_count: desc.mip_levels,
+                        base_array_layer: 0,
+                        layer_count: desc.array_layers,
+                    })
+                    .image(image);
+                self.create_image_view(view_info).map(Some)
+            }
+            false => Ok(None),
+        });
+        let default_view = match view_result {
+            Ok(default_view) => default_view,
+            Err(error) => {
+                self.free_memory(allocation);
+                unsafe { self.handle.destroy_image(image, None) };
+                return Err(error.into());
+            }
         };
 
         Ok(Image {
-            usage: create_info.usage,
-            format: create_info.format,
-            extent: create_info.extent,
-            slices: create_info.array_layers,
-            levels: create_info.mip_levels,
+            usage: desc.usage,
+            format: desc.format,
+            extent: desc.extent,
+            slices: desc.array_layers,
+            levels: desc.mip_levels,
+            states: RefCell::new(vec![
+                ResourceState::UNDEFINED;
+                (desc.array_layers * desc.mip_levels) as usize
+            ]),
+            default_view,
             allocation: Some(allocation),
             handle: image,
         })
     }
 
+    fn validate_image_desc(&self, desc: &ImageDesc) -> Result<(), ImageError> {
+        let name = desc.name.clone().unwrap_or_default();
+        let invalid = |reason: &str| {
+            Err(ImageError::InvalidDesc {
+                name: name.clone(),
+                reason: reason.to_owned(),
+            })
+        };
+        if desc.mip_levels == 0 || desc.mip_levels > mip_level_count(desc.extent) {
+            return invalid("mip count doesn't fit the extent");
+        }
+        match desc.dimension {
+            ImageDimension::D3 if desc.array_layers != 1 => {
+                return invalid("3D images can't have array layers");
+            }
+            ImageDimension::Cube if !desc.array_layers.is_multiple_of(6) => {
+                return invalid("cube images need 6 layers per cube");
+            }
+            ImageDimension::Cube if desc.extent.width != desc.extent.height => {
+                return invalid("cube faces must be square");
+            }
+            _ => {}
+        }
+
+        let format_properties = unsafe {
+            self.physical_device
+                .instance
+                .get_physical_device_format_properties(self.physical_device.handle, desc.format)
+        };
+        let supported_features = match desc.tiling {
+            vk::ImageTiling::LINEAR => format_properties.linear_tiling_features,
+            _ => format_properties.optimal_tiling_features,
+        };
+        let missing_features = desc.required_format_features() & !supported_features;
+        if !missing_features.is_empty() {
+            return Err(ImageError::UnsupportedFormat {
+                name,
+                format: desc.format,
+                tiling: desc.tiling,
+                missing_features,
+            });
+        }
+
+        Ok(())
+    }
+
     pub fn create_image_view(
         &self,
         create_info: vk::ImageViewCreateInfo,
@@ -261,50 +527,328 @@ impl Device {
         })
     }
 
-    pub fn create_sampler(
-        &self,
-        create_info: vk::SamplerCreateInfo,
-    ) -> Result<Sampler, vk::Result> {
-        let sampler = unsafe { self.handle.create_sampler(&create_info, None)? };
-        Ok(Sampler { handle: sampler })
+    // Returns the cached sampler for identical descriptions, new samplers are
+    // written to the bindless sampler binding at their ID.
+    pub fn create_sampler(&mut self, desc: &SamplerDesc) -> Result<SamplerID, vk::Result> {
+        if let Some(&sampler_id) = self.sampler_cache.get(desc) {
+            return Ok(sampler_id);
+        }
+
+        let limits = &self.physical_device.properties.limits;
+        let mut sampler_desc = *desc;
+        sampler_desc.max_anisotropy = desc
+            .max_anisotropy
+            .filter(|_| self.physical_device.features.sampler_anisotropy == vk::TRUE)
+            .map(|max_anisotropy| max_anisotropy.clamp(1.0, limits.max_sampler_anisotropy));
+        let sampler_limit = limits
+            .max_sampler_allocation_count
+            .min(BINDLESS_DESCRIPTOR_COUNT);
+        // IDs are indices into the bindless sampler array, check the next one
+        // before creating anything
+        if self.samplers.next_index() >= sampler_limit {
+            return Err(vk::Result::ERROR_TOO_MANY_OBJECTS);
+        }
+
+        let handle = unsafe {
+            self.handle
+                .create_sampler(&sampler_desc.create_info(), None)?
+        };
+        let Some((_, sampler_id)) = self.samplers.create(|| Sampler {
+            desc: sampler_desc,
+            handle,
+        }) else {
+            unsafe { self.handle.destroy_sampler(handle, None) };
+            return Err(vk::Result::ERROR_TOO_MANY_OBJECTS);
+        };
+
+        let image_infos = [vk::DescriptorImageInfo::default().sampler(handle)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.descriptor_set.0)
+            .dst_binding(Descriptor::SAMPLERS_BINDING)
+            .dst_array_element(sampler_id)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&image_infos);
+        unsafe { self.handle.update_descriptor_sets(&[write], &[]) };
+
+        // Keyed by the requested description, the clamped one may differ
+        self.sampler_cache.insert(*desc, sampler_id);
+
+        Ok(sampler_id)
+    }
+
+    pub fn sampler_at(&self, sampler_id: SamplerID) -> &Sampler {
+        self.samplers.get(sampler_id).expect("Invalid sampler ID")
+    }
+
+    // Samplers are shared, only destroy one nothing else uses anymore
+    pub fn destroy_sampler(&mut self, sampler_id: SamplerID) {
+        let Some(sampler) = self.samplers.destroy(sampler_id) else {
+            return;
+        };
+
+        self.sampler_cache
+            .retain(|_, &mut cached_id| cached_id != sampler_id);
+        unsafe { self.handle.destroy_sampler(sampler.handle, None) };
     }
 
     pub fn create_buffer(
         &mut self,
+        name: &str,
         create_info: vk::BufferCreateInfo,
         memory_location: gpu_allocator::MemoryLocation,
     ) -> Result<Buffer, vk::Result> {
         let buffer = unsafe { self.handle.create_buffer(&create_info, None)? };
         let mem_requirements = unsafe { self.handle.get_buffer_memory_requirements(buffer) };
 
-        let allocation = self
-            .allocator
-            .allocate(&vulkan::AllocationCreateDesc {
-                name: Default::default(),
-                requirements: mem_requirements,
-                location: memory_location,
-                linear: true,
-                allocation_scheme: vulkan::AllocationScheme::GpuAllocatorManaged,
-            })
-            .expect("Failed to allocate buffer");
+        let allocation = self.allocate_memory(
+            name,
+            AllocationKind::Buffer,
+            mem_requirements,
+            memory_location,
+            true,
+        );
 
-        unsafe {
+        let bind_result = unsafe {
             self.handle
-                .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())?
+                .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
+        };
+        if let Err(error) = bind_result {
+            self.free_memory(allocation);
+            unsafe { self.handle.destroy_buffer(buffer, None) };
+            return Err(error);
         }
 
         // Always make sure BDA is requested after `bind_buffer_memory`
-        let bda_info = vk::BufferDeviceAddressInfo::default().buffer(buffer);
-        let buffer_device_address = unsafe { self.handle.get_buffer_device_address(&bda_info) };
+        let buffer_device_address = match create_info
+            .usage
+            .contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+        {
+            true => {
+                let bda_info = vk::BufferDeviceAddressInfo::default().buffer(buffer);
+                unsafe { self.handle.get_buffer_device_address(&bda_info) }
+            }
+            false => 0,
+        };
 
         Ok(Buffer {
             data_size: mem_requirements.size,
             device_address: buffer_device_address,
-            allocation,
+            state: Cell::new(ResourceState::UNDEFINED),
+            allocation: Some(allocation),
             handle: buffer,
         })
     }
 
+    // Every device allocation goes through here so `memory_report` can see it
+    pub fn allocate_memory(
+        &mut self,
+        name: &str,
+        kind: AllocationKind,
+        requirements: vk::MemoryRequirements,
+        location: gpu_allocator::MemoryLocation,
+        linear: bool,
+    ) -> vulkan::Allocation {
+        let allocation = self
+            .allocator
+            .allocate(&vulkan::AllocationCreateDesc {
+                name,
+                requirements,
+                location,
+                linear,
+                allocation_scheme: vulkan::AllocationScheme::GpuAllocatorManaged,
+            })
+            .unwrap_or_else(|error| panic!("Failed to allocate {}: {}", name, error));
+        self.memory
+            .track(&allocation, name, kind, requirements.memory_type_bits);
+
+        allocation
+    }
+
+    pub fn free_memory(&mut self, allocation: vulkan::Allocation) {
+        self.memory.untrack(&allocation);
+        self.allocator
+            .free(allocation)
+            .expect("Failed to free memory");
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        self.memory.report(
+            &self.allocator.generate_report(),
+            self.physical_device.memory_budget().as_ref(),
+        )
+    }
+
+    // Host visible copy of `data` for transfers, destroy it once the GPU is done
+    pub fn create_staging_buffer(
+        &mut self,
+        name: &str,
+        data: &[u8],
+    ) -> Result<BufferID, vk::Result> {
+        let create_info = vk::BufferCreateInfo::default()
+            .size(data.len() as u64)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let mut buffer = self.create_buffer(
+            &format!("{name} staging"),
+            create_info,
+            gpu_allocator::MemoryLocation::CpuToGpu,
+        )?;
+        buffer
+            .allocation
+            .as_mut()
+            .and_then(|allocation| allocation.mapped_slice_mut())
+            .expect("Staging buffer isn't host visible")[..data.len()]
+            .copy_from_slice(data);
+
+        Ok(self.register_buffer(buffer))
+    }
+
+    // `GpuOnly` buffer filled with `data` and addressable through `device_address`,
+    // the upload is finished and visible to `ResourceState::GEOMETRY_READ` on return.
+    pub fn create_buffer_with_data(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        usage: vk::BufferUsageFlags,
+    ) -> Result<BufferID, vk::Result> {
+        // Zero sized buffers aren't allowed
+        let data = match data.is_empty() {
+            true => &[0; 4],
+            false => data,
+        };
+        let create_info = vk::BufferCreateInfo::default()
+            .size(data.len() as u64)
+            .usage(
+                usage
+                    | vk::BufferUsageFlags::TRANSFER_DST
+                    | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS,
+            )
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer =
+            self.create_buffer(name, create_info, gpu_allocator::MemoryLocation::GpuOnly)?;
+        let buffer_id = self.register_buffer(buffer);
+
+        let staging_buffer_id = match self.create_staging_buffer(name, data) {
+            Ok(staging_buffer_id) => staging_buffer_id,
+            Err(error) => {
+                self.destroy_buffer(buffer_id);
+                return Err(error);
+            }
+        };
+        let result = self.immediate_submit(CommandType::Graphics, |device, command_list| {
+            let buffer = device.buffer_at(buffer_id);
+            let staging_buffer = device.buffer_at(staging_buffer_id);
+            command_list.transition_buffer(buffer, ResourceState::TRANSFER_DST);
+            command_list.copy_buffer(
+                staging_buffer,
+                buffer,
+                &[vk::BufferCopy::default().size(data.len() as u64)],
+            );
+            command_list.transition_buffer(buffer, ResourceState::GEOMETRY_READ);
+        });
+        // The copy may still be running after a timeout, leak both buffers like
+        // `immediate_submit` leaks its pool
+        if result == Err(vk::Result::TIMEOUT) {
+            return Err(vk::Result::TIMEOUT);
+        }
+        self.destroy_buffer(staging_buffer_id);
+        if let Err(error) = result {
+            self.destroy_buffer(buffer_id);
+            return Err(error);
+        }
+
+        Ok(buffer_id)
+    }
+
+    pub fn register_image(&mut self, image: Image) -> ImageID {
+        let (_, image_id) = self.images.create(|| image).expect("Image pool is full");
+
+        image_id
+    }
+
+    // Sampled image descriptors are indexed by image ID, check the next one fits
+    // before creating an image that needs one
+    pub fn check_bindless_image_capacity(&self) -> Result<(), vk::Result> {
+        match self.images.next_index() < BINDLESS_DESCRIPTOR_COUNT {
+            true => Ok(()),
+            false => Err(vk::Result::ERROR_TOO_MANY_OBJECTS),
+        }
+    }
+
+    // Writes the default view of `image_id` to the bindless sampled image binding,
+    // shaders index it with the image ID.
+    pub fn write_sampled_image_descriptor(&self, image_id: ImageID) {
+        assert!(
+            image_id < BINDLESS_DESCRIPTOR_COUNT,
+            "Image {} is out of the bindless range",
+            image_id
+        );
+        let image_view = self
+            .image_at(image_id)
+            .default_view
+            .expect("Sampled images need a default view");
+
+        let image_infos = [vk::DescriptorImageInfo::default()
+            .image_view(image_view.handle)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.descriptor_set.0)
+            .dst_binding(Descriptor::IMAGES_BINDING)
+            .dst_array_element(image_id)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&image_infos);
+        unsafe { self.handle.update_descriptor_sets(&[write], &[]) };
+    }
+
+    pub fn image_at(&self, image_id: ImageID) -> &Image {
+        self.images.get(image_id).expect("Invalid image ID")
+    }
+
+    // Swapchain images are owned by the swapchain and must not be destroyed here
+    pub fn destroy_image(&mut self, image_id: ImageID) {
+        let Some(image) = self.images.destroy(image_id) else {
+            return;
+        };
+
+        if let Some(default_view) = image.default_view {
+            unsafe { self.handle.destroy_image_view(default_view.handle, None) };
+        }
+        if let Some(allocation) = image.allocation {
+            self.free_memory(allocation);
+        }
+        self.forget_submitted_states(image.handle.as_raw());
+        unsafe { self.handle.destroy_image(image.handle, None) };
+    }
+
+    pub fn register_buffer(&mut self, buffer: Buffer) -> BufferID {
+        let (_, buffer_id) = self.buffers.create(|| buffer).expect("Buffer pool is full");
+
+        buffer_id
+    }
+
+    pub fn buffer_at(&self, buffer_id: BufferID) -> &Buffer {
+        self.buffers.get(buffer_id).expect("Invalid buffer ID")
+    }
+
+    pub fn destroy_buffer(&mut self, buffer_id: BufferID) {
+        let Some(buffer) = self.buffers.destroy(buffer_id) else {
+            return;
+        };
+
+        if let Some(allocation) = buffer.allocation {
+            self.free_memory(allocation);
+        }
+        self.forget_submitted_states(buffer.handle.as_raw());
+        unsafe { self.handle.destroy_buffer(buffer.handle, None) };
+    }
+
+    // Handles get reused after destruction, stale states would show up as mismatches
+    fn forget_submitted_states(&self, resource: u64) {
+        self.submitted_states
+            .borrow_mut()
+            .retain(|&(tracked_resource, _), _| tracked_resource != resource);
+    }
+
     pub fn create_swapchain(&self, window: &window::Window) -> Result<SwapChain, vk::Result> {
         let surface = self
             .physical_device
@@ -393,13 +937,25 @@ impl Device {
         let images: Vec<Image> = native_images
             .iter()
             .map(|&image| {
-                let extent = vk::Extent3D::default();
+                let extent = vk::Extent3D {
+                    width: swapchain.extent.width,
+                    height: swapchain.extent.height,
+                    depth: 1,
+                };
+                // Contents are undefined on first use, but the first transition still
+                // has to be ordered after the acquire wait like every other frame.
+                let initial_state = ResourceState {
+                    layout: vk::ImageLayout::UNDEFINED,
+                    ..ResourceState::PRESENT
+                };
                 Image {
                     usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
                     format: swapchain.format,
                     extent,
                     slices: 1,
                     levels: 1,
+                    states: RefCell::new(vec![initial_state]),
+                    default_view: None,
                     allocation: None,
                     handle: image,
                 }
@@ -437,6 +993,7 @@ impl Device {
         swapchain: &SwapChain,
         acquire_sema: &Semaphore,
     ) -> Result<u32, vk::Result> {
+        let acquire_start = Instant::now();
         let (image_id, _suboptimal) = unsafe {
             self.swapchain_loader
                 .acquire_next_image(
@@ -447,6 +1004,7 @@ impl Device {
                 )
                 .expect("Failed to acquire swapchain image")
         };
+        self.trace_event("acquire", TraceTrack::Swapchain, acquire_start);
 
         // TODO: properly handle suboptimal case
         Ok(image_id)
@@ -466,36 +1024,420 @@ impl Device {
             .swapchains(&swapchains)
             .image_indices(&image_indices);
 
-        unsafe {
+        let present_start = Instant::now();
+        let result = unsafe {
             self.swapchain_loader
                 .queue_present(self.queue_at(CommandType::Graphics).into(), &present_info)
+        };
+        self.trace_event("present", TraceTrack::Swapchain, present_start);
+
+        result
+    }
+
+    // Every frame must submit `frame_signal_info` exactly once
+    pub fn new_frame(&mut self) -> usize {
+        let sema_counter = self.frame_sema.counter();
+        let wait_val = sema_counter.saturating_sub(self.frame_count as u64 - 1);
+        let wait_start = Instant::now();
+        loop {
+            match self.frame_sema.wait(wait_val, self.watchdog_timeout) {
+                Ok(()) => break,
+                Err(WaitError::Timeout) => self.report_stuck_queues(wait_val, wait_start),
+                Err(error) => {
+                    self.dump_submission_history();
+                    panic!("Failed to wait for frame {}: {}", wait_val, error);
+                }
+            }
         }
+
+        let frame_index = (sema_counter % self.frame_count as u64) as usize;
+        let resolved = self
+            .profiler
+            .begin_frame(&self.handle, frame_index, sema_counter);
+        self.update_trace(sema_counter, resolved);
+        self.queries
+            .begin_frame(&self.handle, frame_index, sema_counter);
+        if let Some(budget) = self.physical_device.memory_budget() {
+            self.memory.check_budget(&budget);
+        }
+        self.trace_event("wait for frame", TraceTrack::MainThread, wait_start);
+
+        #[cfg(feature = "hot-reload")]
+        self.reload_pipelines();
+
+        frame_index
     }
 
-    pub fn new_frame(&self) -> usize {
-        let sema_counter = self.frame_sema.counter as i64;
-        let wait_val = std::cmp::max(0, sema_counter - (self.frame_count - 1) as i64) as u64;
-        self.wait_for_semaphore(&self.frame_sema, wait_val);
+    // Records the next `frame_count` frames into a Chrome trace JSON file at `path`
+    pub fn start_trace(&mut self, frame_count: u32, path: impl Into<PathBuf>) {
+        *self.trace.get_mut() = Some(TraceRecorder::new(path.into(), frame_count));
+    }
 
-        (self.frame_sema.counter % self.frame_count as u64) as usize
+    pub fn cpu_scope(&self, name: &str) -> CpuScope<'_> {
+        CpuScope {
+            device: self,
+            name: name.to_owned(),
+            start: Instant::now(),
+        }
     }
 
-    pub fn end_frame(&mut self) {
-        self.frame_sema.advance();
+    pub fn trace_event(&self, name: &str, track: TraceTrack, start: Instant) {
+        if let Some(trace) = self.trace.borrow_mut().as_mut() {
+            trace.record(name, track, start);
+        }
+    }
+
+    fn update_trace(&mut self, frame: u64, resolved: bool) {
+        let Some(trace) = self.trace.get_mut() else {
+            return;
+        };
+
+        if let (true, Some(submit_time)) = (resolved, self.profiler.resolved_submit_time) {
+            trace.record_gpu_timings(
+                self.profiler.resolved_frame,
+                submit_time,
+                &self.profiler.timings,
+            );
+        }
+        trace.begin_frame(frame);
+
+        if trace.is_complete(frame, self.frame_count) {
+            match trace.write() {
+                Ok(()) => println!("Wrote trace to {}", trace.path.display()),
+                Err(error) => println!(
+                    "Failed to write trace to {}: {}",
+                    trace.path.display(),
+                    error
+                ),
+            }
+            *self.trace.get_mut() = None;
+        }
+    }
+
+    // Reserves the value that marks the end of the current frame
+    pub fn frame_signal_info(&mut self) -> vk::SemaphoreSubmitInfo<'static> {
+        let value = self.frame_sema.reserve();
+        self.frame_sema
+            .submit_info(value, vk::PipelineStageFlags2::ALL_COMMANDS)
     }
 
     pub fn submit(
         &self,
         command_queue: &CommandQueue,
-        submit_info: vk::SubmitInfo2,
+        command_lists: &[&CommandList],
+        wait_sema_infos: &[vk::SemaphoreSubmitInfo],
+        signal_sema_infos: &[vk::SemaphoreSubmitInfo],
     ) -> Result<(), vk::Result> {
+        if self.validate_states {
+            command_lists
+                .iter()
+                .for_each(|command_list| self.validate_command_list_states(command_list));
+        }
+
+        let command_list_infos: Vec<vk::CommandBufferSubmitInfo> = command_lists
+            .iter()
+            .map(|&command_list| {
+                vk::CommandBufferSubmitInfo::default().command_buffer(command_list.into())
+            })
+            .collect();
+        let submit_info = vk::SubmitInfo2::default()
+            .wait_semaphore_infos(wait_sema_infos)
+            .signal_semaphore_infos(signal_sema_infos)
+            .command_buffer_infos(&command_list_infos);
         let submits = [submit_info];
-        unsafe {
+        self.profiler
+            .current()
+            .borrow_mut()
+            .submit_time
+            .get_or_insert_with(Instant::now);
+        self.record_submission(
+            command_queue,
+            command_lists.len(),
+            wait_sema_infos,
+            signal_sema_infos,
+        );
+        let result = unsafe {
             self.handle
-                .queue_submit2(command_queue.into(), &submits, vk::Fence::null())?
+                .queue_submit2(command_queue.into(), &submits, vk::Fence::null())
         };
+        if result == Err(vk::Result::ERROR_DEVICE_LOST) {
+            self.dump_submission_history();
+        }
 
-        Ok(())
+        result
+    }
+
+    fn record_submission(
+        &self,
+        command_queue: &CommandQueue,
+        command_list_count: usize,
+        wait_sema_infos: &[vk::SemaphoreSubmitInfo],
+        signal_sema_infos: &[vk::SemaphoreSubmitInfo],
+    ) {
+        let queue = [
+            CommandType::Graphics,
+            CommandType::Transfer,
+            CommandType::Compute,
+        ]
+        .into_iter()
+        .find(|&command_type| std::ptr::eq(self.queue_at(command_type), command_queue));
+        let semaphore_values = |infos: &[vk::SemaphoreSubmitInfo]| {
+            infos
+                .iter()
+                .map(|info| (info.semaphore, info.value))
+                .collect()
+        };
+
+        let mut history = self.submission_history.borrow_mut();
+        if history.len() == SUBMISSION_HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(SubmissionRecord {
+            frame: self.frame_sema.counter(),
+            time: Instant::now(),
+            queue,
+            command_list_count,
+            waits: semaphore_values(wait_sema_infos),
+            signals: semaphore_values(signal_sema_infos),
+        });
+    }
+
+    fn semaphore_name(&self, semaphore: vk::Semaphore) -> String {
+        if semaphore == self.frame_sema.handle {
+            return String::from("frame");
+        }
+
+        [
+            CommandType::Graphics,
+            CommandType::Transfer,
+            CommandType::Compute,
+        ]
+        .into_iter()
+        .find(|&command_type| self.queue_at(command_type).semaphore.handle == semaphore)
+        .map(|command_type| format!("{:?} queue", command_type))
+        .unwrap_or_else(|| format!("{:#x}", semaphore.as_raw()))
+    }
+
+    fn report_stuck_queues(&self, frame_value: u64, wait_start: Instant) {
+        println!(
+            "GPU watchdog: frame {} has not completed after {:.1?}",
+            frame_value,
+            wait_start.elapsed()
+        );
+        for command_type in [
+            CommandType::Graphics,
+            CommandType::Transfer,
+            CommandType::Compute,
+        ] {
+            let semaphore = &self.queue_at(command_type).semaphore;
+            let gpu_value = semaphore.gpu_value().unwrap_or(0);
+            if gpu_value < semaphore.counter() {
+                println!(
+                    "  {:?} queue is stuck at timeline value {} (submitted up to {})",
+                    command_type,
+                    gpu_value,
+                    semaphore.counter()
+                );
+            }
+        }
+    }
+
+    pub fn dump_submission_history(&self) {
+        let history = self.submission_history.borrow();
+        let Some(first) = history.front() else {
+            println!("No submissions recorded");
+            return;
+        };
+
+        println!("Last {} submissions:", history.len());
+        let format_values = |values: &[(vk::Semaphore, u64)]| {
+            values
+                .iter()
+                .map(|&(semaphore, value)| format!("{}={}", self.semaphore_name(semaphore), value))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        for record in history.iter() {
+            println!(
+                "  [frame {}] +{:.3?} {} queue, {} command lists, waits [{}], signals [{}]",
+                record.frame,
+                record.time.duration_since(first.time),
+                record
+                    .queue
+                    .map_or(String::from("unknown"), |queue| format!("{:?}", queue)),
+                record.command_list_count,
+                format_values(&record.waits),
+                format_values(&record.signals)
+            );
+        }
+
+        for (name, semaphore) in [
+            ("frame", &self.frame_sema),
+            (
+                "Graphics queue",
+                &self.queue_at(CommandType::Graphics).semaphore,
+            ),
+            (
+                "Transfer queue",
+                &self.queue_at(CommandType::Transfer).semaphore,
+            ),
+            (
+                "Compute queue",
+                &self.queue_at(CommandType::Compute).semaphore,
+            ),
+        ] {
+            match semaphore.gpu_value() {
+                Ok(value) => println!(
+                    "  {} timeline: GPU {} / CPU {}",
+                    name,
+                    value,
+                    semaphore.counter()
+                ),
+                Err(error) => println!("  {} timeline: unavailable ({})", name, error),
+            }
+        }
+    }
+
+    // Submits to the queue of `command_type`, waiting for `wait_tokens` of other queues
+    // and signaling the queue's own timeline semaphore.
+    pub fn queue_submit(
+        &mut self,
+        command_type: CommandType,
+        command_lists: &[&CommandList],
+        wait_tokens: &[SubmitToken],
+        wait_sema_infos: &[vk::SemaphoreSubmitInfo],
+        signal_sema_infos: &[vk::SemaphoreSubmitInfo],
+    ) -> Result<SubmitToken, vk::Result> {
+        debug_assert!(command_lists
+            .iter()
+            .all(|command_list| command_list.command_type == command_type));
+
+        // The value is only reserved once the submit went through, a failed one
+        // would leave a value behind that never gets signaled
+        let token = SubmitToken {
+            command_type,
+            value: self.queues[command_type as usize].semaphore.counter() + 1,
+        };
+        let command_queue = self.queue_at(command_type);
+
+        // Work on the same queue is already ordered
+        let mut queue_wait_infos: Vec<vk::SemaphoreSubmitInfo> = wait_tokens
+            .iter()
+            .filter(|wait_token| wait_token.command_type != command_type)
+            .map(|&wait_token| self.token_wait_info(wait_token))
+            .collect();
+        queue_wait_infos.extend_from_slice(wait_sema_infos);
+
+        let mut queue_signal_infos = vec![command_queue
+            .semaphore
+            .submit_info(token.value, vk::PipelineStageFlags2::ALL_COMMANDS)];
+        queue_signal_infos.extend_from_slice(signal_sema_infos);
+
+        self.submit(
+            command_queue,
+            command_lists,
+            &queue_wait_infos,
+            &queue_signal_infos,
+        )?;
+        self.queues[command_type as usize].semaphore.reserve();
+
+        Ok(token)
+    }
+
+    // Records and submits a one-off command list, then blocks until the GPU is done
+    // with it. Meant for uploads outside of the frame loop.
+    pub fn immediate_submit(
+        &mut self,
+        command_type: CommandType,
+        record: impl FnOnce(&Device, &CommandList),
+    ) -> Result<(), vk::Result> {
+        let command_allocator =
+            self.create_command_allocator(command_type, vk::CommandPoolCreateFlags::TRANSIENT)?;
+        let command_list = self.create_command_list(&command_allocator)?;
+        self.begin_command_list(&command_list);
+        record(self, &command_list);
+        self.end_command_list(&command_list);
+
+        let result = self
+            .queue_submit(command_type, &[&command_list], &[], &[], &[])
+            .and_then(|token| {
+                self.wait_for_token(token, self.watchdog_timeout)
+                    .map_err(|error| match error {
+                        WaitError::Timeout => vk::Result::TIMEOUT,
+                        WaitError::DeviceLost => vk::Result::ERROR_DEVICE_LOST,
+                        WaitError::Vulkan(result) => result,
+                    })
+            });
+        // A list that timed out may still be running, leak its pool instead
+        if result != Err(vk::Result::TIMEOUT) {
+            unsafe {
+                self.handle
+                    .destroy_command_pool(command_allocator.handle, None)
+            };
+        }
+
+        result
+    }
+
+    pub fn submit_compute(
+        &mut self,
+        command_list: &CommandList,
+    ) -> Result<SubmitToken, vk::Result> {
+        self.queue_submit(CommandType::Compute, &[command_list], &[], &[], &[])
+    }
+
+    pub fn token_wait_info(&self, token: SubmitToken) -> vk::SemaphoreSubmitInfo<'static> {
+        self.queue_at(token.command_type)
+            .semaphore
+            .submit_info(token.value, vk::PipelineStageFlags2::ALL_COMMANDS)
+    }
+
+    pub fn is_token_complete(&self, token: SubmitToken) -> Result<bool, WaitError> {
+        let result = self
+            .queue_at(token.command_type)
+            .semaphore
+            .gpu_value()
+            .map_err(WaitError::from);
+        if result == Err(WaitError::DeviceLost) {
+            self.dump_submission_history();
+        }
+
+        Ok(result? >= token.value)
+    }
+
+    pub fn wait_for_token(&self, token: SubmitToken, timeout: Duration) -> Result<(), WaitError> {
+        self.wait_for_queue(token.command_type, token.value, timeout)
+    }
+
+    pub fn wait_for_queue(
+        &self,
+        command_type: CommandType,
+        value: u64,
+        timeout: Duration,
+    ) -> Result<(), WaitError> {
+        let result = self.queue_at(command_type).semaphore.wait(value, timeout);
+        if result == Err(WaitError::DeviceLost) {
+            self.dump_submission_history();
+        }
+
+        result
+    }
+
+    fn validate_command_list_states(&self, command_list: &CommandList) {
+        let mut submitted_states = self.submitted_states.borrow_mut();
+        for transition in command_list.transitions.borrow().iter() {
+            let key = (transition.resource, transition.subresource);
+            if let Some(tracked_state) = submitted_states.get(&key) {
+                if *tracked_state != transition.before {
+                    println!(
+                        "State mismatch for resource {:#x} (subresource {}): command list expected {:?}, tracked state is {:?}",
+                        transition.resource, transition.subresource, transition.before, tracked_state
+                    );
+                }
+            }
+
+            submitted_states.insert(key, transition.after);
+        }
     }
 
     pub fn create_command_allocator(
@@ -546,12 +1488,41 @@ impl Device {
 
         Ok(CommandList {
             command_type: command_allocator.command_type,
+            transitions: RefCell::new(Vec::new()),
+            descriptor_set: self.descriptor_set.0,
+            pipeline_layout: self.pipeline_layout.0,
+            max_push_constants_size: self
+                .physical_device
+                .properties
+                .limits
+                .max_push_constants_size,
+            descriptor_set_bound: Cell::new([false; 2]),
+            profiler_frame: RefCell::new(None),
+            timestamp_valid_bits: self.physical_device.timestamp_valid_bits
+                [command_allocator.command_type as usize],
+            open_scopes: RefCell::new(Vec::new()),
+            query_frame: RefCell::new(None),
+            occlusion_query_precise: self.physical_device.features.occlusion_query_precise
+                == vk::TRUE,
+            active_occlusion_query: Cell::new(None),
+            active_statistics_query: Cell::new(None),
             device: self.handle.clone(),
             handle: command_list,
         })
     }
 
     pub fn begin_command_list(&self, command_list: &CommandList) {
+        command_list.transitions.borrow_mut().clear();
+        command_list.descriptor_set_bound.set([false; 2]);
+        command_list
+            .profiler_frame
+            .replace(Some(self.profiler.current()));
+        command_list.open_scopes.borrow_mut().clear();
+        command_list
+            .query_frame
+            .replace(Some(self.queries.current()));
+        command_list.active_occlusion_query.set(None);
+        command_list.active_statistics_query.set(None);
         let begin_info = vk::CommandBufferBeginInfo::default()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
         unsafe {
@@ -564,4 +1535,435 @@ impl Device {
     pub fn end_command_list(&self, command_list: &CommandList) {
         unsafe { self.handle.end_command_buffer(command_list.into()).unwrap() };
     }
+
+    pub fn pipeline_at(&self, pipeline_id: PipelineID) -> &Pipeline {
+        self.pipelines
+            .get(pipeline_id)
+            .expect("Invalid pipeline ID")
+    }
+
+    // The GPU must be done with the pipeline, the shared layout stays alive
+    pub fn destroy_pipeline(&mut self, pipeline_id: PipelineID) {
+        let Some(pipeline) = self.pipelines.destroy(pipeline_id) else {
+            return;
+        };
+
+        unsafe { self.handle.destroy_pipeline(pipeline.handle, None) };
+    }
+
+    pub fn create_shader(&self, name: &str, source: ShaderSource) -> Result<Shader, ShaderError> {
+        match source {
+            ShaderSource::Bytes(bytes) => Shader::from_spirv(name, bytes),
+            ShaderSource::Path(path) => Shader::from_path(name, path),
+            #[cfg(feature = "shader-compiler")]
+            ShaderSource::Compile(desc) => super::compile_shader(name, desc),
+        }
+    }
+
+    // Checks reflected shader interface against the shared bindless pipeline layout
+    pub fn validate_shader(&self, shader: &Shader) -> Result<(), ShaderError> {
+        let max_push_constants_size = self
+            .physical_device
+            .properties
+            .limits
+            .max_push_constants_size;
+        if shader.push_constant_size > max_push_constants_size {
+            return Err(ShaderError::PushConstantTooLarge {
+                name: shader.name.clone(),
+                size: shader.push_constant_size,
+                max_size: max_push_constants_size,
+            });
+        }
+
+        for binding in &shader.bindings {
+            let mismatch = |reason: String| ShaderError::BindingMismatch {
+                name: shader.name.clone(),
+                set: binding.set,
+                binding: binding.binding,
+                reason,
+            };
+
+            if binding.set != 0 {
+                return Err(mismatch(String::from(
+                    "only the bindless set 0 is available",
+                )));
+            }
+
+            match self.descriptor_types.get(binding.binding as usize) {
+                None => {
+                    return Err(mismatch(format!(
+                        "bindless layout only has {} bindings",
+                        self.descriptor_types.len()
+                    )))
+                }
+                Some(&descriptor_type) if descriptor_type != binding.descriptor_type => {
+                    return Err(mismatch(format!(
+                        "shader declares {:?} but bindless layout has {:?}",
+                        binding.descriptor_type, descriptor_type
+                    )))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn create_graphics_pipeline(
+        &mut self,
+        desc: &GraphicsPipelineDesc,
+    ) -> Result<PipelineID, ShaderError> {
+        let pipeline_desc = PipelineDesc::Graphics(desc.clone());
+        let pipeline = self.build_pipeline(&pipeline_desc)?;
+
+        Ok(self.insert_pipeline(pipeline_desc, pipeline))
+    }
+
+    pub fn create_compute_pipeline(
+        &mut self,
+        desc: &ComputePipelineDesc,
+    ) -> Result<PipelineID, ShaderError> {
+        let pipeline_desc = PipelineDesc::Compute(desc.clone());
+        let pipeline = self.build_pipeline(&pipeline_desc)?;
+
+        Ok(self.insert_pipeline(pipeline_desc, pipeline))
+    }
+
+    fn insert_pipeline(&mut self, desc: PipelineDesc, pipeline: vk::Pipeline) -> PipelineID {
+        #[cfg(feature = "hot-reload")]
+        self.watch_pipeline_shaders(&desc);
+
+        let pipeline_layout = self.pipeline_layout.0;
+        let (_, pipeline_id) = self
+            .pipelines
+            .create(|| Pipeline {
+                bind_point: desc.bind_point(),
+                layout: pipeline_layout,
+                desc,
+                handle: pipeline,
+            })
+            .expect("Pipeline pool is full");
+
+        pipeline_id
+    }
+
+    fn build_pipeline(&self, desc: &PipelineDesc) -> Result<vk::Pipeline, ShaderError> {
+        match desc {
+            PipelineDesc::Graphics(desc) => self.build_graphics_pipeline(desc),
+            PipelineDesc::Compute(desc) => self.build_compute_pipeline(desc),
+        }
+    }
+
+    fn build_graphics_pipeline(
+        &self,
+        desc: &GraphicsPipelineDesc,
+    ) -> Result<vk::Pipeline, ShaderError> {
+        for shader in &desc.shaders {
+            self.validate_shader(shader)?;
+        }
+
+        let mut shader_modules = Vec::new();
+        for shader in &desc.shaders {
+            let create_info = vk::ShaderModuleCreateInfo::default().code(&shader.code);
+            shader_modules.push(unsafe { self.handle.create_shader_module(&create_info, None)? });
+        }
+
+        let entry_points: Vec<CString> = desc
+            .shaders
+            .iter()
+            .map(|shader| CString::new(shader.entry_point.as_str()).unwrap())
+            .collect();
+        let shader_stage_infos: Vec<vk::PipelineShaderStageCreateInfo> = desc
+            .shaders
+            .iter()
+            .zip(shader_modules.iter())
+            .zip(entry_points.iter())
+            .map(|((shader, &module), entry_point)| {
+                vk::PipelineShaderStageCreateInfo::default()
+                    .stage(shader.stage)
+                    .module(module)
+                    .name(entry_point)
+            })
+            .collect();
+
+        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&desc.vertex_bindings)
+            .vertex_attribute_descriptions(&desc.vertex_attributes);
+        let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(desc.topology)
+            .primitive_restart_enable(desc.primitive_restart);
+        // Viewport and scissor counts are required even though they are dynamic
+        let viewport_info = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterizer = &desc.rasterizer;
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(rasterizer.polygon_mode)
+            .cull_mode(rasterizer.cull_mode)
+            .front_face(rasterizer.front_face)
+            .depth_clamp_enable(rasterizer.depth_clamp)
+            .depth_bias_enable(rasterizer.depth_bias)
+            .depth_bias_constant_factor(rasterizer.depth_bias_constant_factor)
+            .depth_bias_clamp(rasterizer.depth_bias_clamp)
+            .depth_bias_slope_factor(rasterizer.depth_bias_slope_factor)
+            .line_width(rasterizer.line_width);
+        let multisample_info =
+            vk::PipelineMultisampleStateCreateInfo::default().rasterization_samples(desc.samples);
+        let depth_stencil = &desc.depth_stencil;
+        let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(depth_stencil.depth_test)
+            .depth_write_enable(depth_stencil.depth_write)
+            .depth_compare_op(depth_stencil.depth_compare_op)
+            .stencil_test_enable(depth_stencil.stencil_test)
+            .front(depth_stencil.stencil_front)
+            .back(depth_stencil.stencil_back);
+
+        let blend_attachments: Vec<vk::PipelineColorBlendAttachmentState> =
+            (0..desc.color_formats.len())
+                .map(|i| {
+                    desc.blend_attachments
+                        .get(i)
+                        .copied()
+                        .unwrap_or_else(super::opaque_blend_attachment)
+                })
+                .collect();
+        let color_blend_info = vk::PipelineColorBlendStateCreateInfo::default()
+            .attachments(&blend_attachments)
+            .blend_constants(desc.blend_constants);
+        let dynamic_state_info =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&desc.dynamic_states);
+        let mut rendering_info = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&desc.color_formats)
+            .depth_attachment_format(desc.depth_format)
+            .stencil_attachment_format(desc.stencil_format);
+
+        let create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&shader_stage_infos)
+            .vertex_input_state(&vertex_input_info)
+            .input_assembly_state(&input_assembly_info)
+            .viewport_state(&viewport_info)
+            .rasterization_state(&rasterization_info)
+            .multisample_state(&multisample_info)
+            .depth_stencil_state(&depth_stencil_info)
+            .color_blend_state(&color_blend_info)
+            .dynamic_state(&dynamic_state_info)
+            .layout(self.pipeline_layout.0)
+            .push_next(&mut rendering_info);
+        let pipeline = unsafe {
+            self.handle
+                .create_graphics_pipelines(self.pipeline_cache, &[create_info], None)
+        };
+
+        // Modules are only needed during creation
+        shader_modules
+            .iter()
+            .for_each(|&module| unsafe { self.handle.destroy_shader_module(module, None) });
+        let pipeline = pipeline.map_err(|(_, result)| result)?[0];
+
+        Ok(pipeline)
+    }
+
+    fn build_compute_pipeline(
+        &self,
+        desc: &ComputePipelineDesc,
+    ) -> Result<vk::Pipeline, ShaderError> {
+        if desc.shader.stage != vk::ShaderStageFlags::COMPUTE {
+            return Err(ShaderError::StageMismatch {
+                name: desc.shader.name.clone(),
+                expected: vk::ShaderStageFlags::COMPUTE,
+                found: desc.shader.stage,
+            });
+        }
+        self.validate_shader(&desc.shader)?;
+
+        let create_info = vk::ShaderModuleCreateInfo::default().code(&desc.shader.code);
+        let shader_module = unsafe { self.handle.create_shader_module(&create_info, None)? };
+
+        let entry_point = CString::new(desc.shader.entry_point.as_str()).unwrap();
+        let shader_stage_info = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(&entry_point);
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(shader_stage_info)
+            .layout(self.pipeline_layout.0);
+        let pipeline = unsafe {
+            self.handle
+                .create_compute_pipelines(self.pipeline_cache, &[create_info], None)
+        };
+
+        unsafe { self.handle.destroy_shader_module(shader_module, None) };
+        let pipeline = pipeline.map_err(|(_, result)| result)?[0];
+
+        Ok(pipeline)
+    }
+
+    #[cfg(feature = "hot-reload")]
+    pub fn enable_hot_reload(&mut self) -> Result<(), notify::Error> {
+        self.shader_watcher = Some(ShaderWatcher::new()?);
+
+        let descs: Vec<PipelineDesc> = self
+            .pipelines
+            .iter()
+            .map(|(_, pipeline)| pipeline.desc.clone())
+            .collect();
+        descs
+            .iter()
+            .for_each(|desc| self.watch_pipeline_shaders(desc));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn watch_pipeline_shaders(&mut self, desc: &PipelineDesc) {
+        let Some(shader_watcher) = self.shader_watcher.as_mut() else {
+            return;
+        };
+
+        for path in desc
+            .shaders()
+            .iter()
+            .flat_map(|shader| &shader.dependencies)
+        {
+            if let Err(error) = shader_watcher.watch(path) {
+                println!("Failed to watch shader file {}: {}", path.display(), error);
+            }
+        }
+    }
+
+    // Rebuilds pipelines whose shader sources changed, old pipeline objects are
+    // destroyed once the frames that could still use them have completed.
+    #[cfg(feature = "hot-reload")]
+    fn reload_pipelines(&mut self) {
+        let completed_value = self.frame_sema.gpu_value().unwrap_or(0);
+        let device = &self.handle;
+        self.retired_pipelines.retain(|&(retire_value, pipeline)| {
+            if retire_value > completed_value {
+                return true;
+            }
+
+            unsafe { device.destroy_pipeline(pipeline, None) };
+            false
+        });
+
+        let Some(shader_watcher) = self.shader_watcher.as_ref() else {
+            return;
+        };
+        let changed_files = shader_watcher.changed_files();
+        if changed_files.is_empty() {
+            return;
+        }
+
+        let is_changed = |shader: &Shader| {
+            shader
+                .dependencies
+                .iter()
+                .any(|path| changed_files.contains(path))
+        };
+        let pipeline_ids: Vec<PipelineID> = self
+            .pipelines
+            .iter()
+            .filter(|(_, pipeline)| pipeline.desc.shaders().iter().any(is_changed))
+            .map(|(pipeline_id, _)| pipeline_id)
+            .collect();
+
+        for pipeline_id in pipeline_ids {
+            let mut desc = self.pipeline_at(pipeline_id).desc.clone();
+            let result = desc
+                .shaders_mut()
+                .iter_mut()
+                .filter(|shader| is_changed(shader))
+                .try_for_each(|shader| {
+                    if let Some(source) = shader.source.as_ref() {
+                        *shader = super::compile_shader(&shader.name, source)?;
+                    }
+
+                    Ok(())
+                })
+                .and_then(|_| self.build_pipeline(&desc));
+
+            match result {
+                Ok(new_pipeline) => {
+                    self.watch_pipeline_shaders(&desc);
+
+                    let pipeline = self.pipelines.get_mut(pipeline_id).unwrap();
+                    let old_pipeline = std::mem::replace(&mut pipeline.handle, new_pipeline);
+                    pipeline.desc = desc;
+                    self.retired_pipelines
+                        .push((self.frame_sema.counter(), old_pipeline));
+                    println!("Reloaded pipeline {}", pipeline_id);
+                }
+                Err(error) => {
+                    println!(
+                        "Failed to reload pipeline {}, keeping the previous one: {}",
+                        pipeline_id, error
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe { self.handle.device_wait_idle().ok() };
+
+        if let Err(error) = self.save_pipeline_cache() {
+            println!(
+                "Failed to save pipeline cache to {}: {}",
+                self.pipeline_cache_path.display(),
+                error
+            );
+        }
+
+        unsafe {
+            self.handle
+                .destroy_pipeline_cache(self.pipeline_cache, None)
+        };
+
+        let pipeline_ids: Vec<PipelineID> = self
+            .pipelines
+            .iter()
+            .map(|(pipeline_id, _)| pipeline_id)
+            .collect();
+        pipeline_ids
+            .into_iter()
+            .for_each(|pipeline_id| self.destroy_pipeline(pipeline_id));
+        #[cfg(feature = "hot-reload")]
+        for (_, pipeline) in self.retired_pipelines.drain(..) {
+            unsafe { self.handle.destroy_pipeline(pipeline, None) };
+        }
+        unsafe {
+            self.handle
+                .destroy_pipeline_layout(self.pipeline_layout.0, None)
+        };
+
+        let sampler_ids: Vec<SamplerID> = self
+            .samplers
+            .iter()
+            .map(|(sampler_id, _)| sampler_id)
+            .collect();
+        sampler_ids
+            .into_iter()
+            .for_each(|sampler_id| self.destroy_sampler(sampler_id));
+
+        self.profiler.destroy(&self.handle);
+        self.queries.destroy(&self.handle);
+
+        // Descriptor sets are freed with their pool
+        unsafe {
+            self.handle
+                .destroy_descriptor_pool(self.descriptor_pool.0, None);
+            self.handle
+                .destroy_descriptor_set_layout(self.descriptor_set_layout.0, None);
+            for queue in &self.queues {
+                self.handle.destroy_semaphore(queue.semaphore.handle, None);
+            }
+            self.handle.destroy_semaphore(self.frame_sema.handle, None);
+        }
+
+        if let Some(leak_report) = self.memory.leak_report() {
+            print!("{}", leak_report);
+        }
+    }
 }
@@ -0,0 +1,516 @@
+use ash::vk;
+use std::path::Path;
+
+use super::{
+    AlphaMode, MaterialData, MeshData, MeshPrimitive, SamplerDesc, SceneData, SceneError,
+    SceneImage, SceneNode, SceneTexture, TextureData, Vertex,
+};
+
+fn sampler_desc(sampler: gltf::texture::Sampler) -> SamplerDesc {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let address_mode = |wrapping_mode| match wrapping_mode {
+        WrappingMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        WrappingMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+        WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
+    };
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => vk::Filter::NEAREST,
+        Some(MagFilter::Linear) | None => vk::Filter::LINEAR,
+    };
+    // Filters without a mipmap mode only sample the base level
+    let (min_filter, mipmap_mode, max_lod) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) => (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST, 0.25),
+        Some(MinFilter::Linear) => (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST, 0.25),
+        Some(MinFilter::NearestMipmapNearest) => (
+            vk::Filter::NEAREST,
+            vk::SamplerMipmapMode::NEAREST,
+            vk::LOD_CLAMP_NONE,
+        ),
+        Some(MinFilter::LinearMipmapNearest) => (
+            vk::Filter::LINEAR,
+            vk::SamplerMipmapMode::NEAREST,
+            vk::LOD_CLAMP_NONE,
+        ),
+        Some(MinFilter::NearestMipmapLinear) => (
+            vk::Filter::NEAREST,
+            vk::SamplerMipmapMode::LINEAR,
+            vk::LOD_CLAMP_NONE,
+        ),
+        Some(MinFilter::LinearMipmapLinear) | None => (
+            vk::Filter::LINEAR,
+            vk::SamplerMipmapMode::LINEAR,
+            vk::LOD_CLAMP_NONE,
+        ),
+    };
+
+    SamplerDesc {
+        mag_filter,
+        min_filter,
+        mipmap_mode,
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        max_lod,
+        ..Default::default()
+    }
+}
+
+// Decoded glTF images can be 8/16 bit or float with 1 to 4 channels, one and two
+// channel images are grayscale (with alpha).
+fn rgba8_pixels(image: &gltf::image::Data) -> Vec<u8> {
+    use gltf::image::Format;
+
+    let (channel_count, channel_size) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let channel = |texel: &[u8], index: usize| -> u8 {
+        let bytes = &texel[index * channel_size..(index + 1) * channel_size];
+        match channel_size {
+            1 => bytes[0],
+            // Little endian, the high byte is enough for 8 bits
+            2 => bytes[1],
+            _ => {
+                let value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                (value.clamp(0.0, 1.0) * 255.0).round() as u8
+            }
+        }
+    };
+
+    image
+        .pixels
+        .chunks_exact(channel_count * channel_size)
+        .flat_map(|texel| match channel_count {
+            1 => [channel(texel, 0); 3].into_iter().chain([255]),
+            2 => [channel(texel, 0); 3]
+                .into_iter()
+                .chain([channel(texel, 1)]),
+            3 => [channel(texel, 0), channel(texel, 1), channel(texel, 2)]
+                .into_iter()
+                .chain([255]),
+            _ => [channel(texel, 0), channel(texel, 1), channel(texel, 2)]
+                .into_iter()
+                .chain([channel(texel, 3)]),
+        })
+        .collect()
+}
+
+fn triangle_list(mode: gltf::mesh::Mode, indices: Vec<u32>) -> Option<Vec<u32>> {
+    use gltf::mesh::Mode;
+
+    match mode {
+        Mode::Triangles => Some(indices),
+        // Every other strip triangle is flipped to keep the winding
+        Mode::TriangleStrip => Some(
+            indices
+                .windows(3)
+                .enumerate()
+                .flat_map(|(i, triangle)| match i % 2 {
+                    0 => [triangle[0], triangle[1], triangle[2]],
+                    _ => [triangle[1], triangle[0], triangle[2]],
+                })
+                .collect(),
+        ),
+        Mode::TriangleFan => Some(
+            indices
+                .windows(2)
+                .skip(1)
+                .flat_map(|edge| [indices[0], edge[0], edge[1]])
+                .collect(),
+        ),
+        Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => None,
+    }
+}
+
+impl SceneData {
+    // Loads a .gltf or .glb file, buffers and images can be embedded as base64,
+    // stored in the GLB blob or referenced as files relative to `path`.
+    pub fn from_gltf(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let gltf_error = |error| SceneError::Gltf {
+            name: name.clone(),
+            error,
+        };
+        let malformed = |reason: String| SceneError::Malformed {
+            name: name.clone(),
+            reason,
+        };
+
+        let gltf::Gltf { document, blob } = gltf::Gltf::open(path).map_err(gltf_error)?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        let buffers = gltf::import_buffers(&document, Some(base), blob).map_err(gltf_error)?;
+
+        // Only color data is stored as sRGB
+        let mut srgb_images = vec![false; document.images().len()];
+        for material in document.materials() {
+            let color_textures = [
+                material.pbr_metallic_roughness().base_color_texture(),
+                material.emissive_texture(),
+            ];
+            for info in color_textures.into_iter().flatten() {
+                srgb_images[info.texture().source().index()] = true;
+            }
+        }
+
+        let mut images = Vec::with_capacity(srgb_images.len());
+        for image in document.images() {
+            let image_name = match image.name() {
+                Some(image_name) => format!("{name}/{image_name}"),
+                None => format!("{name}/image {}", image.index()),
+            };
+            let data = gltf::image::Data::from_source(image.source(), Some(base), &buffers)
+                .map_err(gltf_error)?;
+            images.push(SceneImage {
+                name: image_name,
+                data: TextureData::rgba8(
+                    data.width,
+                    data.height,
+                    rgba8_pixels(&data),
+                    srgb_images[image.index()],
+                ),
+            });
+        }
+
+        let textures = document
+            .textures()
+            .map(|texture| SceneTexture {
+                image: texture.source().index(),
+                sampler: sampler_desc(texture.sampler()),
+            })
+            .collect();
+
+        let materials = document
+            .materials()
+            .map(|material| {
+                let pbr = material.pbr_metallic_roughness();
+                MaterialData {
+                    name: material.name().unwrap_or_default().to_owned(),
+                    base_color_factor: pbr.base_color_factor(),
+                    metallic_factor: pbr.metallic_factor(),
+                    roughness_factor: pbr.roughness_factor(),
+                    emissive_factor: material.emissive_factor(),
+                    normal_scale: material.normal_texture().map_or(1.0, |info| info.scale()),
+                    occlusion_strength: material
+                        .occlusion_texture()
+                        .map_or(1.0, |info| info.strength()),
+                    alpha_mode: match material.alpha_mode() {
+                        gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                        gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                        gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                    },
+                    alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+                    double_sided: material.double_sided(),
+                    base_color_texture: pbr.base_color_texture().map(|info| info.texture().index()),
+                    metallic_roughness_texture: pbr
+                        .metallic_roughness_texture()
+                        .map(|info| info.texture().index()),
+                    normal_texture: material.normal_texture().map(|info| info.texture().index()),
+                    occlusion_texture: material
+                        .occlusion_texture()
+                        .map(|info| info.texture().index()),
+                    emissive_texture: material
+                        .emissive_texture()
+                        .map(|info| info.texture().index()),
+                }
+            })
+            .collect();
+
+        let mut meshes = Vec::with_capacity(document.meshes().len());
+        for mesh in document.meshes() {
+            let mesh_name = mesh.name().unwrap_or_default().to_owned();
+            let mut primitives = Vec::new();
+            for primitive in mesh.primitives() {
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let positions = reader.read_positions().ok_or_else(|| {
+                    malformed(format!(
+                        "mesh {} has a primitive without positions",
+                        mesh.index()
+                    ))
+                })?;
+                let mut vertices: Vec<Vertex> = positions
+                    .map(|position| Vertex {
+                        position,
+                        ..Default::default()
+                    })
+                    .collect();
+                // Missing normals and tangents are left zeroed
+                if let Some(normals) = reader.read_normals() {
+                    vertices
+                        .iter_mut()
+                        .zip(normals)
+                        .for_each(|(vertex, normal)| vertex.normal = normal);
+                }
+                if let Some(tex_coords) = reader.read_tex_coords(0) {
+                    vertices
+                        .iter_mut()
+                        .zip(tex_coords.into_f32())
+                        .for_each(|(vertex, tex_coord)| vertex.tex_coord = tex_coord);
+                }
+                if let Some(tangents) = reader.read_tangents() {
+                    vertices
+                        .iter_mut()
+                        .zip(tangents)
+                        .for_each(|(vertex, tangent)| vertex.tangent = tangent);
+                }
+
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..vertices.len() as u32).collect(),
+                };
+                if let Some(&index) = indices
+                    .iter()
+                    .find(|&&index| index as usize >= vertices.len())
+                {
+                    return Err(malformed(format!(
+                        "mesh {} has index {index} past its {} vertices",
+                        mesh.index(),
+                        vertices.len()
+                    )));
+                }
+                let Some(indices) = triangle_list(primitive.mode(), indices) else {
+                    println!(
+                        "{name}: skipping {:?} primitive of mesh {}",
+                        primitive.mode(),
+                        mesh.index()
+                    );
+                    continue;
+                };
+
+                primitives.push(MeshPrimitive {
+                    vertices,
+                    indices,
+                    material: primitive.material().index(),
+                    ..Default::default()
+                });
+            }
+            meshes.push(MeshData {
+                name: mesh_name,
+                primitives,
+            });
+        }
+
+        let nodes = document
+            .nodes()
+            .map(|node| SceneNode {
+                name: node.name().unwrap_or_default().to_owned(),
+                transform: node.transform().matrix(),
+                mesh: node.mesh().map(|mesh| mesh.index()),
+                children: node.children().map(|child| child.index()).collect(),
+            })
+            .collect::<Vec<_>>();
+
+        // Without scenes every node that isn't a child is a root
+        let root_nodes = match document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => {
+                let mut is_child = vec![false; nodes.len()];
+                nodes
+                    .iter()
+                    .flat_map(|node| &node.children)
+                    .for_each(|&child| is_child[child] = true);
+                (0..nodes.len()).filter(|&node| !is_child[node]).collect()
+            }
+        };
+
+        Ok(Self {
+            meshes,
+            materials,
+            images,
+            textures,
+            nodes,
+            root_nodes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::test_util::TempDir;
+    use gltf::mesh::Mode;
+
+    // Standard alphabet with padding, as `data:` URIs expect
+    fn base64(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut encoded = String::new();
+        for chunk in bytes.chunks(3) {
+            let value = chunk.iter().enumerate().fold(0u32, |value, (i, &byte)| {
+                value | u32::from(byte) << (16 - i * 8)
+            });
+            for i in 0..4 {
+                match i <= chunk.len() {
+                    true => encoded.push(ALPHABET[(value >> (18 - i * 6) & 63) as usize] as char),
+                    false => encoded.push('='),
+                }
+            }
+        }
+
+        encoded
+    }
+
+    // One triangle with u16 indices, followed by the indices
+    fn triangle_buffer(indices: [u16; 3]) -> Vec<u8> {
+        let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let mut bytes: Vec<u8> = bytemuck::cast_slice(&positions).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&indices));
+        bytes
+    }
+
+    fn triangle_gltf(uri: &str) -> String {
+        format!(
+            r#"{{
+    "asset": {{ "version": "2.0" }},
+    "buffers": [{{ "uri": "{uri}", "byteLength": 42 }}],
+    "bufferViews": [
+        {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+        {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+    ],
+    "accessors": [
+        {{
+            "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+            "min": [0, 0, 0], "max": [1, 1, 0]
+        }},
+        {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+    ],
+    "materials": [{{
+        "name": "cutout",
+        "pbrMetallicRoughness": {{
+            "baseColorFactor": [0.5, 0.25, 1, 1],
+            "metallicFactor": 0.125,
+            "roughnessFactor": 0.75
+        }},
+        "alphaMode": "MASK",
+        "alphaCutoff": 0.25,
+        "doubleSided": true
+    }}],
+    "meshes": [{{
+        "name": "triangle",
+        "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1, "material": 0 }}]
+    }}],
+    "nodes": [
+        {{ "name": "root", "translation": [1, 2, 3], "children": [1] }},
+        {{ "name": "child", "mesh": 0, "scale": [2, 2, 2] }}
+    ],
+    "scenes": [{{ "nodes": [0] }}],
+    "scene": 0
+}}"#
+        )
+    }
+
+    fn check_triangle_scene(scene: &SceneData) {
+        let primitive = &scene.meshes[0].primitives[0];
+        assert_eq!(scene.meshes[0].name, "triangle");
+        assert_eq!(
+            primitive
+                .vertices
+                .iter()
+                .map(|vertex| vertex.position)
+                .collect::<Vec<_>>(),
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]
+        );
+        assert_eq!(primitive.indices, [0, 1, 2]);
+        assert_eq!(primitive.material, Some(0));
+
+        assert_eq!(scene.root_nodes, [0]);
+        assert_eq!(scene.nodes[0].children, [1]);
+        assert_eq!(scene.nodes[0].transform[3], [1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(scene.nodes[1].mesh, Some(0));
+        assert_eq!(scene.nodes[1].transform[0], [2.0, 0.0, 0.0, 0.0]);
+
+        let material = &scene.materials[0];
+        assert_eq!(material.name, "cutout");
+        assert_eq!(material.base_color_factor, [0.5, 0.25, 1.0, 1.0]);
+        assert_eq!(
+            (material.metallic_factor, material.roughness_factor),
+            (0.125, 0.75)
+        );
+        assert_eq!(material.alpha_mode, AlphaMode::Mask);
+        assert_eq!(material.alpha_cutoff, 0.25);
+        assert!(material.double_sided);
+    }
+
+    #[test]
+    fn loads_embedded_and_external_buffers() {
+        let buffer = triangle_buffer([0, 1, 2]);
+        let uri = format!("data:application/octet-stream;base64,{}", base64(&buffer));
+        let dir = TempDir::new(
+            "loads_gltf_buffers",
+            &[
+                ("embedded.gltf", triangle_gltf(&uri).as_bytes()),
+                ("external.gltf", triangle_gltf("triangle.bin").as_bytes()),
+                ("triangle.bin", &buffer),
+            ],
+        );
+
+        check_triangle_scene(&SceneData::from_gltf(dir.join("embedded.gltf")).unwrap());
+        check_triangle_scene(&SceneData::from_gltf(dir.join("external.gltf")).unwrap());
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        let dir = TempDir::new(
+            "rejects_gltf_indices",
+            &[
+                ("triangle.gltf", triangle_gltf("triangle.bin").as_bytes()),
+                ("triangle.bin", &triangle_buffer([0, 1, 3])),
+            ],
+        );
+
+        match SceneData::from_gltf(dir.join("triangle.gltf")) {
+            Err(SceneError::Malformed { reason, .. }) => {
+                assert_eq!(reason, "mesh 0 has index 3 past its 3 vertices");
+            }
+            _ => panic!("out of range index was accepted"),
+        }
+    }
+
+    #[test]
+    fn converts_strips_and_fans() {
+        assert_eq!(
+            triangle_list(Mode::Triangles, vec![0, 1, 2, 2, 1, 3]),
+            Some(vec![0, 1, 2, 2, 1, 3])
+        );
+        // Odd triangles swap their first two vertices so all of them wind the same way
+        assert_eq!(
+            triangle_list(Mode::TriangleStrip, vec![0, 1, 2, 3, 4]),
+            Some(vec![0, 1, 2, 2, 1, 3, 2, 3, 4])
+        );
+        assert_eq!(
+            triangle_list(Mode::TriangleFan, vec![5, 6, 7, 8]),
+            Some(vec![5, 6, 7, 5, 7, 8])
+        );
+
+        // Too few indices for a single triangle
+        assert_eq!(
+            triangle_list(Mode::TriangleStrip, vec![0, 1]),
+            Some(Vec::new())
+        );
+        assert_eq!(
+            triangle_list(Mode::TriangleFan, vec![0, 1]),
+            Some(Vec::new())
+        );
+        assert_eq!(
+            triangle_list(Mode::TriangleFan, Vec::new()),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn skips_points_and_lines() {
+        for mode in [Mode::Points, Mode::Lines, Mode::LineLoop, Mode::LineStrip] {
+            assert_eq!(triangle_list(mode, vec![0, 1, 2]), None);
+        }
+    }
+}
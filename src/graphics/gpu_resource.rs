@@ -1,22 +1,395 @@
-use std::{marker::PhantomData, mem::MaybeUninit};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    mem::MaybeUninit,
+};
 
 use ash::vk;
-use gpu_allocator::vulkan;
+use gpu_allocator::{vulkan, MemoryLocation};
+
+/////////////////////////////////
+// RESOURCE STATES
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ResourceState {
+    pub layout: vk::ImageLayout,
+    pub stage_mask: vk::PipelineStageFlags2,
+    pub access_mask: vk::AccessFlags2,
+}
+
+impl ResourceState {
+    pub const UNDEFINED: Self = Self::new(
+        vk::ImageLayout::UNDEFINED,
+        vk::PipelineStageFlags2::TOP_OF_PIPE,
+        vk::AccessFlags2::NONE,
+    );
+    pub const PRESENT: Self = Self::new(
+        vk::ImageLayout::PRESENT_SRC_KHR,
+        vk::PipelineStageFlags2::ALL_COMMANDS,
+        vk::AccessFlags2::NONE,
+    );
+    pub const COLOR_ATTACHMENT: Self = Self::new(
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        vk::AccessFlags2::from_raw(
+            vk::AccessFlags2::COLOR_ATTACHMENT_READ.as_raw()
+                | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw(),
+        ),
+    );
+    pub const DEPTH_ATTACHMENT: Self = Self::new(
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        vk::PipelineStageFlags2::from_raw(
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS.as_raw()
+                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS.as_raw(),
+        ),
+        vk::AccessFlags2::from_raw(
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ.as_raw()
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw(),
+        ),
+    );
+    pub const SHADER_READ: Self = Self::new(
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::PipelineStageFlags2::from_raw(
+            vk::PipelineStageFlags2::VERTEX_SHADER.as_raw()
+                | vk::PipelineStageFlags2::FRAGMENT_SHADER.as_raw()
+                | vk::PipelineStageFlags2::COMPUTE_SHADER.as_raw(),
+        ),
+        vk::AccessFlags2::SHADER_READ,
+    );
+    pub const SHADER_WRITE: Self = Self::new(
+        vk::ImageLayout::GENERAL,
+        vk::PipelineStageFlags2::from_raw(
+            vk::PipelineStageFlags2::FRAGMENT_SHADER.as_raw()
+                | vk::PipelineStageFlags2::COMPUTE_SHADER.as_raw(),
+        ),
+        vk::AccessFlags2::from_raw(
+            vk::AccessFlags2::SHADER_READ.as_raw() | vk::AccessFlags2::SHADER_WRITE.as_raw(),
+        ),
+    );
+    pub const TRANSFER_SRC: Self = Self::new(
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::PipelineStageFlags2::ALL_TRANSFER,
+        vk::AccessFlags2::TRANSFER_READ,
+    );
+    pub const TRANSFER_DST: Self = Self::new(
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::PipelineStageFlags2::ALL_TRANSFER,
+        vk::AccessFlags2::TRANSFER_WRITE,
+    );
+    // Vertex/index fetches and shader reads through device addresses
+    pub const GEOMETRY_READ: Self = Self::new(
+        vk::ImageLayout::UNDEFINED,
+        vk::PipelineStageFlags2::from_raw(
+            vk::PipelineStageFlags2::INDEX_INPUT.as_raw()
+                | vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT.as_raw()
+                | vk::PipelineStageFlags2::VERTEX_SHADER.as_raw()
+                | vk::PipelineStageFlags2::FRAGMENT_SHADER.as_raw()
+                | vk::PipelineStageFlags2::COMPUTE_SHADER.as_raw(),
+        ),
+        vk::AccessFlags2::from_raw(
+            vk::AccessFlags2::INDEX_READ.as_raw()
+                | vk::AccessFlags2::VERTEX_ATTRIBUTE_READ.as_raw()
+                | vk::AccessFlags2::SHADER_READ.as_raw(),
+        ),
+    );
+
+    pub const fn new(
+        layout: vk::ImageLayout,
+        stage_mask: vk::PipelineStageFlags2,
+        access_mask: vk::AccessFlags2,
+    ) -> Self {
+        Self {
+            layout,
+            stage_mask,
+            access_mask,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        let write_mask = vk::AccessFlags2::SHADER_WRITE
+            | vk::AccessFlags2::SHADER_STORAGE_WRITE
+            | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE
+            | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE
+            | vk::AccessFlags2::TRANSFER_WRITE
+            | vk::AccessFlags2::HOST_WRITE
+            | vk::AccessFlags2::MEMORY_WRITE;
+
+        !self.access_mask.intersects(write_mask)
+    }
+}
+
+impl Default for ResourceState {
+    fn default() -> Self {
+        Self::UNDEFINED
+    }
+}
 
 /////////////////////////////////
 // IMAGES
+pub fn format_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+// Views can only sample one aspect, depth wins for depth/stencil formats
+pub fn view_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format_aspect_mask(format) {
+        aspect_mask if aspect_mask.contains(vk::ImageAspectFlags::DEPTH) => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        aspect_mask => aspect_mask,
+    }
+}
+
+pub fn mip_level_count(extent: vk::Extent3D) -> u32 {
+    let max_dimension = extent.width.max(extent.height).max(extent.depth).max(1);
+    u32::BITS - max_dimension.leading_zeros()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageDimension {
+    D2,
+    D3,
+    Cube,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageDesc {
+    // Also names the allocation in memory reports
+    pub name: Option<String>,
+    pub dimension: ImageDimension,
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    // Cube images need 6 layers per cube
+    pub array_layers: u32,
+    pub mip_levels: u32,
+    pub samples: vk::SampleCountFlags,
+    pub usage: vk::ImageUsageFlags,
+    pub tiling: vk::ImageTiling,
+    pub location: MemoryLocation,
+    // Creates `Image::default_view` covering every level and layer
+    pub create_view: bool,
+}
+
+impl Default for ImageDesc {
+    fn default() -> Self {
+        Self {
+            name: None,
+            dimension: ImageDimension::D2,
+            format: vk::Format::UNDEFINED,
+            extent: vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+            array_layers: 1,
+            mip_levels: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            usage: vk::ImageUsageFlags::SAMPLED,
+            tiling: vk::ImageTiling::OPTIMAL,
+            location: MemoryLocation::GpuOnly,
+            create_view: true,
+        }
+    }
+}
+
+impl ImageDesc {
+    pub fn new_2d(format: vk::Format, width: u32, height: u32) -> Self {
+        Self {
+            format,
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn new_3d(format: vk::Format, extent: vk::Extent3D) -> Self {
+        Self {
+            dimension: ImageDimension::D3,
+            format,
+            extent,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_cube(format: vk::Format, size: u32) -> Self {
+        Self {
+            dimension: ImageDimension::Cube,
+            array_layers: 6,
+            ..Self::new_2d(format, size, size)
+        }
+    }
+
+    pub fn full_mip_chain(self) -> Self {
+        Self {
+            mip_levels: mip_level_count(self.extent),
+            ..self
+        }
+    }
+
+    pub fn create_info(&self) -> vk::ImageCreateInfo<'static> {
+        let (image_type, flags) = match self.dimension {
+            ImageDimension::D2 => (vk::ImageType::TYPE_2D, vk::ImageCreateFlags::empty()),
+            ImageDimension::D3 => (vk::ImageType::TYPE_3D, vk::ImageCreateFlags::empty()),
+            ImageDimension::Cube => (
+                vk::ImageType::TYPE_2D,
+                vk::ImageCreateFlags::CUBE_COMPATIBLE,
+            ),
+        };
+
+        vk::ImageCreateInfo::default()
+            .flags(flags)
+            .image_type(image_type)
+            .format(self.format)
+            .extent(self.extent)
+            .mip_levels(self.mip_levels)
+            .array_layers(self.array_layers)
+            .samples(self.samples)
+            .tiling(self.tiling)
+            .usage(self.usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+    }
+
+    pub fn view_type(&self) -> vk::ImageViewType {
+        match (self.dimension, self.array_layers) {
+            (ImageDimension::D2, 1) => vk::ImageViewType::TYPE_2D,
+            (ImageDimension::D2, _) => vk::ImageViewType::TYPE_2D_ARRAY,
+            (ImageDimension::D3, _) => vk::ImageViewType::TYPE_3D,
+            (ImageDimension::Cube, 6) => vk::ImageViewType::CUBE,
+            (ImageDimension::Cube, _) => vk::ImageViewType::CUBE_ARRAY,
+        }
+    }
+
+    // Format features each usage flag depends on
+    pub fn required_format_features(&self) -> vk::FormatFeatureFlags {
+        [
+            (
+                vk::ImageUsageFlags::SAMPLED,
+                vk::FormatFeatureFlags::SAMPLED_IMAGE,
+            ),
+            (
+                vk::ImageUsageFlags::STORAGE,
+                vk::FormatFeatureFlags::STORAGE_IMAGE,
+            ),
+            (
+                vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+            ),
+            (
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+            ),
+            (
+                vk::ImageUsageFlags::TRANSFER_SRC,
+                vk::FormatFeatureFlags::TRANSFER_SRC,
+            ),
+            (
+                vk::ImageUsageFlags::TRANSFER_DST,
+                vk::FormatFeatureFlags::TRANSFER_DST,
+            ),
+        ]
+        .into_iter()
+        .filter(|&(usage, _)| self.usage.contains(usage))
+        .fold(vk::FormatFeatureFlags::empty(), |features, (_, feature)| {
+            features | feature
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ImageError {
+    UnsupportedFormat {
+        name: String,
+        format: vk::Format,
+        tiling: vk::ImageTiling,
+        missing_features: vk::FormatFeatureFlags,
+    },
+    InvalidDesc {
+        name: String,
+        reason: String,
+    },
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::UnsupportedFormat {
+                name,
+                format,
+                tiling,
+                missing_features,
+            } => write!(
+                f,
+                "{name}: {format:?} with {tiling:?} tiling doesn't support {missing_features:?}"
+            ),
+            ImageError::InvalidDesc { name, reason } => write!(f, "{name}: {reason}"),
+            ImageError::Vulkan(result) => write!(f, "Vulkan error: {result}"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<vk::Result> for ImageError {
+    fn from(value: vk::Result) -> Self {
+        ImageError::Vulkan(value)
+    }
+}
+
+pub type ImageID = u32;
 pub struct Image {
     pub usage: vk::ImageUsageFlags,
     pub format: vk::Format,
     pub extent: vk::Extent3D,
     pub slices: u32,
     pub levels: u32,
+    // One entry per subresource, indexed by `subresource_index`
+    pub states: RefCell<Vec<ResourceState>>,
+    // Owned by the image, destroyed along with it
+    pub default_view: Option<ImageView>,
 
     pub allocation: Option<vulkan::Allocation>,
     pub handle: vk::Image,
 }
 define_from!(Image, vk::Image);
 
+impl Image {
+    pub fn subresource_index(&self, level: u32, layer: u32) -> usize {
+        (layer * self.levels + level) as usize
+    }
+
+    pub fn state(&self, level: u32, layer: u32) -> ResourceState {
+        self.states.borrow()[self.subresource_index(level, layer)]
+    }
+
+    pub fn full_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: format_aspect_mask(self.format),
+            base_mip_level: 0,
+            level_count: self.levels,
+            base_array_layer: 0,
+            layer_count: self.slices,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct ImageView {
     pub format: vk::Format,
     pub subresource_range: vk::ImageSubresourceRange,
@@ -25,19 +398,121 @@ pub struct ImageView {
 }
 define_from!(ImageView, vk::ImageView);
 
+/////////////////////////////////
+// SAMPLERS
+// Identical descriptions share one sampler, see `Device::create_sampler`
+#[derive(Clone, Copy, Debug)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+    pub address_mode_w: vk::SamplerAddressMode,
+    // Clamped to the device limit, `None` disables anisotropic filtering
+    pub max_anisotropy: Option<f32>,
+    pub compare_op: Option<vk::CompareOp>,
+    pub border_color: vk::BorderColor,
+    pub mip_lod_bias: f32,
+    pub min_lod: f32,
+    pub max_lod: f32,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: vk::SamplerAddressMode::REPEAT,
+            address_mode_v: vk::SamplerAddressMode::REPEAT,
+            address_mode_w: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: None,
+            compare_op: None,
+            border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+            mip_lod_bias: 0.0,
+            min_lod: 0.0,
+            max_lod: vk::LOD_CLAMP_NONE,
+        }
+    }
+}
+
+impl SamplerDesc {
+    pub fn with_address_mode(self, address_mode: vk::SamplerAddressMode) -> Self {
+        Self {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            ..self
+        }
+    }
+
+    pub fn create_info(&self) -> vk::SamplerCreateInfo<'static> {
+        vk::SamplerCreateInfo::default()
+            .mag_filter(self.mag_filter)
+            .min_filter(self.min_filter)
+            .mipmap_mode(self.mipmap_mode)
+            .address_mode_u(self.address_mode_u)
+            .address_mode_v(self.address_mode_v)
+            .address_mode_w(self.address_mode_w)
+            .anisotropy_enable(self.max_anisotropy.is_some())
+            .max_anisotropy(self.max_anisotropy.unwrap_or(1.0))
+            .compare_enable(self.compare_op.is_some())
+            .compare_op(self.compare_op.unwrap_or(vk::CompareOp::NEVER))
+            .border_color(self.border_color)
+            .mip_lod_bias(self.mip_lod_bias)
+            .min_lod(self.min_lod)
+            .max_lod(self.max_lod)
+    }
+
+    // Floats are compared bitwise so the description can be hashed
+    fn key(&self) -> impl PartialEq + Hash {
+        (
+            (self.mag_filter, self.min_filter, self.mipmap_mode),
+            (
+                self.address_mode_u,
+                self.address_mode_v,
+                self.address_mode_w,
+            ),
+            self.max_anisotropy.map(f32::to_bits),
+            self.compare_op,
+            self.border_color,
+            [self.mip_lod_bias, self.min_lod, self.max_lod].map(f32::to_bits),
+        )
+    }
+}
+
+impl PartialEq for SamplerDesc {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for SamplerDesc {}
+
+impl Hash for SamplerDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+pub type SamplerID = u32;
 pub struct Sampler {
+    pub desc: SamplerDesc,
+
     pub handle: vk::Sampler,
 }
 define_from!(Sampler, vk::Sampler);
 
 /////////////////////////////////
 // BUFFERS
-type BufferID = u32;
+pub type BufferID = u32;
 pub struct Buffer {
     pub data_size: u64,
     pub device_address: u64,
+    pub state: Cell<ResourceState>,
 
-    pub allocation: vulkan::Allocation,
+    pub allocation: Option<vulkan::Allocation>,
     pub handle: vk::Buffer,
 }
 define_from!(Buffer, vk::Buffer);
@@ -53,6 +528,9 @@ define_from_tupl!(DescriptorSetLayout, vk::DescriptorSetLayout, 0);
 #[derive(Default)]
 pub struct DescriptorSet(pub vk::DescriptorSet);
 define_from_tupl!(DescriptorSet, vk::DescriptorSet, 0);
+#[derive(Default)]
+pub struct PipelineLayout(pub vk::PipelineLayout);
+define_from_tupl!(PipelineLayout, vk::PipelineLayout, 0);
 
 /////////////////////////////////
 // RESOURCE POOL
@@ -71,6 +549,7 @@ where
     pub pages: [Option<Box<Page<ResourceT>>>; PAGE_COUNT as usize],
     pub free_indices: Vec<u32>,
     pub latest_index: u32,
+    pub alive: Vec<bool>,
     _rust: PhantomData<ResourceID>, // ???
 }
 
@@ -78,16 +557,67 @@ impl<ResourceT, ResourceID> ResourcePool<ResourceT, ResourceID>
 where
     ResourceID: Into<u32>,
 {
-    fn new() -> Self {
+    pub fn new() -> Self {
         Self {
             latest_index: 0,
             free_indices: Vec::new(),
             pages: [const { None }; PAGE_COUNT as usize],
+            alive: Vec::new(),
             _rust: PhantomData,
         }
     }
 
-    fn create(&mut self, args: impl FnOnce() -> ResourceT) -> Option<(&ResourceT, ResourceID)>
+    fn slot(&self, index: u32) -> Option<&MaybeUninit<ResourceT>> {
+        if !self.alive.get(index as usize).copied().unwrap_or(false) {
+            return None;
+        }
+
+        let page = self.pages[(index >> PAGE_BITS) as usize].as_ref()?;
+        Some(&page[(index & PAGE_MASK) as usize])
+    }
+
+    pub fn get(&self, id: ResourceID) -> Option<&ResourceT> {
+        let slot = self.slot(id.into())?;
+        Some(unsafe { slot.assume_init_ref() })
+    }
+
+    pub fn get_mut(&mut self, id: ResourceID) -> Option<&mut ResourceT> {
+        let index: u32 = id.into();
+        self.slot(index)?;
+
+        let page = self.pages[(index >> PAGE_BITS) as usize].as_mut()?;
+        Some(unsafe { page[(index & PAGE_MASK) as usize].assume_init_mut() })
+    }
+
+    pub fn destroy(&mut self, id: ResourceID) -> Option<ResourceT> {
+        let index: u32 = id.into();
+        let resource = unsafe { self.slot(index)?.assume_init_read() };
+        self.alive[index as usize] = false;
+        self.free_indices.push(index);
+
+        Some(resource)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &ResourceT)> {
+        self.alive
+            .iter()
+            .enumerate()
+            .filter(|(_, &alive)| alive)
+            .filter_map(|(index, _)| {
+                let slot = self.slot(index as u32)?;
+                Some((index as u32, unsafe { slot.assume_init_ref() }))
+            })
+    }
+
+    // ID the next `create` hands out
+    pub fn next_index(&self) -> u32 {
+        match self.free_indices.last() {
+            Some(&index) => index,
+            None => self.latest_index + 1,
+        }
+    }
+
+    pub fn create(&mut self, args: impl FnOnce() -> ResourceT) -> Option<(&ResourceT, ResourceID)>
     where
         ResourceID: From<u32>,
     {
@@ -121,6 +651,154 @@ where
             &mut *page[page_offset as usize].as_mut_ptr()
         };
 
+        if self.alive.len() <= index as usize {
+            self.alive.resize(index as usize + 1, false);
+        }
+        self.alive[index as usize] = true;
+
         Some((resource, ResourceID::from(index)))
     }
 }
+
+impl<ResourceT, ResourceID> Default for ResourcePool<ResourceT, ResourceID>
+where
+    ResourceID: Into<u32>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<ResourceT, ResourceID> Drop for ResourcePool<ResourceT, ResourceID>
+where
+    ResourceID: Into<u32>,
+{
+    fn drop(&mut self) {
+        for (index, alive) in self.alive.iter().enumerate() {
+            if !alive {
+                continue;
+            }
+
+            if let Some(page) = self.pages[index >> PAGE_BITS].as_mut() {
+                unsafe { page[index & PAGE_MASK as usize].assume_init_drop() };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{hash_map::DefaultHasher, HashMap};
+
+    fn hash(desc: &SamplerDesc) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        desc.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn predicts_next_pool_index() {
+        let mut pool: ResourcePool<u8, u32> = ResourcePool::new();
+        assert_eq!(pool.next_index(), 1);
+        let (_, first) = pool.create(|| 1).unwrap();
+        let (_, second) = pool.create(|| 2).unwrap();
+        assert_eq!((first, second), (1, 2));
+        assert_eq!(pool.next_index(), 3);
+
+        // Freed indices are reused first
+        pool.destroy(first);
+        assert_eq!(pool.next_index(), first);
+        assert_eq!(pool.create(|| 3).map(|(_, id)| id), Some(first));
+        assert_eq!(pool.next_index(), 3);
+    }
+
+    #[test]
+    fn identical_sampler_descs_are_equal() {
+        let desc = SamplerDesc {
+            max_anisotropy: Some(16.0),
+            ..SamplerDesc::default().with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        };
+        let other = SamplerDesc {
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            max_anisotropy: Some(16.0),
+            ..Default::default()
+        };
+
+        assert_eq!(desc, other);
+        assert_eq!(hash(&desc), hash(&other));
+
+        // Bitwise comparison keeps `Eq` reflexive for NaN
+        let nan = SamplerDesc {
+            mip_lod_bias: f32::NAN,
+            ..Default::default()
+        };
+        assert_eq!(nan, nan);
+        assert_eq!(hash(&nan), hash(&nan));
+    }
+
+    #[test]
+    fn differing_sampler_descs_are_not_equal() {
+        let desc = SamplerDesc::default();
+        let others = [
+            SamplerDesc {
+                mag_filter: vk::Filter::NEAREST,
+                ..desc
+            },
+            SamplerDesc {
+                mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                ..desc
+            },
+            SamplerDesc {
+                address_mode_w: vk::SamplerAddressMode::MIRRORED_REPEAT,
+                ..desc
+            },
+            SamplerDesc {
+                max_anisotropy: Some(1.0),
+                ..desc
+            },
+            SamplerDesc {
+                compare_op: Some(vk::CompareOp::NEVER),
+                ..desc
+            },
+            SamplerDesc {
+                border_color: vk::BorderColor::FLOAT_OPAQUE_BLACK,
+                ..desc
+            },
+            SamplerDesc {
+                max_lod: 0.25,
+                ..desc
+            },
+        ];
+
+        for (index, other) in others.iter().enumerate() {
+            assert_ne!(desc, *other, "description {index}");
+            assert!(others[index + 1..].iter().all(|later| later != other));
+        }
+    }
+
+    #[test]
+    fn sampler_descs_deduplicate_in_maps() {
+        let mut samplers: HashMap<SamplerDesc, SamplerID> = HashMap::new();
+        let descs = [
+            SamplerDesc::default(),
+            SamplerDesc::default().with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+            SamplerDesc::default(),
+            SamplerDesc {
+                min_lod: 0.0,
+                ..SamplerDesc::default().with_address_mode(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            },
+        ];
+        let ids: Vec<SamplerID> = descs
+            .iter()
+            .map(|desc| {
+                let next_id = samplers.len() as SamplerID + 1;
+                *samplers.entry(*desc).or_insert(next_id)
+            })
+            .collect();
+
+        assert_eq!(ids, [1, 2, 1, 2]);
+    }
+}
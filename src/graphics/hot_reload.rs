@@ -0,0 +1,52 @@
+use notify::Watcher;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+pub struct ShaderWatcher {
+    pub watched_dirs: HashSet<PathBuf>,
+
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    watcher: notify::RecommendedWatcher,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender)?;
+
+        Ok(Self {
+            watched_dirs: HashSet::new(),
+            events,
+            watcher,
+        })
+    }
+
+    // Directories are watched instead of files, editors often save by
+    // replacing the file which would drop a watch on the file itself.
+    pub fn watch(&mut self, file: &Path) -> notify::Result<()> {
+        let Some(dir) = file.parent() else {
+            return Ok(());
+        };
+
+        if self.watched_dirs.insert(dir.to_owned()) {
+            self.watcher
+                .watch(dir, notify::RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(())
+    }
+
+    // Drains pending events, returns canonical paths of modified files
+    pub fn changed_files(&self) -> HashSet<PathBuf> {
+        self.events
+            .try_iter()
+            .filter_map(|event| event.ok())
+            .filter(|event| event.kind.is_modify() || event.kind.is_create())
+            .flat_map(|event| event.paths)
+            .filter_map(|path| path.canonicalize().ok())
+            .collect()
+    }
+}
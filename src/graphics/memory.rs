@@ -0,0 +1,350 @@
+use ash::vk::{self, Handle};
+use gpu_allocator::vulkan;
+use std::{collections::HashMap, fmt};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AllocationKind {
+    Image,
+    Buffer,
+    // Shared by aliased render graph transients
+    RenderGraphHeap,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackedAllocation {
+    pub name: String,
+    pub kind: AllocationKind,
+    pub size: u64,
+    pub heap_index: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct HeapReport {
+    pub heap_index: u32,
+    pub flags: vk::MemoryHeapFlags,
+    pub size: u64,
+    // Bytes of live allocations made through the device
+    pub allocated_bytes: u64,
+    pub allocation_count: usize,
+    // Only available with VK_EXT_memory_budget, `usage` is the whole process'
+    pub budget: Option<u64>,
+    pub usage: Option<u64>,
+}
+
+impl HeapReport {
+    pub fn budget_ratio(&self) -> Option<f64> {
+        match (self.usage, self.budget) {
+            (Some(usage), Some(budget)) if budget != 0 => Some(usage as f64 / budget as f64),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AllocationGroup {
+    pub count: usize,
+    pub bytes: u64,
+}
+
+// A `vk::DeviceMemory` block owned by gpu-allocator
+#[derive(Clone, Debug)]
+pub struct BlockReport {
+    pub size: u64,
+    pub allocated_bytes: u64,
+    pub allocation_count: usize,
+    pub largest_free_range: u64,
+}
+
+impl BlockReport {
+    // 0 when all free memory is contiguous, approaches 1 as it gets scattered
+    pub fn fragmentation(&self) -> f64 {
+        let free_bytes = self.size - self.allocated_bytes;
+        match free_bytes {
+            0 => 0.0,
+            free_bytes => 1.0 - self.largest_free_range as f64 / free_bytes as f64,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryReport {
+    pub heaps: Vec<HeapReport>,
+    pub by_kind: Vec<(AllocationKind, AllocationGroup)>,
+    // Sorted by size, largest first
+    pub by_name: Vec<(String, AllocationGroup)>,
+    pub blocks: Vec<BlockReport>,
+    pub total_allocated_bytes: u64,
+    pub total_reserved_bytes: u64,
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: f64 = 1024.0 * 1024.0;
+
+        writeln!(
+            f,
+            "GPU memory: {:.2} MiB allocated, {:.2} MiB reserved in {} blocks",
+            self.total_allocated_bytes as f64 / MIB,
+            self.total_reserved_bytes as f64 / MIB,
+            self.blocks.len()
+        )?;
+        for heap in &self.heaps {
+            write!(
+                f,
+                "  heap {} ({:?}): {:.2} MiB in {} allocations, size {:.2} MiB",
+                heap.heap_index,
+                heap.flags,
+                heap.allocated_bytes as f64 / MIB,
+                heap.allocation_count,
+                heap.size as f64 / MIB
+            )?;
+            if let (Some(usage), Some(budget)) = (heap.usage, heap.budget) {
+                write!(
+                    f,
+                    ", usage {:.2}/{:.2} MiB",
+                    usage as f64 / MIB,
+                    budget as f64 / MIB
+                )?;
+            }
+            writeln!(f)?;
+        }
+        for (kind, group) in &self.by_kind {
+            writeln!(
+                f,
+                "  {:?}: {:.2} MiB in {} allocations",
+                kind,
+                group.bytes as f64 / MIB,
+                group.count
+            )?;
+        }
+        for (name, group) in &self.by_name {
+            writeln!(
+                f,
+                "  \"{}\": {:.2} MiB in {} allocations",
+                name,
+                group.bytes as f64 / MIB,
+                group.count
+            )?;
+        }
+        for (index, block) in self.blocks.iter().enumerate() {
+            writeln!(
+                f,
+                "  block {}: {:.2}/{:.2} MiB, {} allocations, {:.0}% fragmented",
+                index,
+                block.allocated_bytes as f64 / MIB,
+                block.size as f64 / MIB,
+                block.allocation_count,
+                block.fragmentation() * 100.0
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub type BudgetWarningFn = Box<dyn FnMut(&HeapReport)>;
+
+pub struct MemoryTracker {
+    // Keyed by (`vk::DeviceMemory`, offset) of the allocation
+    pub allocations: HashMap<(u64, u64), TrackedAllocation>,
+    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    // `budget_warning` runs once a heap's usage crosses this fraction of its budget
+    pub budget_warning_threshold: f64,
+    pub budget_warning: Option<BudgetWarningFn>,
+    // Heaps that already warned, cleared once they drop below the threshold
+    pub warned_heaps: u32,
+}
+
+impl MemoryTracker {
+    pub fn new(memory_properties: vk::PhysicalDeviceMemoryProperties) -> Self {
+        Self {
+            allocations: HashMap::new(),
+            memory_properties,
+            budget_warning_threshold: 0.9,
+            budget_warning: Some(Box::new(|heap| {
+                println!(
+                    "GPU memory heap {} is at {:.0}% of its budget",
+                    heap.heap_index,
+                    heap.budget_ratio().unwrap_or_default() * 100.0
+                )
+            })),
+            warned_heaps: 0,
+        }
+    }
+
+    // gpu-allocator picks the first memory type with matching properties
+    fn heap_index(&self, memory_type_bits: u32, properties: vk::MemoryPropertyFlags) -> u32 {
+        self.memory_properties
+            .memory_types_as_slice()
+            .iter()
+            .enumerate()
+            .find(|&(index, memory_type)| {
+                memory_type_bits & (1 << index) != 0 && memory_type.property_flags == properties
+            })
+            .map(|(_, memory_type)| memory_type.heap_index)
+            .unwrap_or_default()
+    }
+
+    pub fn track(
+        &mut self,
+        allocation: &vulkan::Allocation,
+        name: &str,
+        kind: AllocationKind,
+        memory_type_bits: u32,
+    ) {
+        let heap_index = self.heap_index(memory_type_bits, allocation.memory_properties());
+        self.allocations.insert(
+            Self::key(allocation),
+            TrackedAllocation {
+                name: name.to_owned(),
+                kind,
+                size: allocation.size(),
+                heap_index,
+            },
+        );
+    }
+
+    pub fn untrack(&mut self, allocation: &vulkan::Allocation) {
+        self.allocations.remove(&Self::key(allocation));
+    }
+
+    fn key(allocation: &vulkan::Allocation) -> (u64, u64) {
+        (unsafe { allocation.memory() }.as_raw(), allocation.offset())
+    }
+
+    pub fn heap_reports(
+        &self,
+        budget: Option<&vk::PhysicalDeviceMemoryBudgetPropertiesEXT>,
+    ) -> Vec<HeapReport> {
+        self.memory_properties
+            .memory_heaps_as_slice()
+            .iter()
+            .enumerate()
+            .map(|(index, heap)| {
+                let allocations = self
+                    .allocations
+                    .values()
+                    .filter(|allocation| allocation.heap_index == index as u32);
+                HeapReport {
+                    heap_index: index as u32,
+                    flags: heap.flags,
+                    size: heap.size,
+                    allocated_bytes: allocations.clone().map(|allocation| allocation.size).sum(),
+                    allocation_count: allocations.count(),
+                    budget: budget.map(|budget| budget.heap_budget[index]),
+                    usage: budget.map(|budget| budget.heap_usage[index]),
+                }
+            })
+            .collect()
+    }
+
+    pub fn report(
+        &self,
+        allocator_report: &gpu_allocator::AllocatorReport,
+        budget: Option<&vk::PhysicalDeviceMemoryBudgetPropertiesEXT>,
+    ) -> MemoryReport {
+        let mut by_kind: HashMap<AllocationKind, AllocationGroup> = HashMap::new();
+        let mut by_name: HashMap<&str, AllocationGroup> = HashMap::new();
+        for allocation in self.allocations.values() {
+            for group in [
+                by_kind
+                    .entry(allocation.kind)
+                    .or_insert(AllocationGroup { count: 0, bytes: 0 }),
+                by_name
+                    .entry(&allocation.name)
+                    .or_insert(AllocationGroup { count: 0, bytes: 0 }),
+            ] {
+                group.count += 1;
+                group.bytes += allocation.size;
+            }
+        }
+        let mut by_kind: Vec<_> = by_kind.into_iter().collect();
+        by_kind.sort_by_key(|(_, group)| std::cmp::Reverse(group.bytes));
+        let mut by_name: Vec<_> = by_name
+            .into_iter()
+            .map(|(name, group)| (name.to_owned(), group))
+            .collect();
+        by_name.sort_by_key(|(_, group)| std::cmp::Reverse(group.bytes));
+
+        let blocks = allocator_report
+            .blocks
+            .iter()
+            .map(|block| {
+                let mut allocations: Vec<_> = allocator_report.allocations
+                    [block.allocations.clone()]
+                .iter()
+                .map(|allocation| (allocation.offset, allocation.size))
+                .collect();
+                allocations.sort_unstable();
+
+                // Gaps between allocations plus the tail of the block
+                let mut largest_free_range = 0;
+                let mut end = 0;
+                for (offset, size) in allocations.iter().copied() {
+                    largest_free_range = largest_free_range.max(offset.saturating_sub(end));
+                    end = end.max(offset + size);
+                }
+                largest_free_range = largest_free_range.max(block.size.saturating_sub(end));
+
+                BlockReport {
+                    size: block.size,
+                    allocated_bytes: allocations.iter().map(|&(_, size)| size).sum(),
+                    allocation_count: allocations.len(),
+                    largest_free_range,
+                }
+            })
+            .collect();
+
+        MemoryReport {
+            heaps: self.heap_reports(budget),
+            by_kind,
+            by_name,
+            blocks,
+            total_allocated_bytes: allocator_report.total_allocated_bytes,
+            total_reserved_bytes: allocator_report.total_reserved_bytes,
+        }
+    }
+
+    pub fn check_budget(&mut self, budget: &vk::PhysicalDeviceMemoryBudgetPropertiesEXT) {
+        for heap in self.heap_reports(Some(budget)) {
+            let heap_bit = 1 << heap.heap_index;
+            let over_threshold = heap
+                .budget_ratio()
+                .is_some_and(|ratio| ratio >= self.budget_warning_threshold);
+            if !over_threshold {
+                self.warned_heaps &= !heap_bit;
+                continue;
+            }
+
+            if self.warned_heaps & heap_bit == 0 {
+                self.warned_heaps |= heap_bit;
+                if let Some(budget_warning) = self.budget_warning.as_mut() {
+                    budget_warning(&heap);
+                }
+            }
+        }
+    }
+
+    // Allocations that are still alive, used for the leak report on shutdown
+    pub fn leak_report(&self) -> Option<String> {
+        if self.allocations.is_empty() {
+            return None;
+        }
+
+        let mut allocations: Vec<_> = self.allocations.values().collect();
+        allocations.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+        let mut report = format!("{} GPU allocations were leaked:\n", allocations.len());
+        for allocation in allocations {
+            let name = match allocation.name.as_str() {
+                "" => "<unnamed>",
+                name => name,
+            };
+            report.push_str(&format!(
+                "  {:?} \"{}\": {} bytes in heap {}\n",
+                allocation.kind, name, allocation.size, allocation.heap_index
+            ));
+        }
+
+        Some(report)
+    }
+}
@@ -0,0 +1,569 @@
+use ash::vk;
+use std::{fmt, ops::Range, path::Path};
+
+use super::{
+    BufferID, Device, ImageID, MeshLod, MeshletData, ObjDesc, SamplerDesc, SamplerID, TextureData,
+    TextureDesc, TextureError,
+};
+
+pub type Matrix4 = [[f32; 4]; 4];
+
+pub const IDENTITY: Matrix4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+// Column major, same as glTF and GLSL
+pub fn multiply_matrix(lhs: &Matrix4, rhs: &Matrix4) -> Matrix4 {
+    let mut result = [[0.0; 4]; 4];
+    for (column, rhs_column) in result.iter_mut().zip(rhs) {
+        for (row, value) in column.iter_mut().enumerate() {
+            *value = (0..4).map(|i| lhs[i][row] * rhs_column[i]).sum();
+        }
+    }
+
+    result
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, PartialEq, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coord: [f32; 2],
+    // `w` is the bitangent sign
+    pub tangent: [f32; 4],
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct MeshPrimitive {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    // Index into `SceneData::materials`, `None` uses the default material
+    pub material: Option<usize>,
+    // Filled in by `process`, `indices` stays the most detailed level
+    pub lods: Vec<MeshLod>,
+    pub meshlets: MeshletData,
+}
+
+impl MeshPrimitive {
+    // Fills in zeroed normals from the area weighted normals of the triangles using
+    // the vertex, flat shading needs vertices that aren't shared between faces.
+    pub fn generate_normals(&mut self) {
+        let missing: Vec<bool> = self
+            .vertices
+            .iter()
+            .map(|vertex| vertex.normal == [0.0; 3])
+            .collect();
+        if !missing.contains(&true) {
+            return;
+        }
+
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize].position);
+            let ab = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+            let ac = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+            let normal = [
+                ab[1] * ac[2] - ab[2] * ac[1],
+                ab[2] * ac[0] - ab[0] * ac[2],
+                ab[0] * ac[1] - ab[1] * ac[0],
+            ];
+            for &index in triangle {
+                if missing[index as usize] {
+                    let vertex_normal = &mut self.vertices[index as usize].normal;
+                    (0..3).for_each(|i| vertex_normal[i] += normal[i]);
+                }
+            }
+        }
+
+        for (vertex, _) in self
+            .vertices
+            .iter_mut()
+            .zip(missing)
+            .filter(|(_, missing)| *missing)
+        {
+            let length = vertex
+                .normal
+                .iter()
+                .map(|value| value * value)
+                .sum::<f32>()
+                .sqrt();
+            if length > 0.0 {
+                vertex.normal = vertex.normal.map(|value| value / length);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct MeshData {
+    pub name: String,
+    pub primitives: Vec<MeshPrimitive>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+// glTF metallic-roughness parameters, textures index into `SceneData::textures`
+#[derive(Clone, Debug)]
+pub struct MaterialData {
+    pub name: String,
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub emissive_factor: [f32; 3],
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+
+    pub base_color_texture: Option<usize>,
+    // Roughness in green, metallic in blue
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub occlusion_texture: Option<usize>,
+    pub emissive_texture: Option<usize>,
+}
+
+impl Default for MaterialData {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            base_color_factor: [1.0; 4],
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            emissive_factor: [0.0; 3],
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+            base_color_texture: None,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive_texture: None,
+        }
+    }
+}
+
+pub struct SceneImage {
+    pub name: String,
+    pub data: TextureData,
+}
+
+// Images can be shared by textures with different samplers
+#[derive(Clone, Copy, Debug)]
+pub struct SceneTexture {
+    pub image: usize,
+    pub sampler: SamplerDesc,
+}
+
+#[derive(Clone, Debug)]
+pub struct SceneNode {
+    pub name: String,
+    // Relative to the parent node
+    pub transform: Matrix4,
+    pub mesh: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+// CPU side scene as loaded from a file, `Device::create_scene` uploads it
+#[derive(Default)]
+pub struct SceneData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+    pub images: Vec<SceneImage>,
+    pub textures: Vec<SceneTexture>,
+    pub nodes: Vec<SceneNode>,
+    pub root_nodes: Vec<usize>,
+}
+
+// Indexed like `nodes`, nodes that aren't reachable from a root keep identity
+pub fn world_transforms(nodes: &[SceneNode], root_nodes: &[usize]) -> Vec<Matrix4> {
+    let mut transforms = vec![IDENTITY; nodes.len()];
+    let mut stack: Vec<(usize, Matrix4)> = root_nodes
+        .iter()
+        .map(|&node_index| (node_index, IDENTITY))
+        .collect();
+    while let Some((node_index, parent_transform)) = stack.pop() {
+        let node = &nodes[node_index];
+        let transform = multiply_matrix(&parent_transform, &node.transform);
+        transforms[node_index] = transform;
+        stack.extend(node.children.iter().map(|&child| (child, transform)));
+    }
+
+    transforms
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io { name: String, error: std::io::Error },
+    Gltf { name: String, error: gltf::Error },
+    Malformed { name: String, reason: String },
+    Texture(TextureError),
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { name, error } => write!(f, "{name}: {error}"),
+            SceneError::Gltf { name, error } => write!(f, "{name}: {error}"),
+            SceneError::Malformed { name, reason } => write!(f, "{name}: malformed, {reason}"),
+            SceneError::Texture(error) => write!(f, "{error}"),
+            SceneError::Vulkan(result) => write!(f, "Vulkan error: {result}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<TextureError> for SceneError {
+    fn from(value: TextureError) -> Self {
+        SceneError::Texture(value)
+    }
+}
+
+impl From<vk::Result> for SceneError {
+    fn from(value: vk::Result) -> Self {
+        SceneError::Vulkan(value)
+    }
+}
+
+// Draw ranges into the scene's vertex and index buffers, indices are relative to
+// `vertex_offset`.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuPrimitive {
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    pub first_index: u32,
+    pub index_count: u32,
+    pub material_index: u32,
+    // Range of the LOD buffer, the first level is the base one
+    pub first_lod: u32,
+    pub lod_count: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuMeshLod {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub first_meshlet: u32,
+    pub meshlet_count: u32,
+    pub error: f32,
+}
+
+// `Meshlet` with vertex indices resolved to the scene's vertex buffer
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuMeshlet {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub cone_apex: [f32; 3],
+    pub cone_cutoff: f32,
+    pub cone_axis: [f32; 3],
+    // Into the meshlet vertex buffer, whose values index the vertex buffer
+    pub vertex_offset: u32,
+    // In bytes into the meshlet triangle buffer, always 4 byte aligned
+    pub triangle_offset: u32,
+    pub vertex_count: u32,
+    pub triangle_count: u32,
+    pub primitive_index: u32,
+}
+
+// Texture slots are bindless image and sampler IDs, 0 means the slot is empty
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuMaterial {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 3],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    pub alpha_cutoff: f32,
+    // `AlphaMode` as u32
+    pub alpha_mode: u32,
+    pub double_sided: u32,
+    pub base_color_texture: [u32; 2],
+    pub metallic_roughness_texture: [u32; 2],
+    pub normal_texture: [u32; 2],
+    pub occlusion_texture: [u32; 2],
+    pub emissive_texture: [u32; 2],
+}
+
+pub struct Mesh {
+    pub name: String,
+    // Range of `Scene::primitives`
+    pub primitives: Range<usize>,
+}
+
+// Every mesh of a scene shares the same few buffers, shaders reach them through
+// their device addresses.
+pub struct Scene {
+    pub meshes: Vec<Mesh>,
+    pub primitives: Vec<GpuPrimitive>,
+    pub lods: Vec<GpuMeshLod>,
+    pub meshlets: Vec<GpuMeshlet>,
+    pub materials: Vec<GpuMaterial>,
+    pub nodes: Vec<SceneNode>,
+    pub root_nodes: Vec<usize>,
+    pub images: Vec<ImageID>,
+
+    // `Vertex` array
+    pub vertex_buffer: BufferID,
+    // u32 indices
+    pub index_buffer: BufferID,
+    // `GpuPrimitive` array
+    pub primitive_buffer: BufferID,
+    // `GpuMaterial` array
+    pub material_buffer: BufferID,
+    // `GpuMeshLod` array
+    pub lod_buffer: BufferID,
+    // `GpuMeshlet` array
+    pub meshlet_buffer: BufferID,
+    // u32 indices into the vertex buffer
+    pub meshlet_vertex_buffer: BufferID,
+    // 3 u8 local vertex indices per triangle
+    pub meshlet_triangle_buffer: BufferID,
+}
+
+impl Device {
+    pub fn load_gltf(&mut self, path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+        let path = path.as_ref();
+        let scene_data = SceneData::from_gltf(path)?;
+
+        self.create_scene(&path.display().to_string(), &scene_data)
+    }
+
+    pub fn load_obj(
+        &mut self,
+        path: impl AsRef<Path>,
+        desc: &ObjDesc,
+    ) -> Result<Scene, SceneError> {
+        let path = path.as_ref();
+        let scene_data = SceneData::from_obj(path, desc)?;
+
+        self.create_scene(&path.display().to_string(), &scene_data)
+    }
+
+    pub fn create_scene(
+        &mut self,
+        name: &str,
+        scene_data: &SceneData,
+    ) -> Result<Scene, SceneError> {
+        let mut images = Vec::with_capacity(scene_data.images.len());
+        for image in &scene_data.images {
+            match self.create_texture(&image.name, &image.data, &TextureDesc::default()) {
+                Ok(image_id) => images.push(image_id),
+                Err(error) => {
+                    images
+                        .into_iter()
+                        .for_each(|image_id| self.destroy_image(image_id));
+                    return Err(error.into());
+                }
+            }
+        }
+
+        let mut samplers: Vec<SamplerID> = Vec::with_capacity(scene_data.textures.len());
+        for texture in &scene_data.textures {
+            match self.create_sampler(&texture.sampler) {
+                Ok(sampler_id) => samplers.push(sampler_id),
+                Err(error) => {
+                    images
+                        .into_iter()
+                        .for_each(|image_id| self.destroy_image(image_id));
+                    return Err(error.into());
+                }
+            }
+        }
+        let texture_slot = |texture: Option<usize>| match texture {
+            Some(texture) => [
+                images[scene_data.textures[texture].image],
+                samplers[texture],
+            ],
+            None => [0, 0],
+        };
+
+        // Primitives without a material use the default one at the end
+        let default_material = MaterialData::default();
+        let materials: Vec<GpuMaterial> = scene_data
+            .materials
+            .iter()
+            .chain([&default_material])
+            .map(|material| GpuMaterial {
+                base_color_factor: material.base_color_factor,
+                emissive_factor: material.emissive_factor,
+                metallic_factor: material.metallic_factor,
+                roughness_factor: material.roughness_factor,
+                normal_scale: material.normal_scale,
+                occlusion_strength: material.occlusion_strength,
+                alpha_cutoff: material.alpha_cutoff,
+                alpha_mode: material.alpha_mode as u32,
+                double_sided: material.double_sided as u32,
+                base_color_texture: texture_slot(material.base_color_texture),
+                metallic_roughness_texture: texture_slot(material.metallic_roughness_texture),
+                normal_texture: texture_slot(material.normal_texture),
+                occlusion_texture: texture_slot(material.occlusion_texture),
+                emissive_texture: texture_slot(material.emissive_texture),
+            })
+            .collect();
+
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        let mut primitives = Vec::new();
+        let mut lods = Vec::new();
+        let mut meshlets = Vec::new();
+        let mut meshlet_vertices: Vec<u32> = Vec::new();
+        let mut meshlet_triangles: Vec<u8> = Vec::new();
+        let mut meshes = Vec::with_capacity(scene_data.meshes.len());
+        for mesh in &scene_data.meshes {
+            let first_primitive = primitives.len();
+            for primitive in &mesh.primitives {
+                let vertex_offset = vertices.len() as u32;
+                let first_lod = lods.len() as u32;
+                for (level_indices, error, level_meshlets) in primitive.levels() {
+                    lods.push(GpuMeshLod {
+                        first_index: indices.len() as u32,
+                        index_count: level_indices.len() as u32,
+                        first_meshlet: meshlets.len() as u32,
+                        meshlet_count: level_meshlets.meshlets.len() as u32,
+                        error,
+                    });
+                    indices.extend_from_slice(level_indices);
+
+                    for meshlet in &level_meshlets.meshlets {
+                        let vertex_range = meshlet.vertex_offset as usize
+                            ..(meshlet.vertex_offset + meshlet.vertex_count) as usize;
+                        let triangle_range = meshlet.triangle_offset as usize
+                            ..(meshlet.triangle_offset + meshlet.triangle_count) as usize;
+                        meshlets.push(GpuMeshlet {
+                            center: meshlet.center,
+                            radius: meshlet.radius,
+                            cone_apex: meshlet.cone_apex,
+                            cone_cutoff: meshlet.cone_cutoff,
+                            cone_axis: meshlet.cone_axis,
+                            vertex_offset: meshlet_vertices.len() as u32,
+                            triangle_offset: meshlet_triangles.len() as u32,
+                            vertex_count: meshlet.vertex_count,
+                            triangle_count: meshlet.triangle_count,
+                            primitive_index: primitives.len() as u32,
+                        });
+                        meshlet_vertices.extend(
+                            level_meshlets.vertices[vertex_range]
+                                .iter()
+                                .map(|&index| vertex_offset + index),
+                        );
+                        meshlet_triangles
+                            .extend(level_meshlets.triangles[triangle_range].iter().flatten());
+                        meshlet_triangles.resize(meshlet_triangles.len().next_multiple_of(4), 0);
+                    }
+                }
+
+                let base_lod = lods[first_lod as usize];
+                primitives.push(GpuPrimitive {
+                    vertex_offset,
+                    vertex_count: primitive.vertices.len() as u32,
+                    first_index: base_lod.first_index,
+                    index_count: base_lod.index_count,
+                    material_index: primitive.material.unwrap_or(scene_data.materials.len()) as u32,
+                    first_lod,
+                    lod_count: lods.len() as u32 - first_lod,
+                });
+                vertices.extend_from_slice(&primitive.vertices);
+            }
+            meshes.push(Mesh {
+                name: mesh.name.clone(),
+                primitives: first_primitive..primitives.len(),
+            });
+        }
+
+        let usage = vk::BufferUsageFlags::STORAGE_BUFFER;
+        let buffers = [
+            (
+                "vertices",
+                bytemuck::cast_slice(&vertices),
+                usage | vk::BufferUsageFlags::VERTEX_BUFFER,
+            ),
+            (
+                "indices",
+                bytemuck::cast_slice(&indices),
+                usage | vk::BufferUsageFlags::INDEX_BUFFER,
+            ),
+            ("primitives", bytemuck::cast_slice(&primitives), usage),
+            ("materials", bytemuck::cast_slice(&materials), usage),
+            ("LODs", bytemuck::cast_slice(&lods), usage),
+            ("meshlets", bytemuck::cast_slice(&meshlets), usage),
+            (
+                "meshlet vertices",
+                bytemuck::cast_slice(&meshlet_vertices),
+                usage,
+            ),
+            ("meshlet triangles", &meshlet_triangles, usage),
+        ];
+        let mut buffer_ids = Vec::with_capacity(buffers.len());
+        for (buffer_name, data, usage) in buffers {
+            match self.create_buffer_with_data(&format!("{name} {buffer_name}"), data, usage) {
+                Ok(buffer_id) => buffer_ids.push(buffer_id),
+                Err(error) => {
+                    buffer_ids
+                        .into_iter()
+                        .for_each(|buffer_id| self.destroy_buffer(buffer_id));
+                    images
+                        .into_iter()
+                        .for_each(|image_id| self.destroy_image(image_id));
+                    return Err(error.into());
+                }
+            }
+        }
+
+        Ok(Scene {
+            meshes,
+            primitives,
+            lods,
+            meshlets,
+            materials,
+            nodes: scene_data.nodes.clone(),
+            root_nodes: scene_data.root_nodes.clone(),
+            images,
+            vertex_buffer: buffer_ids[0],
+            index_buffer: buffer_ids[1],
+            primitive_buffer: buffer_ids[2],
+            material_buffer: buffer_ids[3],
+            lod_buffer: buffer_ids[4],
+            meshlet_buffer: buffer_ids[5],
+            meshlet_vertex_buffer: buffer_ids[6],
+            meshlet_triangle_buffer: buffer_ids[7],
+        })
+    }
+
+    // Samplers are shared through the sampler cache and stay alive until
+    // `destroy_sampler` or the device is dropped
+    pub fn destroy_scene(&mut self, scene: Scene) {
+        for buffer_id in [
+            scene.vertex_buffer,
+            scene.index_buffer,
+            scene.primitive_buffer,
+            scene.material_buffer,
+            scene.lod_buffer,
+            scene.meshlet_buffer,
+            scene.meshlet_vertex_buffer,
+            scene.meshlet_triangle_buffer,
+        ] {
+            self.destroy_buffer(buffer_id);
+        }
+        for image_id in scene.images {
+            self.destroy_image(image_id);
+        }
+    }
+}
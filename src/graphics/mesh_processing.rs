@@ -0,0 +1,319 @@
+use meshopt::{SimplifyOptions, VertexDataAdapter};
+
+use super::{MeshPrimitive, SceneData, Vertex};
+
+// Sized for mesh shaders and compute culling with 64 wide workgroups
+pub const MESHLET_MAX_VERTICES: usize = 64;
+pub const MESHLET_MAX_TRIANGLES: usize = 124;
+// Trades meshlet size for tighter normal cones
+const MESHLET_CONE_WEIGHT: f32 = 0.25;
+
+#[derive(Clone, Copy, Debug)]
+pub struct MeshProcessDesc {
+    pub build_meshlets: bool,
+    // Simplified levels generated after the base one
+    pub lod_count: u32,
+    // Target index count of each level relative to the previous one
+    pub lod_reduction: f32,
+    // Relative to the mesh extents, the chain ends early once a level exceeds it
+    pub lod_max_error: f32,
+}
+
+impl Default for MeshProcessDesc {
+    fn default() -> Self {
+        Self {
+            build_meshlets: true,
+            lod_count: 4,
+            lod_reduction: 0.5,
+            lod_max_error: 0.05,
+        }
+    }
+}
+
+// Bounds are in mesh space, culling rejects the meshlet when
+// `dot(normalize(cone_apex - camera), cone_axis) >= cone_cutoff`.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Meshlet {
+    pub center: [f32; 3],
+    pub radius: f32,
+    pub cone_apex: [f32; 3],
+    pub cone_axis: [f32; 3],
+    pub cone_cutoff: f32,
+
+    // Range of `MeshletData::vertices`
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    // Range of `MeshletData::triangles` in triangles
+    pub triangle_offset: u32,
+    pub triangle_count: u32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct MeshletData {
+    pub meshlets: Vec<Meshlet>,
+    // Indices into the primitive's vertices
+    pub vertices: Vec<u32>,
+    // 3 indices into the meshlet's vertices per triangle
+    pub triangles: Vec<[u8; 3]>,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct MeshLod {
+    pub indices: Vec<u32>,
+    // Relative to the mesh extents
+    pub error: f32,
+    pub meshlets: MeshletData,
+}
+
+impl MeshletData {
+    fn build(indices: &[u32], vertices: &VertexDataAdapter) -> Self {
+        let built = meshopt::build_meshlets(
+            indices,
+            vertices,
+            MESHLET_MAX_VERTICES,
+            MESHLET_MAX_TRIANGLES,
+            MESHLET_CONE_WEIGHT,
+        );
+
+        let mut meshlet_data = Self::default();
+        for meshlet in built.iter() {
+            let bounds = meshopt::compute_meshlet_bounds(meshlet, vertices);
+            meshlet_data.meshlets.push(Meshlet {
+                center: bounds.center,
+                radius: bounds.radius,
+                cone_apex: bounds.cone_apex,
+                cone_axis: bounds.cone_axis,
+                cone_cutoff: bounds.cone_cutoff,
+                vertex_offset: meshlet_data.vertices.len() as u32,
+                vertex_count: meshlet.vertices.len() as u32,
+                triangle_offset: meshlet_data.triangles.len() as u32,
+                triangle_count: (meshlet.triangles.len() / 3) as u32,
+            });
+            meshlet_data.vertices.extend_from_slice(meshlet.vertices);
+            meshlet_data.triangles.extend(
+                meshlet
+                    .triangles
+                    .chunks_exact(3)
+                    .map(|triangle| [triangle[0], triangle[1], triangle[2]]),
+            );
+        }
+
+        meshlet_data
+    }
+}
+
+impl MeshPrimitive {
+    // Reorders indices for the post-transform cache and vertices for fetch locality,
+    // then builds the LOD chain and meshlets of every level. Earlier results are
+    // replaced.
+    pub fn process(&mut self, desc: &MeshProcessDesc) {
+        self.lods.clear();
+        self.meshlets = MeshletData::default();
+        if self.indices.is_empty() {
+            return;
+        }
+
+        meshopt::optimize_vertex_cache_in_place(&mut self.indices, self.vertices.len());
+        let vertex_count =
+            meshopt::optimize_vertex_fetch_in_place(&mut self.indices, &mut self.vertices);
+        self.vertices.truncate(vertex_count);
+
+        let vertex_data = bytemuck::cast_slice(&self.vertices);
+        let vertices = VertexDataAdapter::new(vertex_data, size_of::<Vertex>(), 0)
+            .expect("Vertex positions are at the start of `Vertex`");
+
+        // Every level is simplified from the base one to avoid accumulating error
+        let mut previous_count = self.indices.len();
+        for _ in 0..desc.lod_count {
+            let target_count = ((previous_count as f32 * desc.lod_reduction) as usize) / 3 * 3;
+            let mut error = 0.0;
+            let mut indices = meshopt::simplify(
+                &self.indices,
+                &vertices,
+                target_count,
+                desc.lod_max_error,
+                SimplifyOptions::None,
+                Some(&mut error),
+            );
+            // Stop once the simplifier can't get meaningfully below the last level
+            if indices.is_empty() || indices.len() as f32 > previous_count as f32 * 0.95 {
+                break;
+            }
+
+            meshopt::optimize_vertex_cache_in_place(&mut indices, self.vertices.len());
+            previous_count = indices.len();
+            self.lods.push(MeshLod {
+                indices,
+                error,
+                meshlets: MeshletData::default(),
+            });
+        }
+
+        if desc.build_meshlets {
+            self.meshlets = MeshletData::build(&self.indices, &vertices);
+            for lod in &mut self.lods {
+                lod.meshlets = MeshletData::build(&lod.indices, &vertices);
+            }
+        }
+    }
+
+    // (indices, error, meshlets) of every level, starting with the base one
+    pub fn levels(&self) -> impl Iterator<Item = (&[u32], f32, &MeshletData)> {
+        [(self.indices.as_slice(), 0.0, &self.meshlets)]
+            .into_iter()
+            .chain(
+                self.lods
+                    .iter()
+                    .map(|lod| (lod.indices.as_slice(), lod.error, &lod.meshlets)),
+            )
+    }
+}
+
+impl SceneData {
+    pub fn process_meshes(&mut self, desc: &MeshProcessDesc) {
+        self.meshes
+            .iter_mut()
+            .flat_map(|mesh| &mut mesh.primitives)
+            .for_each(|primitive| primitive.process(desc));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Wavy grid of `size` x `size` quads, plus a vertex no triangle uses
+    fn grid(size: u32) -> MeshPrimitive {
+        let mut primitive = MeshPrimitive::default();
+        for y in 0..=size {
+            for x in 0..=size {
+                let (u, v) = (x as f32 / size as f32, y as f32 / size as f32);
+                primitive.vertices.push(Vertex {
+                    position: [u, v, (u * 12.0).sin() * (v * 9.0).cos() * 0.05],
+                    normal: [0.0, 0.0, 1.0],
+                    tex_coord: [u, v],
+                    ..Default::default()
+                });
+            }
+        }
+        primitive.vertices.push(Vertex::default());
+
+        let row = size + 1;
+        for y in 0..size {
+            for x in 0..size {
+                let corner = y * row + x;
+                primitive.indices.extend([
+                    corner,
+                    corner + 1,
+                    corner + row,
+                    corner + row,
+                    corner + 1,
+                    corner + row + 1,
+                ]);
+            }
+        }
+
+        primitive
+    }
+
+    // Rotated so the smallest index comes first, which keeps the winding
+    fn canonical_triangles(triangles: impl Iterator<Item = [u32; 3]>) -> Vec<[u32; 3]> {
+        let mut triangles: Vec<[u32; 3]> = triangles
+            .map(|[a, b, c]| match a.min(b).min(c) {
+                min if min == a => [a, b, c],
+                min if min == b => [b, c, a],
+                _ => [c, a, b],
+            })
+            .collect();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn builds_decreasing_lods() {
+        let mut primitive = grid(32);
+        let desc = MeshProcessDesc::default();
+        primitive.process(&desc);
+
+        // The unused vertex is dropped
+        assert_eq!(primitive.vertices.len(), 33 * 33);
+        assert_eq!(primitive.indices.len(), 32 * 32 * 6);
+        assert!(!primitive.lods.is_empty());
+        assert!(primitive.lods.len() <= desc.lod_count as usize);
+
+        let mut previous_count = primitive.indices.len();
+        for (indices, error, _) in primitive.levels().skip(1) {
+            assert!(indices.len() < previous_count);
+            assert_eq!(indices.len() % 3, 0);
+            assert!(indices
+                .iter()
+                .all(|&index| (index as usize) < primitive.vertices.len()));
+            assert!((0.0..=desc.lod_max_error).contains(&error));
+            previous_count = indices.len();
+        }
+
+        // Processing again replaces the previous results
+        let lod_count = primitive.lods.len();
+        primitive.process(&desc);
+        assert_eq!(primitive.lods.len(), lod_count);
+    }
+
+    #[test]
+    fn builds_meshlets_within_limits() {
+        let mut primitive = grid(32);
+        primitive.process(&MeshProcessDesc::default());
+
+        for (indices, _, meshlet_data) in primitive.levels() {
+            assert!(!meshlet_data.meshlets.is_empty());
+            let mut triangles = Vec::new();
+            for meshlet in &meshlet_data.meshlets {
+                assert!((1..=MESHLET_MAX_VERTICES).contains(&(meshlet.vertex_count as usize)));
+                assert!((1..=MESHLET_MAX_TRIANGLES).contains(&(meshlet.triangle_count as usize)));
+
+                let vertex_range = meshlet.vertex_offset as usize
+                    ..(meshlet.vertex_offset + meshlet.vertex_count) as usize;
+                let triangle_range = meshlet.triangle_offset as usize
+                    ..(meshlet.triangle_offset + meshlet.triangle_count) as usize;
+                let vertices = &meshlet_data.vertices[vertex_range];
+                assert!(vertices
+                    .iter()
+                    .all(|&vertex| (vertex as usize) < primitive.vertices.len()));
+                for triangle in &meshlet_data.triangles[triangle_range] {
+                    assert!(triangle
+                        .iter()
+                        .all(|&index| u32::from(index) < meshlet.vertex_count));
+                    triangles.push(triangle.map(|index| vertices[index as usize]));
+                }
+                assert!(meshlet.radius > 0.0);
+            }
+
+            // Meshlets cover every triangle of the level exactly once
+            assert_eq!(
+                canonical_triangles(triangles.into_iter()),
+                canonical_triangles(indices.chunks_exact(3).map(|triangle| [
+                    triangle[0],
+                    triangle[1],
+                    triangle[2]
+                ]))
+            );
+        }
+    }
+
+    #[test]
+    fn skips_disabled_and_empty_work() {
+        let mut primitive = grid(8);
+        primitive.process(&MeshProcessDesc {
+            build_meshlets: false,
+            lod_count: 0,
+            ..Default::default()
+        });
+        assert!(primitive.lods.is_empty());
+        assert!(primitive.meshlets.meshlets.is_empty());
+        assert_eq!(primitive.levels().count(), 1);
+
+        let mut primitive = MeshPrimitive::default();
+        primitive.process(&MeshProcessDesc::default());
+        assert!(primitive.lods.is_empty());
+        assert!(primitive.meshlets.meshlets.is_empty());
+    }
+}
@@ -32,12 +32,47 @@ macro_rules! define_from_tupl {
 
 mod command;
 mod device;
+mod gltf_file;
 mod gpu_resource;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+mod memory;
+mod mesh;
+mod mesh_processing;
+mod obj_file;
 mod physical_device;
+mod pipeline;
+mod profiler;
+mod query;
+mod render_graph;
+mod shader;
+#[cfg(feature = "shader-compiler")]
+mod shader_compiler;
 mod swapchain;
+#[cfg(test)]
+mod test_util;
+mod texture;
+mod texture_file;
+mod trace;
 
 pub use command::*;
 pub use device::*;
 pub use gpu_resource::*;
+#[cfg(feature = "hot-reload")]
+pub use hot_reload::*;
+pub use memory::*;
+pub use mesh::*;
+pub use mesh_processing::*;
+pub use obj_file::*;
 pub use physical_device::*;
+pub use pipeline::*;
+pub use profiler::*;
+pub use query::*;
+pub use render_graph::*;
+pub use shader::*;
+#[cfg(feature = "shader-compiler")]
+pub use shader_compiler::*;
 pub use swapchain::*;
+pub use texture::*;
+pub use texture_file::*;
+pub use trace::*;
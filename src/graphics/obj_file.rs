@@ -0,0 +1,695 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    AlphaMode, MaterialData, MeshData, MeshPrimitive, SamplerDesc, SceneData, SceneError,
+    SceneImage, SceneNode, SceneTexture, TextureData, Vertex, IDENTITY,
+};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ObjDesc {
+    // Shading of generated normals until the first `s` statement, faces after it
+    // follow their smoothing group.
+    pub smooth_normals: bool,
+}
+
+// (position, texture coordinate, normal, smoothing), vertices without a normal are
+// only shared within a smoothing group and flat faces never share them.
+type VertexKey = (u32, Option<u32>, Option<u32>, u64);
+
+struct PrimitiveBuilder {
+    primitive: MeshPrimitive,
+    vertices: HashMap<VertexKey, u32>,
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+    name: String,
+    // Keyed by material
+    primitives: HashMap<Option<usize>, PrimitiveBuilder>,
+}
+
+#[derive(Default)]
+struct MaterialLibrary {
+    materials: Vec<MaterialData>,
+    names: HashMap<String, usize>,
+    images: Vec<SceneImage>,
+    textures: Vec<SceneTexture>,
+    texture_cache: HashMap<(PathBuf, bool), usize>,
+}
+
+impl MaterialLibrary {
+    fn texture(&mut self, path: PathBuf, srgb: bool) -> Result<usize, SceneError> {
+        if let Some(&texture) = self.texture_cache.get(&(path.clone(), srgb)) {
+            return Ok(texture);
+        }
+
+        self.images.push(SceneImage {
+            name: path.display().to_string(),
+            data: TextureData::load(&path, srgb)?,
+        });
+        self.textures.push(SceneTexture {
+            image: self.images.len() - 1,
+            sampler: SamplerDesc::default(),
+        });
+        self.texture_cache
+            .insert((path, srgb), self.textures.len() - 1);
+
+        Ok(self.textures.len() - 1)
+    }
+
+    // Classic Phong materials are mapped to metallic-roughness, `Pr`/`Pm` from the
+    // PBR extension override the estimate.
+    fn load(&mut self, path: &Path) -> Result<(), SceneError> {
+        let name = path.display().to_string();
+        let source = std::fs::read_to_string(path).map_err(|error| SceneError::Io {
+            name: name.clone(),
+            error,
+        })?;
+        let base = path.parent().unwrap_or(Path::new("."));
+
+        let mut current = None;
+        // Set once the current material has a `Pr`, a later `Ns` must not override it
+        let mut has_pbr_roughness = false;
+        for (line_index, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+            let arguments: Vec<&str> = tokens.collect();
+            let malformed = || SceneError::Malformed {
+                name: name.clone(),
+                reason: format!(
+                    "line {}: invalid `{}` statement",
+                    line_index + 1,
+                    line.trim()
+                ),
+            };
+
+            if keyword == "newmtl" {
+                self.materials.push(MaterialData {
+                    name: arguments.join(" "),
+                    metallic_factor: 0.0,
+                    ..Default::default()
+                });
+                current = Some(self.materials.len() - 1);
+                has_pbr_roughness = false;
+                self.names
+                    .insert(arguments.join(" "), self.materials.len() - 1);
+                continue;
+            }
+            let Some(material_index) = current else {
+                continue;
+            };
+
+            let floats = || -> Result<Vec<f32>, SceneError> {
+                arguments
+                    .iter()
+                    .map(|argument| argument.parse::<f32>().map_err(|_| malformed()))
+                    .collect()
+            };
+            let float =
+                || -> Result<f32, SceneError> { floats()?.first().copied().ok_or_else(malformed) };
+            let color = || -> Result<[f32; 3], SceneError> {
+                match *floats()?.as_slice() {
+                    // A single value is a gray level
+                    [value] => Ok([value; 3]),
+                    [r, g, b, ..] => Ok([r, g, b]),
+                    _ => Err(malformed()),
+                }
+            };
+            // Texture options come first, the file name is the last argument
+            let texture_path = || -> Result<PathBuf, SceneError> {
+                let file = arguments.last().ok_or_else(malformed)?;
+                Ok(base.join(file.replace('\\', "/")))
+            };
+
+            match keyword {
+                "Kd" => {
+                    let [r, g, b] = color()?;
+                    let material = &mut self.materials[material_index];
+                    material.base_color_factor = [r, g, b, material.base_color_factor[3]];
+                }
+                "d" | "Tr" => {
+                    let alpha = match keyword {
+                        "d" => float()?,
+                        _ => 1.0 - float()?,
+                    };
+                    let material = &mut self.materials[material_index];
+                    material.base_color_factor[3] = alpha;
+                    material.alpha_mode = match alpha < 1.0 {
+                        true => AlphaMode::Blend,
+                        false => AlphaMode::Opaque,
+                    };
+                }
+                "Ke" => self.materials[material_index].emissive_factor = color()?,
+                // Phong exponent to the roughness with a similar highlight
+                "Ns" => {
+                    let roughness = (2.0 / (float()?.max(0.0) + 2.0)).sqrt();
+                    if !has_pbr_roughness {
+                        self.materials[material_index].roughness_factor = roughness;
+                    }
+                }
+                "Pr" => {
+                    self.materials[material_index].roughness_factor = float()?;
+                    has_pbr_roughness = true;
+                }
+                "Pm" => self.materials[material_index].metallic_factor = float()?,
+                "map_Kd" => {
+                    let texture = self.texture(texture_path()?, true)?;
+                    self.materials[material_index].base_color_texture = Some(texture);
+                }
+                "map_Ke" => {
+                    let texture = self.texture(texture_path()?, true)?;
+                    self.materials[material_index].emissive_texture = Some(texture);
+                }
+                "norm" | "map_Bump" | "map_bump" | "bump" => {
+                    let texture = self.texture(texture_path()?, false)?;
+                    self.materials[material_index].normal_texture = Some(texture);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Ear clipping in the plane the polygon is most aligned with, indices are local to
+// `polygon` and keep its winding.
+fn triangulate(polygon: &[[f32; 3]]) -> Vec<[usize; 3]> {
+    let count = polygon.len();
+    if count == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method, works for concave polygons
+    let mut normal = [0.0f32; 3];
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % count];
+        normal[0] += (a[1] - b[1]) * (a[2] + b[2]);
+        normal[1] += (a[2] - b[2]) * (a[0] + b[0]);
+        normal[2] += (a[0] - b[0]) * (a[1] + b[1]);
+    }
+    let axis = (0..3)
+        .max_by(|&lhs, &rhs| normal[lhs].abs().total_cmp(&normal[rhs].abs()))
+        .unwrap_or(2);
+    // Cyclic so counter clockwise in 2D matches a positive normal
+    let (u, v) = [(1, 2), (2, 0), (0, 1)][axis];
+    let sign = normal[axis].signum();
+    let points: Vec<[f32; 2]> = polygon.iter().map(|point| [point[u], point[v]]).collect();
+    let cross = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| {
+        ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])) * sign
+    };
+
+    let mut remaining: Vec<usize> = (0..count).collect();
+    let mut triangles = Vec::with_capacity(count - 2);
+    while remaining.len() > 3 {
+        let remaining_count = remaining.len();
+        let corner = |i: usize| {
+            [
+                remaining[(i + remaining_count - 1) % remaining_count],
+                remaining[i],
+                remaining[(i + 1) % remaining_count],
+            ]
+        };
+        let ear = (0..remaining_count).find(|&i| {
+            let triangle = corner(i);
+            let [a, b, c] = triangle.map(|index| points[index]);
+            cross(a, b, c) > 0.0
+                && remaining.iter().all(|other| {
+                    let p = points[*other];
+                    triangle.contains(other)
+                        || cross(a, b, p) < 0.0
+                        || cross(b, c, p) < 0.0
+                        || cross(c, a, p) < 0.0
+                })
+        });
+        // Degenerate or self intersecting polygons have no ears, clip anyway
+        let ear = ear.unwrap_or(0);
+        triangles.push(corner(ear));
+        remaining.remove(ear);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+
+    triangles
+}
+
+impl MeshBuilder {
+    fn finish(self, meshes: &mut Vec<MeshData>) {
+        let mut primitives: Vec<_> = self
+            .primitives
+            .into_values()
+            .map(|builder| builder.primitive)
+            .filter(|primitive| !primitive.indices.is_empty())
+            .collect();
+        if primitives.is_empty() {
+            return;
+        }
+
+        primitives.sort_by_key(|primitive| primitive.material);
+        primitives
+            .iter_mut()
+            .for_each(MeshPrimitive::generate_normals);
+        meshes.push(MeshData {
+            name: self.name,
+            primitives,
+        });
+    }
+}
+
+impl SceneData {
+    // Every object or group becomes a mesh with one primitive per material and a
+    // root node of its own. Material libraries and textures are resolved relative
+    // to the file that references them.
+    pub fn from_obj(path: impl AsRef<Path>, desc: &ObjDesc) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let source = std::fs::read_to_string(path).map_err(|error| SceneError::Io {
+            name: name.clone(),
+            error,
+        })?;
+        let base = path.parent().unwrap_or(Path::new("."));
+
+        let mut positions: Vec<[f32; 3]> = Vec::new();
+        let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+        let mut normals: Vec<[f32; 3]> = Vec::new();
+        let mut library = MaterialLibrary::default();
+        let mut meshes = Vec::new();
+        let mut mesh = MeshBuilder {
+            name: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        let mut material = None;
+        // 0 is off, flat faces get unique keys from `face_index`
+        let mut smoothing_group = desc.smooth_normals as u64;
+        let mut face_index = 0u64;
+
+        for (line_index, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+            let arguments: Vec<&str> = tokens.collect();
+            let malformed = |reason: &str| SceneError::Malformed {
+                name: name.clone(),
+                reason: format!("line {}: {reason}", line_index + 1),
+            };
+            // At least `min` values, anything after the first `max` is ignored
+            let floats = |min: usize, max: usize| -> Result<Vec<f32>, SceneError> {
+                let values = arguments
+                    .iter()
+                    .take(max)
+                    .map(|argument| argument.parse::<f32>())
+                    .collect::<Result<Vec<f32>, _>>()
+                    .map_err(|_| malformed("invalid number"))?;
+                match values.len() >= min {
+                    true => Ok(values),
+                    false => Err(malformed("missing values")),
+                }
+            };
+
+            match keyword {
+                "v" => {
+                    let values = floats(3, 3)?;
+                    positions.push([values[0], values[1], values[2]]);
+                }
+                // OBJ puts the origin at the bottom left, `v` defaults to 0
+                "vt" => {
+                    let values = floats(1, 3)?;
+                    let v = values.get(1).copied().unwrap_or(0.0);
+                    tex_coords.push([values[0], 1.0 - v]);
+                }
+                "vn" => {
+                    let values = floats(3, 3)?;
+                    normals.push([values[0], values[1], values[2]]);
+                }
+                "o" | "g" => {
+                    let mesh_name = arguments.join(" ");
+                    if mesh_name != mesh.name {
+                        std::mem::take(&mut mesh).finish(&mut meshes);
+                        mesh.name = mesh_name;
+                    }
+                }
+                "s" => {
+                    smoothing_group = match arguments.first() {
+                        Some(&"off") | None => 0,
+                        Some(group) => group
+                            .parse()
+                            .map_err(|_| malformed("invalid smoothing group"))?,
+                    };
+                }
+                "mtllib" => {
+                    for file in arguments {
+                        library.load(&base.join(file.replace('\\', "/")))?;
+                    }
+                }
+                "usemtl" => {
+                    let material_name = arguments.join(" ");
+                    material = library.names.get(&material_name).copied();
+                    if material.is_none() {
+                        println!("{name}: material `{material_name}` not found");
+                    }
+                }
+                "f" => {
+                    if arguments.len() < 3 {
+                        return Err(malformed("faces need at least 3 vertices"));
+                    }
+                    face_index += 1;
+
+                    // Indices start at 1, negative ones count back from the end
+                    let resolve = |index: Option<&str>, count: usize| -> Result<_, SceneError> {
+                        let Some(index) = index.filter(|index| !index.is_empty()) else {
+                            return Ok(None);
+                        };
+                        let index: i64 = index.parse().map_err(|_| malformed("invalid index"))?;
+                        let resolved = match index {
+                            1.. => index - 1,
+                            ..0 => count as i64 + index,
+                            0 => -1,
+                        };
+                        match (0..count as i64).contains(&resolved) {
+                            true => Ok(Some(resolved as u32)),
+                            false => Err(malformed("index out of range")),
+                        }
+                    };
+
+                    let mut keys = Vec::with_capacity(arguments.len());
+                    for argument in &arguments {
+                        let mut indices = argument.split('/');
+                        let position = resolve(indices.next(), positions.len())?
+                            .ok_or_else(|| malformed("vertex without a position"))?;
+                        let tex_coord = resolve(indices.next(), tex_coords.len())?;
+                        let normal = resolve(indices.next(), normals.len())?;
+                        let smoothing = match (normal, smoothing_group) {
+                            (Some(_), _) => 0,
+                            (None, 0) => face_index << 32,
+                            (None, group) => group,
+                        };
+                        keys.push((position, tex_coord, normal, smoothing));
+                    }
+
+                    let builder =
+                        mesh.primitives
+                            .entry(material)
+                            .or_insert_with(|| PrimitiveBuilder {
+                                primitive: MeshPrimitive {
+                                    material,
+                                    ..Default::default()
+                                },
+                                vertices: HashMap::new(),
+                            });
+                    let vertex_indices: Vec<u32> = keys
+                        .iter()
+                        .map(|&key| {
+                            *builder.vertices.entry(key).or_insert_with(|| {
+                                let (position, tex_coord, normal, _) = key;
+                                builder.primitive.vertices.push(Vertex {
+                                    position: positions[position as usize],
+                                    normal: normal.map_or([0.0; 3], |n| normals[n as usize]),
+                                    tex_coord: tex_coord
+                                        .map_or([0.0; 2], |t| tex_coords[t as usize]),
+                                    ..Default::default()
+                                });
+                                builder.primitive.vertices.len() as u32 - 1
+                            })
+                        })
+                        .collect();
+
+                    let polygon: Vec<[f32; 3]> = keys
+                        .iter()
+                        .map(|&(position, ..)| positions[position as usize])
+                        .collect();
+                    for triangle in triangulate(&polygon) {
+                        builder
+                            .primitive
+                            .indices
+                            .extend(triangle.map(|i| vertex_indices[i]));
+                    }
+                }
+                _ => {}
+            }
+        }
+        mesh.finish(&mut meshes);
+
+        let nodes = meshes
+            .iter()
+            .enumerate()
+            .map(|(mesh_index, mesh)| SceneNode {
+                name: mesh.name.clone(),
+                transform: IDENTITY,
+                mesh: Some(mesh_index),
+                children: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            meshes,
+            materials: library.materials,
+            images: library.images,
+            textures: library.textures,
+            root_nodes: (0..nodes.len()).collect(),
+            nodes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::test_util::TempDir;
+
+    fn load(test: &str, source: &str, desc: &ObjDesc) -> Result<SceneData, SceneError> {
+        let dir = TempDir::new(test, &[("test.obj", source)]);
+        SceneData::from_obj(dir.join("test.obj"), desc)
+    }
+
+    // Twice the signed area of `triangle` projected on the XY plane
+    fn signed_area(polygon: &[[f32; 3]], triangle: [usize; 3]) -> f32 {
+        let [a, b, c] = triangle.map(|index| polygon[index]);
+        (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+    }
+
+    #[test]
+    fn triangulates_concave_polygons() {
+        // L shape, the corner at (1, 1) is reflex
+        let polygon = [
+            [0.0, 0.0, 0.0],
+            [2.0, 0.0, 0.0],
+            [2.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 2.0, 0.0],
+            [0.0, 2.0, 0.0],
+        ];
+        let triangles = triangulate(&polygon);
+        assert_eq!(triangles.len(), 4);
+        // Covering the polygon with counter clockwise triangles means none of them
+        // overlap or reach outside
+        assert!(triangles
+            .iter()
+            .all(|&triangle| signed_area(&polygon, triangle) > 0.0));
+        let area: f32 = triangles
+            .iter()
+            .map(|&triangle| signed_area(&polygon, triangle))
+            .sum();
+        assert_eq!(area, 6.0);
+
+        // Clockwise input stays clockwise
+        let reversed: Vec<[f32; 3]> = polygon.iter().rev().copied().collect();
+        let triangles = triangulate(&reversed);
+        assert_eq!(triangles.len(), 4);
+        assert!(triangles
+            .iter()
+            .all(|&triangle| signed_area(&reversed, triangle) < 0.0));
+    }
+
+    #[test]
+    fn resolves_negative_indices() {
+        let source = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 1
+f 1/1 2/1 3/2
+f -4/-2 -2/-1 -1/-1
+";
+        let scene = load("resolves_negative_indices", source, &ObjDesc::default()).unwrap();
+        let primitive = &scene.meshes[0].primitives[0];
+        let positions: Vec<[f32; 3]> = primitive
+            .indices
+            .iter()
+            .map(|&index| primitive.vertices[index as usize].position)
+            .collect();
+
+        assert_eq!(scene.meshes[0].name, "test");
+        assert_eq!(
+            positions,
+            [
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ]
+        );
+        // Flipped vertically
+        assert_eq!(
+            primitive.vertices[primitive.indices[5] as usize].tex_coord,
+            [1.0, 0.0]
+        );
+
+        // `v` and `w` are optional
+        let source = "v 0 0 0\nv 1 0 0\nv 1 1 0\nvt 0.5\nvt 0.25 0.5 1\nf 1/1 2/2 3/1\n";
+        let scene = load("resolves_texture_coordinates", source, &ObjDesc::default()).unwrap();
+        let primitive = &scene.meshes[0].primitives[0];
+        let tex_coords: Vec<[f32; 2]> = primitive
+            .indices
+            .iter()
+            .map(|&index| primitive.vertices[index as usize].tex_coord)
+            .collect();
+        assert_eq!(tex_coords, [[0.5, 1.0], [0.25, 0.5], [0.5, 1.0]]);
+        assert!(load("resolves_texture_coordinates", "vt\n", &ObjDesc::default()).is_err());
+
+        for (face, line) in [("f 1 2 4", 4), ("f 0 1 2", 4), ("f -4 1 2", 4)] {
+            let source = format!("v 0 0 0\nv 1 0 0\nv 1 1 0\n{face}\n");
+            match load("resolves_negative_indices", &source, &ObjDesc::default()) {
+                Err(SceneError::Malformed { reason, .. }) => {
+                    assert_eq!(reason, format!("line {line}: index out of range"));
+                }
+                _ => panic!("`{face}` was accepted"),
+            }
+        }
+    }
+
+    #[test]
+    fn generates_flat_and_smooth_normals() {
+        // Two faces folded along the X axis, facing +Z and +Y
+        let faces = "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v 0 0 1
+f 1 2 3
+f 1 4 2
+";
+        let normals = |scene: &SceneData| -> Vec<[f32; 3]> {
+            let primitive = &scene.meshes[0].primitives[0];
+            primitive
+                .indices
+                .iter()
+                .map(|&index| primitive.vertices[index as usize].normal)
+                .collect()
+        };
+
+        let flat = load("generates_flat_normals", faces, &ObjDesc::default()).unwrap();
+        assert_eq!(flat.meshes[0].primitives[0].vertices.len(), 6);
+        assert_eq!(
+            normals(&flat),
+            [[[0.0, 0.0, 1.0]; 3], [[0.0, 1.0, 0.0]; 3]].concat()
+        );
+
+        // Shared vertices average the faces around them
+        let smooth_desc = ObjDesc {
+            smooth_normals: true,
+        };
+        let smooth = load("generates_smooth_normals", faces, &smooth_desc).unwrap();
+        let diagonal = [
+            0.0,
+            std::f32::consts::FRAC_1_SQRT_2,
+            std::f32::consts::FRAC_1_SQRT_2,
+        ];
+        assert_eq!(smooth.meshes[0].primitives[0].vertices.len(), 4);
+        assert_eq!(
+            normals(&smooth),
+            [
+                diagonal,
+                diagonal,
+                [0.0, 0.0, 1.0],
+                diagonal,
+                [0.0, 1.0, 0.0],
+                diagonal,
+            ]
+        );
+
+        // Smoothing groups override the description, normals from the file are kept
+        let grouped = load(
+            "generates_grouped_normals",
+            &format!("s off\n{faces}s 1\nvn 1 0 0\nf 2//1 3//1 4//1\n"),
+            &smooth_desc,
+        )
+        .unwrap();
+        assert_eq!(
+            normals(&grouped),
+            [
+                [[0.0, 0.0, 1.0]; 3],
+                [[0.0, 1.0, 0.0]; 3],
+                [[1.0, 0.0, 0.0]; 3]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn loads_materials() {
+        let dir = TempDir::new(
+            "loads_materials",
+            &[
+                (
+                    "test.mtl",
+                    "\
+newmtl shiny
+Kd 1 0.5 0.25
+d 0.5
+Pr 0.3
+Ns 10
+newmtl rough
+Ns 0
+",
+                ),
+                (
+                    "test.obj",
+                    "\
+mtllib test.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+usemtl rough
+f 1 2 3
+usemtl shiny
+f 1 3 2
+usemtl missing
+f 3 2 1
+",
+                ),
+            ],
+        );
+        let scene = SceneData::from_obj(dir.join("test.obj"), &ObjDesc::default()).unwrap();
+
+        let [shiny, rough] = &scene.materials[..] else {
+            panic!("expected two materials");
+        };
+        assert_eq!(shiny.name, "shiny");
+        assert_eq!(shiny.base_color_factor, [1.0, 0.5, 0.25, 0.5]);
+        assert_eq!(shiny.alpha_mode, AlphaMode::Blend);
+        assert_eq!(shiny.roughness_factor, 0.3);
+        assert_eq!(shiny.metallic_factor, 0.0);
+        assert_eq!(rough.roughness_factor, 1.0);
+
+        let materials: Vec<Option<usize>> = scene.meshes[0]
+            .primitives
+            .iter()
+            .map(|primitive| primitive.material)
+            .collect();
+        assert_eq!(materials, [None, Some(0), Some(1)]);
+    }
+}
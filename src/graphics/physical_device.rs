@@ -1,5 +1,4 @@
 use ash::{ext, khr, vk, Entry};
-use core::ffi;
 use std::error::Error;
 use winit::{
     raw_window_handle::{HasDisplayHandle, HasWindowHandle},
@@ -58,12 +57,18 @@ pub struct PhysicalDevice {
     pub instance: ash::Instance,
     pub handle: vk::PhysicalDevice,
     pub queue_type_indices: [usize; 3],
+    // Indexed by `CommandType`, 0 means the queue doesn't support timestamps
+    pub timestamp_valid_bits: [u32; 3],
     pub properties: vk::PhysicalDeviceProperties,
+    pub features: vk::PhysicalDeviceFeatures,
+    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    // VK_EXT_memory_budget
+    pub memory_budget_supported: bool,
 }
 
 impl PhysicalDevice {
     pub fn new() -> Result<Self, vk::Result> {
-        let app_name = unsafe { ffi::CStr::from_bytes_with_nul_unchecked(b"Lorr\0") };
+        let app_name = c"Lorr";
         let app_info = vk::ApplicationInfo::default()
             .application_name(app_name)
             .engine_name(app_name)
@@ -110,6 +115,17 @@ impl PhysicalDevice {
 
         let handle = physical_devices[idx];
         let properties = unsafe { instance.get_physical_device_properties(handle) };
+        let features = unsafe { instance.get_physical_device_features(handle) };
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(handle) };
+        let memory_budget_supported = unsafe {
+            instance
+                .enumerate_device_extension_properties(handle)
+                .expect("Failed to get device extensions")
+                .iter()
+                .any(|extension| {
+                    extension.extension_name_as_c_str() == Ok(ext::memory_budget::NAME)
+                })
+        };
         let queue_family_properties = unsafe {
             instance
                 .get_physical_device_queue_family_properties(handle)
@@ -134,13 +150,19 @@ impl PhysicalDevice {
             vk::QueueFlags::COMPUTE,
         )
         .expect("Transfer queue not found");
+        let timestamp_valid_bits =
+            queue_type_indices.map(|index| queue_family_properties[index].1.timestamp_valid_bits);
 
         Ok(Self {
             entry,
             instance,
             handle,
             queue_type_indices,
+            timestamp_valid_bits,
             properties,
+            features,
+            memory_properties,
+            memory_budget_supported,
         })
     }
 
@@ -155,7 +177,10 @@ impl PhysicalDevice {
             queue_create_infos.push(queue_create_info);
         }
 
-        let extensions = [khr::swapchain::NAME.as_ptr()];
+        let mut extensions = vec![khr::swapchain::NAME.as_ptr()];
+        if self.memory_budget_supported {
+            extensions.push(ext::memory_budget::NAME.as_ptr());
+        }
 
         let mut vk13_features = vk::PhysicalDeviceVulkan13Features::default()
             .synchronization2(true)
@@ -178,7 +203,12 @@ impl PhysicalDevice {
         let mut vk11_features = vk::PhysicalDeviceVulkan11Features::default()
             .variable_pointers(true)
             .variable_pointers_storage_buffer(true);
-        let vk10_features = vk::PhysicalDeviceFeatures::default().shader_int64(true);
+        // Optional features are enabled whenever the device has them
+        let vk10_features = vk::PhysicalDeviceFeatures::default()
+            .shader_int64(true)
+            .sampler_anisotropy(self.features.sampler_anisotropy == vk::TRUE)
+            .pipeline_statistics_query(self.features.pipeline_statistics_query == vk::TRUE)
+            .occlusion_query_precise(self.features.occlusion_query_precise == vk::TRUE);
         let mut device_features = vk::PhysicalDeviceFeatures2::default()
             .features(vk10_features)
             .push_next(&mut vk11_features)
@@ -200,6 +230,23 @@ impl PhysicalDevice {
         Ok(device)
     }
 
+    // Heap budgets and this process' usage, `None` without VK_EXT_memory_budget
+    pub fn memory_budget(&self) -> Option<vk::PhysicalDeviceMemoryBudgetPropertiesEXT<'static>> {
+        if !self.memory_budget_supported {
+            return None;
+        }
+
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut memory_properties =
+            vk::PhysicalDeviceMemoryProperties2::default().push_next(&mut budget_properties);
+        unsafe {
+            self.instance
+                .get_physical_device_memory_properties2(self.handle, &mut memory_properties)
+        };
+
+        Some(budget_properties)
+    }
+
     pub fn create_surface(&self, window: &window::Window) -> Result<Surface, Box<dyn Error>> {
         let surface = unsafe {
             ash_window::create_surface(
@@ -0,0 +1,147 @@
+use ash::vk;
+
+use super::Shader;
+
+pub type PipelineID = u32;
+
+#[derive(Clone, Copy)]
+pub struct RasterizerState {
+    pub polygon_mode: vk::PolygonMode,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub depth_clamp: bool,
+    pub depth_bias: bool,
+    pub depth_bias_constant_factor: f32,
+    pub depth_bias_clamp: f32,
+    pub depth_bias_slope_factor: f32,
+    pub line_width: f32,
+}
+
+impl Default for RasterizerState {
+    fn default() -> Self {
+        Self {
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_clamp: false,
+            depth_bias: false,
+            depth_bias_constant_factor: 0.0,
+            depth_bias_clamp: 0.0,
+            depth_bias_slope_factor: 0.0,
+            line_width: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct DepthStencilState {
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare_op: vk::CompareOp,
+    pub stencil_test: bool,
+    pub stencil_front: vk::StencilOpState,
+    pub stencil_back: vk::StencilOpState,
+}
+
+impl Default for DepthStencilState {
+    fn default() -> Self {
+        Self {
+            depth_test: false,
+            depth_write: false,
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            stencil_test: false,
+            stencil_front: vk::StencilOpState::default(),
+            stencil_back: vk::StencilOpState::default(),
+        }
+    }
+}
+
+pub fn opaque_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState::default()
+        .blend_enable(false)
+        .color_write_mask(vk::ColorComponentFlags::RGBA)
+}
+
+#[derive(Clone)]
+pub struct GraphicsPipelineDesc {
+    pub shaders: Vec<Shader>,
+    pub vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    pub vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    pub topology: vk::PrimitiveTopology,
+    pub primitive_restart: bool,
+    pub rasterizer: RasterizerState,
+    pub samples: vk::SampleCountFlags,
+    pub depth_stencil: DepthStencilState,
+    // Missing entries are filled with `opaque_blend_attachment`
+    pub blend_attachments: Vec<vk::PipelineColorBlendAttachmentState>,
+    pub blend_constants: [f32; 4],
+    pub dynamic_states: Vec<vk::DynamicState>,
+    pub color_formats: Vec<vk::Format>,
+    pub depth_format: vk::Format,
+    pub stencil_format: vk::Format,
+}
+
+impl Default for GraphicsPipelineDesc {
+    fn default() -> Self {
+        Self {
+            shaders: Vec::new(),
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            rasterizer: RasterizerState::default(),
+            samples: vk::SampleCountFlags::TYPE_1,
+            depth_stencil: DepthStencilState::default(),
+            blend_attachments: Vec::new(),
+            blend_constants: [0.0; 4],
+            dynamic_states: vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+            color_formats: Vec::new(),
+            depth_format: vk::Format::UNDEFINED,
+            stencil_format: vk::Format::UNDEFINED,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ComputePipelineDesc {
+    pub shader: Shader,
+}
+
+#[derive(Clone)]
+pub enum PipelineDesc {
+    Graphics(GraphicsPipelineDesc),
+    Compute(ComputePipelineDesc),
+}
+
+impl PipelineDesc {
+    pub fn bind_point(&self) -> vk::PipelineBindPoint {
+        match self {
+            PipelineDesc::Graphics(_) => vk::PipelineBindPoint::GRAPHICS,
+            PipelineDesc::Compute(_) => vk::PipelineBindPoint::COMPUTE,
+        }
+    }
+
+    pub fn shaders(&self) -> &[Shader] {
+        match self {
+            PipelineDesc::Graphics(desc) => &desc.shaders,
+            PipelineDesc::Compute(desc) => std::slice::from_ref(&desc.shader),
+        }
+    }
+
+    pub fn shaders_mut(&mut self) -> &mut [Shader] {
+        match self {
+            PipelineDesc::Graphics(desc) => &mut desc.shaders,
+            PipelineDesc::Compute(desc) => std::slice::from_mut(&mut desc.shader),
+        }
+    }
+}
+
+pub struct Pipeline {
+    pub bind_point: vk::PipelineBindPoint,
+    pub layout: vk::PipelineLayout,
+    // Kept around so the pipeline can be rebuilt
+    pub desc: PipelineDesc,
+
+    pub handle: vk::Pipeline,
+}
+define_from!(Pipeline, vk::Pipeline);
@@ -0,0 +1,221 @@
+use ash::vk;
+use std::{cell::RefCell, rc::Rc, time::Instant};
+
+use super::CommandType;
+
+const MAX_TIMESTAMP_QUERIES: u32 = 1024;
+
+#[derive(Clone, Debug)]
+pub struct GpuScope {
+    pub name: String,
+    pub command_type: CommandType,
+    // Index of the enclosing scope in `ProfilerFrame::scopes`
+    pub parent: Option<usize>,
+    // End timestamp is always `begin_query + 1`
+    pub begin_query: u32,
+    pub ended: bool,
+}
+
+// Queries of a single frame-in-flight, shared with the command lists recording it
+pub struct ProfilerFrame {
+    pub frame: u64,
+    pub next_query: u32,
+    pub scopes: Vec<GpuScope>,
+    // CPU time of the frame's first submission
+    pub submit_time: Option<Instant>,
+
+    pub query_pool: vk::QueryPool,
+}
+
+impl ProfilerFrame {
+    pub fn allocate_queries(&mut self, count: u32) -> Option<u32> {
+        if self.next_query + count > MAX_TIMESTAMP_QUERIES {
+            return None;
+        }
+
+        let first_query = self.next_query;
+        self.next_query += count;
+        Some(first_query)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GpuTiming {
+    pub name: String,
+    pub command_type: CommandType,
+    // Relative to the earliest scope of the frame on the same queue
+    pub start_ms: f64,
+    pub duration_ms: f64,
+    pub children: Vec<GpuTiming>,
+}
+
+pub struct GpuProfiler {
+    // Nanoseconds per timestamp tick
+    pub timestamp_period: f32,
+    pub timestamp_valid_bits: [u32; 3],
+    pub frames: Vec<Rc<RefCell<ProfilerFrame>>>,
+    pub current_frame: usize,
+
+    // Timings of `resolved_frame`, the latest frame that finished on the GPU
+    pub resolved_frame: u64,
+    pub resolved_submit_time: Option<Instant>,
+    pub timings: Vec<GpuTiming>,
+}
+
+impl GpuProfiler {
+    pub fn new(
+        device: &ash::Device,
+        frame_count: u32,
+        timestamp_period: f32,
+        timestamp_valid_bits: [u32; 3],
+    ) -> Result<Self, vk::Result> {
+        let create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(MAX_TIMESTAMP_QUERIES);
+
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        for _ in 0..frame_count {
+            let query_pool = unsafe { device.create_query_pool(&create_info, None)? };
+            // Queries must be reset before their first use
+            unsafe { device.reset_query_pool(query_pool, 0, MAX_TIMESTAMP_QUERIES) };
+            frames.push(Rc::new(RefCell::new(ProfilerFrame {
+                frame: 0,
+                next_query: 0,
+                scopes: Vec::new(),
+                submit_time: None,
+                query_pool,
+            })));
+        }
+
+        Ok(Self {
+            timestamp_period,
+            timestamp_valid_bits,
+            frames,
+            current_frame: 0,
+            resolved_frame: 0,
+            resolved_submit_time: None,
+            timings: Vec::new(),
+        })
+    }
+
+    pub fn current(&self) -> Rc<RefCell<ProfilerFrame>> {
+        self.frames[self.current_frame].clone()
+    }
+
+    // The device must be idle
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for frame in self.frames.drain(..) {
+            unsafe { device.destroy_query_pool(frame.borrow().query_pool, None) };
+        }
+    }
+
+    // The GPU must be done with `frame_index`, its previous results are resolved
+    // into `timings` before the queries are reused. Returns whether `timings` changed.
+    pub fn begin_frame(&mut self, device: &ash::Device, frame_index: usize, frame: u64) -> bool {
+        self.current_frame = frame_index;
+        let profiler_frame = self.frames[frame_index].clone();
+        let mut profiler_frame = profiler_frame.borrow_mut();
+        let resolved = !profiler_frame.scopes.is_empty();
+        if resolved {
+            self.timings = self.resolve(device, &profiler_frame);
+            self.resolved_frame = profiler_frame.frame;
+            self.resolved_submit_time = profiler_frame.submit_time;
+        }
+
+        if profiler_frame.next_query != 0 {
+            unsafe {
+                device.reset_query_pool(profiler_frame.query_pool, 0, profiler_frame.next_query)
+            };
+        }
+        profiler_frame.frame = frame;
+        profiler_frame.next_query = 0;
+        profiler_frame.scopes.clear();
+        profiler_frame.submit_time = None;
+
+        resolved
+    }
+
+    fn resolve(&self, device: &ash::Device, profiler_frame: &ProfilerFrame) -> Vec<GpuTiming> {
+        // (timestamp, availability) pairs, command lists that were never submitted
+        // leave their queries unavailable.
+        let mut results = vec![[0u64; 2]; profiler_frame.next_query as usize];
+        let result = unsafe {
+            device.get_query_pool_results(
+                profiler_frame.query_pool,
+                0,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+        if let Err(error) = result.or_else(|error| match error {
+            vk::Result::NOT_READY => Ok(()),
+            error => Err(error),
+        }) {
+            println!("Failed to read GPU timestamps: {}", error);
+            return Vec::new();
+        }
+
+        let ticks = |scope: &GpuScope| {
+            let valid_bits = self.timestamp_valid_bits[scope.command_type as usize];
+            let mask = match valid_bits {
+                64.. => u64::MAX,
+                bits => (1u64 << bits) - 1,
+            };
+            let [begin, begin_available] = results[scope.begin_query as usize];
+            let [end, end_available] = results[scope.begin_query as usize + 1];
+            if !scope.ended || begin_available == 0 || end_available == 0 {
+                return None;
+            }
+
+            let begin = begin & mask;
+            Some((begin, (end & mask).wrapping_sub(begin) & mask))
+        };
+        let scope_ticks: Vec<Option<(u64, u64)>> =
+            profiler_frame.scopes.iter().map(ticks).collect();
+        // Timestamps of different queues aren't comparable, every queue gets its own baseline
+        let mut first_ticks = [u64::MAX; 3];
+        for (scope, ticks) in profiler_frame.scopes.iter().zip(&scope_ticks) {
+            if let Some((begin, _)) = ticks {
+                let first_tick = &mut first_ticks[scope.command_type as usize];
+                *first_tick = (*first_tick).min(*begin);
+            }
+        }
+
+        let ticks_to_ms = self.timestamp_period as f64 / 1_000_000.0;
+        let mut nodes: Vec<Option<GpuTiming>> = profiler_frame
+            .scopes
+            .iter()
+            .zip(&scope_ticks)
+            .map(|(scope, ticks)| {
+                ticks.map(|(begin, duration)| GpuTiming {
+                    name: scope.name.clone(),
+                    command_type: scope.command_type,
+                    start_ms: begin.wrapping_sub(first_ticks[scope.command_type as usize]) as f64
+                        * ticks_to_ms,
+                    duration_ms: duration as f64 * ticks_to_ms,
+                    children: Vec::new(),
+                })
+            })
+            .collect();
+
+        // Parents always come before their children, so building back to front
+        // finishes every child before it is attached.
+        let mut roots = Vec::new();
+        for index in (0..nodes.len()).rev() {
+            let Some(node) = nodes[index].take() else {
+                continue;
+            };
+
+            match profiler_frame.scopes[index]
+                .parent
+                .and_then(|parent| nodes[parent].as_mut())
+            {
+                Some(parent) => parent.children.insert(0, node),
+                None => roots.push(node),
+            }
+        }
+        roots.reverse();
+
+        roots
+    }
+}
@@ -0,0 +1,229 @@
+use ash::vk;
+use std::{cell::RefCell, rc::Rc};
+
+const MAX_OCCLUSION_QUERIES: u32 = 256;
+const MAX_STATISTICS_QUERIES: u32 = 64;
+
+// Results are written in bit order of the flags
+const STATISTICS_FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+        | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw(),
+);
+const STATISTICS_COUNT: usize = 7;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+}
+
+impl PipelineStatistics {
+    fn from_results(results: &[u64]) -> Self {
+        Self {
+            input_assembly_vertices: results[0],
+            input_assembly_primitives: results[1],
+            vertex_shader_invocations: results[2],
+            clipping_invocations: results[3],
+            clipping_primitives: results[4],
+            fragment_shader_invocations: results[5],
+            compute_shader_invocations: results[6],
+        }
+    }
+}
+
+// Names of the queries a single frame-in-flight recorded, indexed by query
+pub struct QueryFrame {
+    pub frame: u64,
+    pub occlusion_queries: Vec<String>,
+    pub statistics_queries: Vec<String>,
+
+    pub occlusion_pool: vk::QueryPool,
+    // Null when the device doesn't support pipeline statistics
+    pub statistics_pool: vk::QueryPool,
+}
+
+impl QueryFrame {
+    pub fn allocate_occlusion(&mut self, name: &str) -> Option<u32> {
+        if self.occlusion_queries.len() as u32 == MAX_OCCLUSION_QUERIES {
+            return None;
+        }
+
+        self.occlusion_queries.push(name.to_owned());
+        Some(self.occlusion_queries.len() as u32 - 1)
+    }
+
+    pub fn allocate_statistics(&mut self, name: &str) -> Option<u32> {
+        if self.statistics_pool == vk::QueryPool::null()
+            || self.statistics_queries.len() as u32 == MAX_STATISTICS_QUERIES
+        {
+            return None;
+        }
+
+        self.statistics_queries.push(name.to_owned());
+        Some(self.statistics_queries.len() as u32 - 1)
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct QueryResults {
+    pub frame: u64,
+    // Samples that passed depth/stencil testing
+    pub occlusion: Vec<(String, u64)>,
+    pub statistics: Vec<(String, PipelineStatistics)>,
+}
+
+pub struct GpuQueries {
+    pub frames: Vec<Rc<RefCell<QueryFrame>>>,
+    pub current_frame: usize,
+
+    // Results of the latest frame that finished on the GPU
+    pub results: QueryResults,
+}
+
+impl GpuQueries {
+    pub fn new(
+        device: &ash::Device,
+        frame_count: u32,
+        statistics_supported: bool,
+    ) -> Result<Self, vk::Result> {
+        let occlusion_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::OCCLUSION)
+            .query_count(MAX_OCCLUSION_QUERIES);
+        let statistics_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::PIPELINE_STATISTICS)
+            .query_count(MAX_STATISTICS_QUERIES)
+            .pipeline_statistics(STATISTICS_FLAGS);
+
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        for _ in 0..frame_count {
+            let occlusion_pool = unsafe { device.create_query_pool(&occlusion_info, None)? };
+            unsafe { device.reset_query_pool(occlusion_pool, 0, MAX_OCCLUSION_QUERIES) };
+
+            let statistics_pool = if statistics_supported {
+                let statistics_pool = unsafe { device.create_query_pool(&statistics_info, None)? };
+                unsafe { device.reset_query_pool(statistics_pool, 0, MAX_STATISTICS_QUERIES) };
+                statistics_pool
+            } else {
+                vk::QueryPool::null()
+            };
+
+            frames.push(Rc::new(RefCell::new(QueryFrame {
+                frame: 0,
+                occlusion_queries: Vec::new(),
+                statistics_queries: Vec::new(),
+                occlusion_pool,
+                statistics_pool,
+            })));
+        }
+
+        Ok(Self {
+            frames,
+            current_frame: 0,
+            results: QueryResults::default(),
+        })
+    }
+
+    // The device must be idle
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for frame in self.frames.drain(..) {
+            let frame = frame.borrow();
+            unsafe { device.destroy_query_pool(frame.occlusion_pool, None) };
+            if frame.statistics_pool != vk::QueryPool::null() {
+                unsafe { device.destroy_query_pool(frame.statistics_pool, None) };
+            }
+        }
+    }
+
+    pub fn current(&self) -> Rc<RefCell<QueryFrame>> {
+        self.frames[self.current_frame].clone()
+    }
+
+    // Same contract as `GpuProfiler::begin_frame`
+    pub fn begin_frame(&mut self, device: &ash::Device, frame_index: usize, frame: u64) {
+        self.current_frame = frame_index;
+        let query_frame = self.frames[frame_index].clone();
+        let mut query_frame = query_frame.borrow_mut();
+        if !query_frame.occlusion_queries.is_empty() || !query_frame.statistics_queries.is_empty() {
+            self.results = QueryResults {
+                frame: query_frame.frame,
+                occlusion: Self::resolve::<2>(
+                    device,
+                    query_frame.occlusion_pool,
+                    &query_frame.occlusion_queries,
+                )
+                .map(|(name, values)| (name, values[0]))
+                .collect(),
+                statistics: Self::resolve::<{ STATISTICS_COUNT + 1 }>(
+                    device,
+                    query_frame.statistics_pool,
+                    &query_frame.statistics_queries,
+                )
+                .map(|(name, values)| (name, PipelineStatistics::from_results(&values)))
+                .collect(),
+            };
+        }
+
+        unsafe {
+            if !query_frame.occlusion_queries.is_empty() {
+                device.reset_query_pool(
+                    query_frame.occlusion_pool,
+                    0,
+                    query_frame.occlusion_queries.len() as u32,
+                );
+            }
+            if !query_frame.statistics_queries.is_empty() {
+                device.reset_query_pool(
+                    query_frame.statistics_pool,
+                    0,
+                    query_frame.statistics_queries.len() as u32,
+                );
+            }
+        }
+        query_frame.frame = frame;
+        query_frame.occlusion_queries.clear();
+        query_frame.statistics_queries.clear();
+    }
+
+    // Each result is followed by its availability value, queries of command lists
+    // that were never submitted stay unavailable and are skipped.
+    fn resolve<'a, const N: usize>(
+        device: &ash::Device,
+        query_pool: vk::QueryPool,
+        names: &'a [String],
+    ) -> impl Iterator<Item = (String, [u64; N])> + 'a {
+        let mut results = vec![[0u64; N]; names.len()];
+        if !names.is_empty() {
+            let result = unsafe {
+                device.get_query_pool_results(
+                    query_pool,
+                    0,
+                    &mut results,
+                    vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+                )
+            };
+            if let Err(error) = result.or_else(|error| match error {
+                vk::Result::NOT_READY => Ok(()),
+                error => Err(error),
+            }) {
+                println!("Failed to read query results: {}", error);
+                results.clear();
+            }
+        }
+
+        names
+            .iter()
+            .zip(results)
+            .filter(|(_, values)| values[N - 1] != 0)
+            .map(|(name, values)| (name.clone(), values))
+    }
+}
@@ -0,0 +1,1373 @@
+use ash::vk;
+use gpu_allocator::{vulkan, MemoryLocation};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use super::{
+    buffer_state_barrier, image_state_barrier, AllocationKind, Buffer, BufferID, CommandAllocator,
+    CommandList, CommandType, Device, Image, ImageID, ResourceState, SubmitToken,
+};
+
+pub type PassID = usize;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GraphResource {
+    Image(ImageID),
+    Buffer(BufferID),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PassAccess {
+    pub resource: GraphResource,
+    pub state: ResourceState,
+    pub write: bool,
+}
+
+type PassCallback = Box<dyn FnOnce(&Device, &mut CommandList)>;
+
+pub struct RenderPass {
+    pub name: String,
+    pub command_type: CommandType,
+    // A resource is accessed with a single state per pass
+    pub accesses: Vec<PassAccess>,
+    callback: Option<PassCallback>,
+}
+
+impl RenderPass {
+    pub fn read_image(&mut self, image_id: ImageID, state: ResourceState) -> &mut Self {
+        self.access(GraphResource::Image(image_id), state, false)
+    }
+
+    pub fn write_image(&mut self, image_id: ImageID, state: ResourceState) -> &mut Self {
+        self.access(GraphResource::Image(image_id), state, true)
+    }
+
+    pub fn read_buffer(&mut self, buffer_id: BufferID, state: ResourceState) -> &mut Self {
+        self.access(GraphResource::Buffer(buffer_id), state, false)
+    }
+
+    pub fn write_buffer(&mut self, buffer_id: BufferID, state: ResourceState) -> &mut Self {
+        self.access(GraphResource::Buffer(buffer_id), state, true)
+    }
+
+    pub fn execute(
+        &mut self,
+        callback: impl FnOnce(&Device, &mut CommandList) + 'static,
+    ) -> &mut Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    fn access(&mut self, resource: GraphResource, state: ResourceState, write: bool) -> &mut Self {
+        match self
+            .accesses
+            .iter_mut()
+            .find(|access| access.resource == resource)
+        {
+            Some(access) => {
+                access.state = state;
+                access.write |= write;
+            }
+            None => self.accesses.push(PassAccess {
+                resource,
+                state,
+                write,
+            }),
+        }
+
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GraphOutput {
+    pub resource: GraphResource,
+    // State the resource is left in after the graph, e.g. `ResourceState::PRESENT`
+    pub final_state: Option<ResourceState>,
+}
+
+// Consecutive passes on the same queue, recorded into one command list
+#[derive(Clone, Debug)]
+pub struct PassBatch {
+    pub command_type: CommandType,
+    pub passes: Vec<PassID>,
+    // Earlier batches on other queues this one has to wait for
+    pub waits: Vec<usize>,
+}
+
+// Resource created by the graph, its memory is aliased with other transients
+// of the same heap whose lifetimes don't overlap.
+struct TransientResource {
+    name: String,
+    resource: GraphResource,
+    requirements: vk::MemoryRequirements,
+    // Empty for images
+    buffer_usage: vk::BufferUsageFlags,
+    // First and last position in `RenderGraph::order`
+    lifetime: Option<(usize, usize)>,
+    heap: usize,
+    offset: u64,
+    // Transients that used the same memory before this one
+    aliases: Vec<GraphResource>,
+}
+
+struct TransientHeap {
+    memory_type_bits: u32,
+    is_image: bool,
+    size: u64,
+    alignment: u64,
+}
+
+#[derive(Default)]
+struct GraphFrame {
+    command_allocators: [Option<CommandAllocator>; 3],
+    command_lists: [Vec<CommandList>; 3],
+    transients: Vec<GraphResource>,
+    heaps: Vec<vulkan::Allocation>,
+}
+
+// Frame level render graph, rebuilt every frame:
+//
+//  graph.begin(&mut device, frame_index);
+//  let target = graph.create_image(&mut device, "target", create_info)?;
+//  graph.add_pass("draw", CommandType::Graphics)
+//      .write_image(target, ResourceState::COLOR_ATTACHMENT)
+//      .execute(move |device, command_list| { ... });
+//  graph.output_image(target, None);
+//  graph.compile(&mut device)?;
+//  graph.execute(&mut device, &wait_sema_infos, &signal_sema_infos)?;
+//
+// Passes that don't contribute to an output are culled. Imported resources
+// are expected to be owned by the queue family of their first access.
+pub struct RenderGraph {
+    pub passes: Vec<RenderPass>,
+    pub outputs: Vec<GraphOutput>,
+    // Execution order of passes that survived culling
+    pub order: Vec<PassID>,
+    pub batches: Vec<PassBatch>,
+
+    // Passes every pass depends on, see `sort_passes`
+    dependencies: Vec<Vec<PassID>>,
+    transients: Vec<TransientResource>,
+    heaps: Vec<TransientHeap>,
+    frames: Vec<GraphFrame>,
+    frame_index: usize,
+    // Compiling binds transient memory, so it happens once per `begin`
+    compiled: bool,
+    // Transients may already be bound after a failed compile, it isn't retried
+    compile_error: Option<vk::Result>,
+    executed: bool,
+}
+
+impl RenderGraph {
+    pub fn new(frame_count: u32) -> Self {
+        Self {
+            passes: Vec::new(),
+            outputs: Vec::new(),
+            order: Vec::new(),
+            batches: Vec::new(),
+            dependencies: Vec::new(),
+            transients: Vec::new(),
+            heaps: Vec::new(),
+            frames: (0..frame_count).map(|_| GraphFrame::default()).collect(),
+            frame_index: 0,
+            compiled: false,
+            compile_error: None,
+            executed: false,
+        }
+    }
+
+    // `frame_index` is the value returned by `Device::new_frame`, so everything the
+    // graph used for this frame slot before is no longer in use by the GPU.
+    pub fn begin(&mut self, device: &mut Device, frame_index: usize) {
+        self.passes.clear();
+        self.outputs.clear();
+        self.order.clear();
+        self.batches.clear();
+        self.dependencies.clear();
+        self.transients.clear();
+        self.heaps.clear();
+        self.frame_index = frame_index;
+        self.compiled = false;
+        self.compile_error = None;
+        self.executed = false;
+
+        let frame = &mut self.frames[frame_index];
+        Self::release_frame_resources(device, frame);
+        for command_allocator in frame.command_allocators.iter().flatten() {
+            device.reset_command_allocator(command_allocator);
+        }
+    }
+
+    // Frees every transient resource, the device must be idle.
+    pub fn destroy(&mut self, device: &mut Device) {
+        for frame in &mut self.frames {
+            Self::release_frame_resources(device, frame);
+            for command_allocator in frame.command_allocators.iter_mut() {
+                if let Some(command_allocator) = command_allocator.take() {
+                    unsafe {
+                        device
+                            .handle
+                            .destroy_command_pool(command_allocator.into(), None)
+                    };
+                }
+            }
+            frame.command_lists.iter_mut().for_each(Vec::clear);
+        }
+    }
+
+    fn release_frame_resources(device: &mut Device, frame: &mut GraphFrame) {
+        for resource in frame.transients.drain(..) {
+            match resource {
+                GraphResource::Image(image_id) => device.destroy_image(image_id),
+                GraphResource::Buffer(buffer_id) => device.destroy_buffer(buffer_id),
+            }
+        }
+
+        for heap in frame.heaps.drain(..) {
+            device.free_memory(heap);
+        }
+    }
+
+    // Creates an image that only lives for this frame, memory is bound in `compile`.
+    pub fn create_image(
+        &mut self,
+        device: &mut Device,
+        name: &str,
+        create_info: vk::ImageCreateInfo,
+    ) -> Result<ImageID, vk::Result> {
+        assert!(!self.compiled, "Render graph is already compiled");
+        let image = unsafe { device.handle.create_image(&create_info, None)? };
+        let requirements = unsafe { device.handle.get_image_memory_requirements(image) };
+        let image_id = device.register_image(Image {
+            usage: create_info.usage,
+            format: create_info.format,
+            extent: create_info.extent,
+            slices: create_info.array_layers,
+            levels: create_info.mip_levels,
+            states: vec![
+                ResourceState::UNDEFINED;
+                (create_info.array_layers * create_info.mip_levels) as usize
+            ]
+            .into(),
+            default_view: None,
+            allocation: None,
+            handle: image,
+        });
+
+        self.add_transient(
+            name,
+            GraphResource::Image(image_id),
+            requirements,
+            vk::BufferUsageFlags::empty(),
+        );
+        Ok(image_id)
+    }
+
+    // Creates a buffer that only lives for this frame, memory is bound in `compile`.
+    pub fn create_buffer(
+        &mut self,
+        device: &mut Device,
+        name: &str,
+        create_info: vk::BufferCreateInfo,
+    ) -> Result<BufferID, vk::Result> {
+        assert!(!self.compiled, "Render graph is already compiled");
+        let buffer = unsafe { device.handle.create_buffer(&create_info, None)? };
+        let requirements = unsafe { device.handle.get_buffer_memory_requirements(buffer) };
+        let buffer_id = device.register_buffer(Buffer {
+            data_size: create_info.size,
+            device_address: 0,
+            state: ResourceState::UNDEFINED.into(),
+            allocation: None,
+            handle: buffer,
+        });
+
+        self.add_transient(
+            name,
+            GraphResource::Buffer(buffer_id),
+            requirements,
+            create_info.usage,
+        );
+        Ok(buffer_id)
+    }
+
+    fn add_transient(
+        &mut self,
+        name: &str,
+        resource: GraphResource,
+        requirements: vk::MemoryRequirements,
+        buffer_usage: vk::BufferUsageFlags,
+    ) {
+        self.frames[self.frame_index].transients.push(resource);
+        self.transients.push(TransientResource {
+            name: name.to_owned(),
+            resource,
+            requirements,
+            buffer_usage,
+            lifetime: None,
+            heap: 0,
+            offset: 0,
+            aliases: Vec::new(),
+        });
+    }
+
+    pub fn add_pass(&mut self, name: &str, command_type: CommandType) -> &mut RenderPass {
+        assert!(!self.compiled, "Render graph is already compiled");
+        self.passes.push(RenderPass {
+            name: name.to_owned(),
+            command_type,
+            accesses: Vec::new(),
+            callback: None,
+        });
+
+        self.passes.last_mut().unwrap()
+    }
+
+    pub fn output_image(&mut self, image_id: ImageID, final_state: Option<ResourceState>) {
+        assert!(!self.compiled, "Render graph is already compiled");
+        self.outputs.push(GraphOutput {
+            resource: GraphResource::Image(image_id),
+            final_state,
+        });
+    }
+
+    pub fn output_buffer(&mut self, buffer_id: BufferID, final_state: Option<ResourceState>) {
+        assert!(!self.compiled, "Render graph is already compiled");
+        self.outputs.push(GraphOutput {
+            resource: GraphResource::Buffer(buffer_id),
+            final_state,
+        });
+    }
+
+    pub fn compile(&mut self, device: &mut Device) -> Result<(), vk::Result> {
+        if let Some(error) = self.compile_error {
+            return Err(error);
+        }
+        if self.compiled {
+            return Ok(());
+        }
+
+        let alive = self.cull_passes();
+        self.sort_passes(&alive);
+        self.build_batches();
+        self.alias_transients();
+        if let Err(error) = self.bind_transients(device) {
+            self.compile_error = Some(error);
+            return Err(error);
+        }
+        self.compiled = true;
+
+        Ok(())
+    }
+
+    // Walks the passes backwards from the outputs, a pass survives if it writes
+    // something a surviving pass or an output needs.
+    fn cull_passes(&self) -> Vec<bool> {
+        let mut needed: HashSet<GraphResource> =
+            self.outputs.iter().map(|output| output.resource).collect();
+        let mut alive = vec![false; self.passes.len()];
+        for (pass_id, pass) in self.passes.iter().enumerate().rev() {
+            if pass
+                .accesses
+                .iter()
+                .any(|access| access.write && needed.contains(&access.resource))
+            {
+                alive[pass_id] = true;
+                needed.extend(pass.accesses.iter().map(|access| access.resource));
+            }
+        }
+
+        alive
+    }
+
+    fn sort_passes(&mut self, alive: &[bool]) {
+        // Dependencies follow declaration order: reads wait for the last write,
+        // writes wait for the last write and every read since then.
+        let mut dependents: Vec<Vec<PassID>> = vec![Vec::new(); self.passes.len()];
+        let mut dependency_counts = vec![0usize; self.passes.len()];
+        self.dependencies = vec![Vec::new(); self.passes.len()];
+        let mut last_writers: HashMap<GraphResource, PassID> = HashMap::new();
+        let mut readers: HashMap<GraphResource, Vec<PassID>> = HashMap::new();
+        for (pass_id, pass) in self.passes.iter().enumerate() {
+            if !alive[pass_id] {
+                continue;
+            }
+
+            let mut dependencies = Vec::new();
+            for access in &pass.accesses {
+                dependencies.extend(last_writers.get(&access.resource));
+                if access.write {
+                    dependencies.extend(readers.remove(&access.resource).unwrap_or_default());
+                    last_writers.insert(access.resource, pass_id);
+                } else {
+                    readers.entry(access.resource).or_default().push(pass_id);
+                }
+            }
+
+            dependencies.sort_unstable();
+            dependencies.dedup();
+            dependencies.retain(|&dependency| dependency != pass_id);
+            dependency_counts[pass_id] = dependencies.len();
+            for &dependency in &dependencies {
+                dependents[dependency].push(pass_id);
+            }
+            self.dependencies[pass_id] = dependencies;
+        }
+
+        // Kahn's algorithm, preferring passes on the queue of the previous pass
+        // so fewer batches (and semaphore waits) are needed.
+        let mut ready: Vec<PassID> = (0..self.passes.len())
+            .filter(|&pass_id| alive[pass_id] && dependency_counts[pass_id] == 0)
+            .collect();
+        let mut current_type = None;
+        while !ready.is_empty() {
+            let position = ready
+                .iter()
+                .position(|&pass_id| Some(self.passes[pass_id].command_type) == current_type)
+                .unwrap_or(0);
+            let pass_id = ready.remove(position);
+            current_type = Some(self.passes[pass_id].command_type);
+            self.order.push(pass_id);
+
+            for &dependent in &dependents[pass_id] {
+                dependency_counts[dependent] -= 1;
+                if dependency_counts[dependent] == 0 {
+                    let index = ready.partition_point(|&other| other < dependent);
+                    ready.insert(index, dependent);
+                }
+            }
+        }
+    }
+
+    fn build_batches(&mut self) {
+        for &pass_id in &self.order {
+            let command_type = self.passes[pass_id].command_type;
+            match self.batches.last_mut() {
+                Some(batch) if batch.command_type == command_type => batch.passes.push(pass_id),
+                _ => self.batches.push(PassBatch {
+                    command_type,
+                    passes: vec![pass_id],
+                    waits: Vec::new(),
+                }),
+            }
+        }
+
+        // Dependencies between passes, and queue family ownership transfers between
+        // two reads, become semaphore waits when they cross queues.
+        let mut pass_batches = vec![0; self.passes.len()];
+        for (batch_index, batch) in self.batches.iter().enumerate() {
+            batch
+                .passes
+                .iter()
+                .for_each(|&pass_id| pass_batches[pass_id] = batch_index);
+        }
+        let mut edges = Vec::new();
+        let mut last_batches: HashMap<GraphResource, usize> = HashMap::new();
+        for &pass_id in &self.order {
+            let batch_index = pass_batches[pass_id];
+            edges.extend(
+                self.dependencies[pass_id]
+                    .iter()
+                    .map(|&dependency| (batch_index, pass_batches[dependency])),
+            );
+            for access in &self.passes[pass_id].accesses {
+                if let Some(last_batch) = last_batches.insert(access.resource, batch_index) {
+                    edges.push((batch_index, last_batch));
+                }
+            }
+        }
+
+        for (batch_index, dependency) in edges {
+            self.add_batch_wait(batch_index, dependency);
+        }
+    }
+
+    fn add_batch_wait(&mut self, batch_index: usize, dependency: usize) {
+        let command_type = self.batches[dependency].command_type;
+        let batch = &mut self.batches[batch_index];
+        if batch.command_type != command_type && !batch.waits.contains(&dependency) {
+            batch.waits.push(dependency);
+        }
+    }
+
+    // Batch that runs the pass at `position` in `order`
+    fn batch_at(&self, mut position: usize) -> usize {
+        for (batch_index, batch) in self.batches.iter().enumerate() {
+            if position < batch.passes.len() {
+                return batch_index;
+            }
+            position -= batch.passes.len();
+        }
+
+        unreachable!("Position is past the execution order")
+    }
+
+    // Computes transient lifetimes and places them in heaps, no memory is touched
+    fn alias_transients(&mut self) {
+        for (position, &pass_id) in self.order.iter().enumerate() {
+            for access in &self.passes[pass_id].accesses {
+                let Some(transient) = self
+                    .transients
+                    .iter_mut()
+                    .find(|transient| transient.resource == access.resource)
+                else {
+                    continue;
+                };
+
+                transient.lifetime = Some(match transient.lifetime {
+                    Some((first, _)) => (first, position),
+                    None => (position, position),
+                });
+            }
+        }
+
+        // Largest resources are placed first, each one at the lowest offset that
+        // doesn't overlap a resource of the same heap that is alive at the same time.
+        let mut placement_order: Vec<usize> = (0..self.transients.len())
+            .filter(|&index| self.transients[index].lifetime.is_some())
+            .collect();
+        placement_order
+            .sort_by_key(|&index| std::cmp::Reverse(self.transients[index].requirements.size));
+
+        let mut placed: Vec<usize> = Vec::new();
+        for index in placement_order {
+            let transient = &self.transients[index];
+            let is_image = matches!(transient.resource, GraphResource::Image(_));
+            let requirements = transient.requirements;
+            let heap = match self.heaps.iter().position(|heap| {
+                heap.memory_type_bits == requirements.memory_type_bits && heap.is_image == is_image
+            }) {
+                Some(heap) => heap,
+                None => {
+                    self.heaps.push(TransientHeap {
+                        memory_type_bits: requirements.memory_type_bits,
+                        is_image,
+                        size: 0,
+                        alignment: 1,
+                    });
+                    self.heaps.len() - 1
+                }
+            };
+
+            let (first, last) = transient.lifetime.unwrap();
+            let conflicts: Vec<(u64, u64)> = placed
+                .iter()
+                .map(|&other| &self.transients[other])
+                .filter(|other| other.heap == heap)
+                .filter(|other| {
+                    let (other_first, other_last) = other.lifetime.unwrap();
+                    other_first <= last && first <= other_last
+                })
+                .map(|other| (other.offset, other.offset + other.requirements.size))
+                .collect();
+
+            let align = |offset: u64| offset.next_multiple_of(requirements.alignment);
+            let offset = std::iter::once(0)
+                .chain(conflicts.iter().map(|&(_, end)| align(end)))
+                .filter(|&offset| {
+                    conflicts
+                        .iter()
+                        .all(|&(start, end)| offset + requirements.size <= start || offset >= end)
+                })
+                .min()
+                .unwrap();
+
+            let heap_info = &mut self.heaps[heap];
+            heap_info.size = heap_info.size.max(offset + requirements.size);
+            heap_info.alignment = heap_info.alignment.max(requirements.alignment);
+
+            let transient = &mut self.transients[index];
+            transient.heap = heap;
+            transient.offset = offset;
+            placed.push(index);
+        }
+
+        // Earlier occupants of the same memory, their last access has to finish
+        // before the first access of the new resource.
+        for index in 0..self.transients.len() {
+            let transient = &self.transients[index];
+            let Some((first, _)) = transient.lifetime else {
+                continue;
+            };
+
+            let (start, end) = (
+                transient.offset,
+                transient.offset + transient.requirements.size,
+            );
+            let aliases = self
+                .transients
+                .iter()
+                .filter(|other| {
+                    other.heap == transient.heap && other.resource != transient.resource
+                })
+                .filter(|other| {
+                    other
+                        .lifetime
+                        .is_some_and(|(_, other_last)| other_last < first)
+                })
+                .filter(|other| {
+                    other.offset < end && start < other.offset + other.requirements.size
+                })
+                .map(|other| other.resource)
+                .collect();
+            self.transients[index].aliases = aliases;
+        }
+
+        // Reusing memory across queues needs the earlier occupants to be done
+        let mut edges = Vec::new();
+        for transient in &self.transients {
+            let Some((first, _)) = transient.lifetime else {
+                continue;
+            };
+            edges.extend(
+                self.transients
+                    .iter()
+                    .filter(|other| transient.aliases.contains(&other.resource))
+                    .filter_map(|other| other.lifetime)
+                    .map(|(_, other_last)| (self.batch_at(first), self.batch_at(other_last))),
+            );
+        }
+        for (batch_index, dependency) in edges {
+            self.add_batch_wait(batch_index, dependency);
+        }
+    }
+
+    // Allocates every heap and binds the transients placed in it
+    fn bind_transients(&mut self, device: &mut Device) -> Result<(), vk::Result> {
+        let frame = &mut self.frames[self.frame_index];
+        for (heap_index, heap) in self.heaps.iter().enumerate() {
+            let allocation = device.allocate_memory(
+                "render graph transients",
+                AllocationKind::RenderGraphHeap,
+                vk::MemoryRequirements {
+                    size: heap.size,
+                    alignment: heap.alignment,
+                    memory_type_bits: heap.memory_type_bits,
+                },
+                MemoryLocation::GpuOnly,
+                !heap.is_image,
+            );
+            // Tracked by the frame before binding so a failed bind doesn't leak it
+            let memory = unsafe { allocation.memory() };
+            let heap_offset = allocation.offset();
+            frame.heaps.push(allocation);
+
+            for transient in self
+                .transients
+                .iter()
+                .filter(|transient| transient.lifetime.is_some() && transient.heap == heap_index)
+            {
+                let offset = heap_offset + transient.offset;
+                match transient.resource {
+                    GraphResource::Image(image_id) => unsafe {
+                        device.handle.bind_image_memory(
+                            device.image_at(image_id).handle,
+                            memory,
+                            offset,
+                        )?
+                    },
+                    GraphResource::Buffer(buffer_id) => {
+                        let buffer = device.buffer_at(buffer_id).handle;
+                        unsafe { device.handle.bind_buffer_memory(buffer, memory, offset)? };
+
+                        if transient
+                            .buffer_usage
+                            .contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+                        {
+                            let bda_info = vk::BufferDeviceAddressInfo::default().buffer(buffer);
+                            let device_address =
+                                unsafe { device.handle.get_buffer_device_address(&bda_info) };
+                            device.buffers.get_mut(buffer_id).unwrap().device_address =
+                                device_address;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Resources that used the transient's memory before it, see `alias_transients`
+    fn alias_state(&self, device: &Device, resource: GraphResource) -> Option<ResourceState> {
+        let transient = self
+            .transients
+            .iter()
+            .find(|transient| transient.resource == resource)?;
+        let mut state = ResourceState::UNDEFINED;
+        let mut merge = |alias_state: &ResourceState| {
+            state.stage_mask |= alias_state.stage_mask;
+            state.access_mask |= alias_state.access_mask;
+        };
+        for &alias in &transient.aliases {
+            // Every mip and layer of an image alias may have been accessed differently
+            match alias {
+                GraphResource::Image(image_id) => device
+                    .image_at(image_id)
+                    .states
+                    .borrow()
+                    .iter()
+                    .for_each(&mut merge),
+                GraphResource::Buffer(buffer_id) => merge(&device.buffer_at(buffer_id).state.get()),
+            }
+        }
+
+        Some(state)
+    }
+
+    pub fn execute(
+        &mut self,
+        device: &mut Device,
+        wait_sema_infos: &[vk::SemaphoreSubmitInfo],
+        signal_sema_infos: &[vk::SemaphoreSubmitInfo],
+    ) -> Result<(), vk::Result> {
+        assert!(
+            self.compiled,
+            "Render graph must be compiled before execution"
+        );
+        assert!(!self.executed, "Render graph is already executed");
+        self.executed = true;
+
+        if self.batches.is_empty() {
+            device.queue_submit(
+                CommandType::Graphics,
+                &[],
+                &[],
+                wait_sema_infos,
+                signal_sema_infos,
+            )?;
+            return Ok(());
+        }
+
+        // Every batch gets its own command list from this frame's allocators
+        let frame = &mut self.frames[self.frame_index];
+        let mut list_counts = [0usize; 3];
+        for batch in &self.batches {
+            let type_index = batch.command_type as usize;
+            let command_allocator = frame.command_allocators[type_index].get_or_insert_with(|| {
+                device
+                    .create_command_allocator(
+                        batch.command_type,
+                        vk::CommandPoolCreateFlags::empty(),
+                    )
+                    .expect("Failed to create render graph command allocator")
+            });
+            if frame.command_lists[type_index].len() == list_counts[type_index] {
+                let command_list = device.create_command_list(command_allocator)?;
+                frame.command_lists[type_index].push(command_list);
+            }
+            list_counts[type_index] += 1;
+        }
+
+        let callbacks = self
+            .passes
+            .iter_mut()
+            .map(|pass| pass.callback.take())
+            .collect();
+        // Taken out of the frame while recording, passes get them mutably
+        let mut frame_lists = std::mem::take(&mut self.frames[self.frame_index].command_lists);
+        let mut lists_by_type = frame_lists.each_mut().map(|lists| lists.iter_mut());
+        let mut command_lists: Vec<&mut CommandList> = self
+            .batches
+            .iter()
+            .map(|batch| lists_by_type[batch.command_type as usize].next().unwrap())
+            .collect();
+        command_lists
+            .iter()
+            .for_each(|command_list| device.begin_command_list(command_list));
+
+        self.record_passes(device, &mut command_lists, callbacks);
+
+        command_lists
+            .iter()
+            .for_each(|command_list| device.end_command_list(command_list));
+        drop(command_lists);
+        self.frames[self.frame_index].command_lists = frame_lists;
+        let frame = &self.frames[self.frame_index];
+        let mut lists_by_type = frame.command_lists.each_ref().map(|lists| lists.iter());
+        let command_lists: Vec<&CommandList> = self
+            .batches
+            .iter()
+            .map(|batch| lists_by_type[batch.command_type as usize].next().unwrap())
+            .collect();
+
+        // External semaphores can only be waited once, with several queues they are
+        // waited by an empty submit the first batch of every queue waits for.
+        let uses_multiple_queues = self
+            .batches
+            .iter()
+            .any(|batch| batch.command_type != self.batches[0].command_type);
+        let external_token = match !wait_sema_infos.is_empty() && uses_multiple_queues {
+            true => Some(device.queue_submit(
+                self.batches[0].command_type,
+                &[],
+                &[],
+                wait_sema_infos,
+                &[],
+            )?),
+            false => None,
+        };
+
+        let mut batch_tokens: Vec<SubmitToken> = Vec::with_capacity(self.batches.len());
+        for (batch_index, batch) in self.batches.iter().enumerate() {
+            let mut waits = batch.waits.clone();
+            let is_last = batch_index == self.batches.len() - 1;
+            if is_last {
+                // The last batch joins every queue so the signals cover the whole graph
+                waits.extend(0..batch_index);
+            }
+            // Only the latest wait of every queue matters
+            waits.sort_unstable_by(|lhs, rhs| rhs.cmp(lhs));
+            let mut wait_tokens: Vec<SubmitToken> = Vec::new();
+            for wait in waits {
+                let token = batch_tokens[wait];
+                if !wait_tokens
+                    .iter()
+                    .any(|wait_token| wait_token.command_type == token.command_type)
+                {
+                    wait_tokens.push(token);
+                }
+            }
+
+            let is_first_on_queue = !self.batches[..batch_index]
+                .iter()
+                .any(|other| other.command_type == batch.command_type);
+            if is_first_on_queue {
+                wait_tokens.extend(external_token);
+            }
+            let batch_wait_infos = match batch_index == 0 && external_token.is_none() {
+                true => wait_sema_infos,
+                false => &[],
+            };
+            let batch_signal_infos = match is_last {
+                true => signal_sema_infos,
+                false => &[],
+            };
+
+            let token = device.queue_submit(
+                batch.command_type,
+                &[command_lists[batch_index]],
+                &wait_tokens,
+                batch_wait_infos,
+                batch_signal_infos,
+            )?;
+            batch_tokens.push(token);
+        }
+
+        Ok(())
+    }
+
+    fn record_passes(
+        &self,
+        device: &Device,
+        command_lists: &mut [&mut CommandList],
+        mut callbacks: Vec<Option<PassCallback>>,
+    ) {
+        let family_index = |command_type: CommandType| device.queue_at(command_type).family_index;
+        let first_uses: HashMap<GraphResource, usize> = self
+            .transients
+            .iter()
+            .filter_map(|transient| Some((transient.resource, transient.lifetime?.0)))
+            .collect();
+        // Batch of the last access of every resource
+        let mut owners: HashMap<GraphResource, usize> = HashMap::new();
+
+        let mut position = 0;
+        for (batch_index, batch) in self.batches.iter().enumerate() {
+            for &pass_id in &batch.passes {
+                let command_list = &*command_lists[batch_index];
+                let mut image_barriers = Vec::new();
+                let mut buffer_barriers = Vec::new();
+                let mut releases: HashMap<usize, (Vec<_>, Vec<_>)> = HashMap::new();
+
+                for access in &self.passes[pass_id].accesses {
+                    let resource = access.resource;
+                    if first_uses.get(&resource) == Some(&position) {
+                        let initial_state = self.alias_state(device, resource).unwrap();
+                        match resource {
+                            GraphResource::Image(image_id) => device
+                                .image_at(image_id)
+                                .states
+                                .borrow_mut()
+                                .fill(initial_state),
+                            GraphResource::Buffer(buffer_id) => {
+                                device.buffer_at(buffer_id).state.set(initial_state)
+                            }
+                        }
+                    }
+
+                    // Exclusive resources need a release on the previous queue
+                    // and a matching acquire on the new one.
+                    let transfer = owners
+                        .insert(resource, batch_index)
+                        .map(|owner| {
+                            (
+                                owner,
+                                family_index(self.batches[owner].command_type),
+                                family_index(batch.command_type),
+                            )
+                        })
+                        .filter(|&(_, src_family, dst_family)| src_family != dst_family);
+
+                    match resource {
+                        GraphResource::Image(image_id) => {
+                            let image = device.image_at(image_id);
+                            let mut changes = command_list.track_image_state(
+                                image,
+                                image.full_range(),
+                                access.state,
+                            );
+                            if transfer.is_some() && changes.is_empty() {
+                                changes.push((image.full_range(), access.state));
+                            }
+
+                            for (range, old_state) in changes {
+                                let barrier =
+                                    image_state_barrier(image, range, old_state, access.state);
+                                match transfer {
+                                    Some((owner, src_family, dst_family)) => {
+                                        let barrier = barrier
+                                            .src_queue_family_index(src_family)
+                                            .dst_queue_family_index(dst_family);
+                                        releases.entry(owner).or_default().0.push(
+                                            barrier
+                                                .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+                                                .dst_access_mask(vk::AccessFlags2::NONE),
+                                        );
+                                        image_barriers.push(
+                                            barrier
+                                                .src_stage_mask(vk::PipelineStageFlags2::NONE)
+                                                .src_access_mask(vk::AccessFlags2::NONE),
+                                        );
+                                    }
+                                    None => image_barriers.push(barrier),
+                                }
+                            }
+                        }
+                        GraphResource::Buffer(buffer_id) => {
+                            let buffer = device.buffer_at(buffer_id);
+                            let old_state = command_list
+                                .track_buffer_state(buffer, access.state)
+                                .or(transfer.map(|_| access.state));
+                            let Some(old_state) = old_state else {
+                                continue;
+                            };
+
+                            let barrier = buffer_state_barrier(buffer, old_state, access.state);
+                            match transfer {
+                                Some((owner, src_family, dst_family)) => {
+                                    let barrier = barrier
+                                        .src_queue_family_index(src_family)
+                                        .dst_queue_family_index(dst_family);
+                                    releases.entry(owner).or_default().1.push(
+                                        barrier
+                                            .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+                                            .dst_access_mask(vk::AccessFlags2::NONE),
+                                    );
+                                    buffer_barriers.push(
+                                        barrier
+                                            .src_stage_mask(vk::PipelineStageFlags2::NONE)
+                                            .src_access_mask(vk::AccessFlags2::NONE),
+                                    );
+                                }
+                                None => buffer_barriers.push(barrier),
+                            }
+                        }
+                    }
+                }
+
+                command_list.begin_scope(&self.passes[pass_id].name);
+                // Previous batches are still open, releases go at their end
+                for (owner, (release_image_barriers, release_buffer_barriers)) in releases {
+                    command_lists[owner]
+                        .pipeline_barrier(&release_image_barriers, &release_buffer_barriers);
+                }
+                command_list.pipeline_barrier(&image_barriers, &buffer_barriers);
+
+                if let Some(callback) = callbacks[pass_id].take() {
+                    callback(device, command_lists[batch_index]);
+                }
+                command_lists[batch_index].end_scope();
+                position += 1;
+            }
+        }
+
+        for output in &self.outputs {
+            let (Some(final_state), Some(&owner)) =
+                (output.final_state, owners.get(&output.resource))
+            else {
+                continue;
+            };
+
+            let command_list = &command_lists[owner];
+            match output.resource {
+                GraphResource::Image(image_id) => {
+                    command_list.transition(device.image_at(image_id), final_state)
+                }
+                GraphResource::Buffer(buffer_id) => {
+                    command_list.transition_buffer(device.buffer_at(buffer_id), final_state)
+                }
+            }
+        }
+    }
+
+    // Graphviz DOT of the compiled graph, culled passes are drawn dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from(
+            "digraph RenderGraph {\n    rankdir=LR;\n    node [fontname=\"monospace\"];\n",
+        );
+        let resource_node = |resource: GraphResource| match resource {
+            GraphResource::Image(image_id) => format!("image_{image_id}"),
+            GraphResource::Buffer(buffer_id) => format!("buffer_{buffer_id}"),
+        };
+
+        for (batch_index, batch) in self.batches.iter().enumerate() {
+            writeln!(
+                dot,
+                "    subgraph cluster_{batch_index} {{\n        label=\"batch {batch_index} ({:?})\";",
+                batch.command_type
+            )
+            .unwrap();
+            for &pass_id in &batch.passes {
+                writeln!(
+                    dot,
+                    "        pass_{pass_id} [shape=box, label=\"{}\"];",
+                    self.passes[pass_id].name
+                )
+                .unwrap();
+            }
+            dot.push_str("    }\n");
+        }
+
+        let mut resources = Vec::new();
+        for (pass_id, pass) in self.passes.iter().enumerate() {
+            if !self.order.contains(&pass_id) {
+                writeln!(
+                    dot,
+                    "    pass_{pass_id} [shape=box, style=dashed, color=gray, label=\"{} (culled)\"];",
+                    pass.name
+                )
+                .unwrap();
+            }
+
+            for access in &pass.accesses {
+                if !resources.contains(&access.resource) {
+                    resources.push(access.resource);
+                }
+
+                let node = resource_node(access.resource);
+                let label = match access.resource {
+                    GraphResource::Image(_) => format!("{:?}", access.state.layout),
+                    GraphResource::Buffer(_) => format!("{:?}", access.state.access_mask),
+                };
+                if access.write {
+                    writeln!(dot, "    pass_{pass_id} -> {node} [label=\"{label}\"];").unwrap();
+                } else {
+                    writeln!(dot, "    {node} -> pass_{pass_id} [label=\"{label}\"];").unwrap();
+                }
+            }
+        }
+
+        for resource in resources {
+            let node = resource_node(resource);
+            let is_output = self
+                .outputs
+                .iter()
+                .any(|output| output.resource == resource);
+            let peripheries = if is_output { 2 } else { 1 };
+            let label = match self
+                .transients
+                .iter()
+                .find(|transient| transient.resource == resource)
+            {
+                Some(transient) if transient.lifetime.is_some() => format!(
+                    "{}\\nheap {} @ {:#x}, {:#x} bytes",
+                    transient.name, transient.heap, transient.offset, transient.requirements.size
+                ),
+                Some(transient) => format!("{}\\nunused", transient.name),
+                None => node.clone(),
+            };
+            writeln!(
+                dot,
+                "    {node} [shape=ellipse, peripheries={peripheries}, label=\"{label}\"];"
+            )
+            .unwrap();
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Everything `compile` does before memory is bound to transients
+    fn schedule(graph: &mut RenderGraph) {
+        let alive = graph.cull_passes();
+        graph.sort_passes(&alive);
+        graph.build_batches();
+        graph.alias_transients();
+    }
+
+    // Stands in for `create_image`/`create_buffer`, which need a device
+    fn add_transient(graph: &mut RenderGraph, resource: GraphResource, size: u64, alignment: u64) {
+        graph.transients.push(TransientResource {
+            name: format!("{resource:?}"),
+            resource,
+            requirements: vk::MemoryRequirements {
+                size,
+                alignment,
+                memory_type_bits: 0b11,
+            },
+            buffer_usage: vk::BufferUsageFlags::empty(),
+            lifetime: None,
+            heap: 0,
+            offset: 0,
+            aliases: Vec::new(),
+        });
+    }
+
+    fn transient(graph: &RenderGraph, resource: GraphResource) -> &TransientResource {
+        graph
+            .transients
+            .iter()
+            .find(|transient| transient.resource == resource)
+            .unwrap()
+    }
+
+    fn batches(graph: &RenderGraph) -> Vec<(CommandType, Vec<PassID>, Vec<usize>)> {
+        graph
+            .batches
+            .iter()
+            .map(|batch| {
+                (
+                    batch.command_type,
+                    batch.passes.clone(),
+                    batch.waits.clone(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn culls_passes_without_outputs() {
+        let mut graph = RenderGraph::new(1);
+        graph
+            .add_pass("depth", CommandType::Graphics)
+            .write_image(1, ResourceState::DEPTH_ATTACHMENT);
+        graph
+            .add_pass("unused", CommandType::Compute)
+            .write_buffer(2, ResourceState::SHADER_WRITE);
+        graph
+            .add_pass("color", CommandType::Graphics)
+            .read_image(1, ResourceState::SHADER_READ)
+            .write_image(3, ResourceState::COLOR_ATTACHMENT);
+        graph
+            .add_pass("debug", CommandType::Graphics)
+            .read_image(3, ResourceState::SHADER_READ)
+            .write_image(4, ResourceState::COLOR_ATTACHMENT);
+        graph.output_image(3, Some(ResourceState::PRESENT));
+        schedule(&mut graph);
+
+        assert_eq!(graph.order, [0, 2]);
+        assert_eq!(
+            batches(&graph),
+            [(CommandType::Graphics, vec![0, 2], Vec::new())]
+        );
+        assert!(graph.to_dot().contains("unused (culled)"));
+    }
+
+    #[test]
+    fn sorts_passes_by_queue() {
+        let mut graph = RenderGraph::new(1);
+        graph
+            .add_pass("gbuffer", CommandType::Graphics)
+            .write_image(1, ResourceState::COLOR_ATTACHMENT);
+        graph
+            .add_pass("cull", CommandType::Compute)
+            .write_buffer(2, ResourceState::SHADER_WRITE);
+        graph
+            .add_pass("lighting", CommandType::Graphics)
+            .read_image(1, ResourceState::SHADER_READ)
+            .write_image(3, ResourceState::COLOR_ATTACHMENT);
+        graph
+            .add_pass("compact", CommandType::Compute)
+            .read_buffer(2, ResourceState::SHADER_READ)
+            .write_buffer(4, ResourceState::SHADER_WRITE);
+        graph
+            .add_pass("composite", CommandType::Graphics)
+            .read_image(3, ResourceState::SHADER_READ)
+            .read_buffer(4, ResourceState::SHADER_READ)
+            .write_image(5, ResourceState::COLOR_ATTACHMENT);
+        graph.output_image(5, None);
+        schedule(&mut graph);
+
+        // Graphics passes are kept together until the compute results are needed
+        assert_eq!(graph.order, [0, 2, 1, 3, 4]);
+        assert_eq!(
+            batches(&graph),
+            [
+                (CommandType::Graphics, vec![0, 2], Vec::new()),
+                (CommandType::Compute, vec![1, 3], Vec::new()),
+                (CommandType::Graphics, vec![4], vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn orders_writes_after_reads() {
+        let mut graph = RenderGraph::new(1);
+        graph
+            .add_pass("clear", CommandType::Graphics)
+            .write_image(1, ResourceState::COLOR_ATTACHMENT);
+        graph
+            .add_pass("histogram", CommandType::Compute)
+            .read_image(1, ResourceState::SHADER_READ)
+            .write_buffer(2, ResourceState::SHADER_WRITE);
+        graph
+            .add_pass("overlay", CommandType::Graphics)
+            .write_image(1, ResourceState::COLOR_ATTACHMENT);
+        graph.output_image(1, None);
+        graph.output_buffer(2, None);
+        schedule(&mut graph);
+
+        assert_eq!(graph.dependencies[2], [0, 1]);
+        assert_eq!(graph.order, [0, 1, 2]);
+        assert_eq!(
+            batches(&graph),
+            [
+                (CommandType::Graphics, vec![0], Vec::new()),
+                (CommandType::Compute, vec![1], vec![0]),
+                (CommandType::Graphics, vec![2], vec![1]),
+            ]
+        );
+    }
+
+    #[test]
+    fn waits_for_ownership_transfers() {
+        let mut graph = RenderGraph::new(1);
+        graph
+            .add_pass("upload", CommandType::Transfer)
+            .write_image(1, ResourceState::TRANSFER_DST);
+        graph
+            .add_pass("blur", CommandType::Compute)
+            .read_image(1, ResourceState::SHADER_READ)
+            .write_buffer(2, ResourceState::SHADER_WRITE);
+        graph
+            .add_pass("draw", CommandType::Graphics)
+            .read_image(1, ResourceState::SHADER_READ)
+            .write_image(3, ResourceState::COLOR_ATTACHMENT);
+        graph.output_buffer(2, None);
+        graph.output_image(3, None);
+        schedule(&mut graph);
+
+        // The two reads don't depend on each other, but the image is released by
+        // the compute queue before the graphics queue can use it
+        assert_eq!(graph.dependencies[2], [0]);
+        assert_eq!(
+            batches(&graph),
+            [
+                (CommandType::Transfer, vec![0], Vec::new()),
+                (CommandType::Compute, vec![1], vec![0]),
+                (CommandType::Graphics, vec![2], vec![0, 1]),
+            ]
+        );
+        assert_eq!(
+            (0..3)
+                .map(|position| graph.batch_at(position))
+                .collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn aliases_transients_with_disjoint_lifetimes() {
+        let mut graph = RenderGraph::new(1);
+        let [depth, bloom, color, unused] = [1, 2, 3, 4].map(GraphResource::Image);
+        let counts = GraphResource::Buffer(5);
+        add_transient(&mut graph, depth, 1024, 256);
+        add_transient(&mut graph, bloom, 512, 768);
+        add_transient(&mut graph, color, 1024, 256);
+        add_transient(&mut graph, unused, 4096, 256);
+        add_transient(&mut graph, counts, 100, 64);
+        graph
+            .add_pass("depth", CommandType::Graphics)
+            .write_image(1, ResourceState::DEPTH_ATTACHMENT)
+            .write_buffer(5, ResourceState::SHADER_WRITE);
+        graph
+            .add_pass("bloom", CommandType::Graphics)
+            .read_image(1, ResourceState::SHADER_READ)
+            .write_image(2, ResourceState::COLOR_ATTACHMENT);
+        graph
+            .add_pass("color", CommandType::Graphics)
+            .read_image(2, ResourceState::SHADER_READ)
+            .read_buffer(5, ResourceState::SHADER_READ)
+            .write_image(3, ResourceState::COLOR_ATTACHMENT);
+        graph.output_image(3, None);
+        schedule(&mut graph);
+
+        let lifetimes: Vec<_> = [depth, bloom, color, unused, counts]
+            .map(|resource| transient(&graph, resource).lifetime)
+            .into();
+        assert_eq!(
+            lifetimes,
+            [Some((0, 1)), Some((1, 2)), Some((2, 2)), None, Some((0, 2))]
+        );
+
+        // `color` reuses the memory of `depth`, `bloom` overlaps both and goes
+        // after them at its own alignment
+        let placements: Vec<_> = [depth, bloom, color, counts]
+            .map(|resource| {
+                let transient = transient(&graph, resource);
+                (transient.heap, transient.offset)
+            })
+            .into();
+        assert_eq!(placements, [(0, 0), (0, 1536), (0, 0), (1, 0)]);
+        assert_eq!(graph.heaps.len(), 2);
+        assert_eq!((graph.heaps[0].size, graph.heaps[0].alignment), (2048, 768));
+        assert!(graph.heaps[0].is_image && !graph.heaps[1].is_image);
+
+        assert_eq!(transient(&graph, color).aliases, [depth]);
+        assert!(transient(&graph, bloom).aliases.is_empty());
+        assert!(transient(&graph, depth).aliases.is_empty());
+    }
+
+    #[test]
+    fn waits_for_aliases_on_other_queues() {
+        let mut graph = RenderGraph::new(1);
+        let [history, scratch] = [1, 2].map(GraphResource::Image);
+        add_transient(&mut graph, history, 256, 256);
+        add_transient(&mut graph, scratch, 256, 256);
+        graph
+            .add_pass("history", CommandType::Graphics)
+            .write_image(1, ResourceState::COLOR_ATTACHMENT)
+            .write_image(3, ResourceState::COLOR_ATTACHMENT);
+        graph
+            .add_pass("resolve", CommandType::Graphics)
+            .read_image(1, ResourceState::SHADER_READ)
+            .write_image(3, ResourceState::COLOR_ATTACHMENT);
+        graph
+            .add_pass("scratch", CommandType::Compute)
+            .write_image(2, ResourceState::SHADER_WRITE)
+            .write_buffer(4, ResourceState::SHADER_WRITE);
+        graph.output_image(3, None);
+        graph.output_buffer(4, None);
+        schedule(&mut graph);
+
+        // `scratch` only depends on `history` through their shared memory
+        assert!(graph.dependencies[2].is_empty());
+        assert_eq!(transient(&graph, scratch).offset, 0);
+        assert_eq!(transient(&graph, scratch).aliases, [history]);
+        assert_eq!(
+            batches(&graph),
+            [
+                (CommandType::Graphics, vec![0, 1], Vec::new()),
+                (CommandType::Compute, vec![2], vec![0]),
+            ]
+        );
+    }
+}
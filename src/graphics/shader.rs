@@ -0,0 +1,715 @@
+use ash::vk;
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "shader-compiler")]
+use super::ShaderCompileDesc;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+// Opcodes
+const OP_ENTRY_POINT: u32 = 15;
+const OP_EXECUTION_MODE: u32 = 16;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// Decorations
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+const STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER: u32 = 5349;
+
+// Execution modes
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io {
+        name: String,
+        error: std::io::Error,
+    },
+    InvalidMagic {
+        name: String,
+    },
+    Malformed {
+        name: String,
+        reason: String,
+    },
+    MissingEntryPoint {
+        name: String,
+        entry_point: String,
+    },
+    StageMismatch {
+        name: String,
+        expected: vk::ShaderStageFlags,
+        found: vk::ShaderStageFlags,
+    },
+    PushConstantTooLarge {
+        name: String,
+        size: u32,
+        max_size: u32,
+    },
+    BindingMismatch {
+        name: String,
+        set: u32,
+        binding: u32,
+        reason: String,
+    },
+    Compile {
+        name: String,
+        file: PathBuf,
+        line: u32,
+        message: String,
+    },
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::Io { name, error } => write!(f, "{name}: {error}"),
+            ShaderError::InvalidMagic { name } => write!(f, "{name}: not a SPIR-V module"),
+            ShaderError::Malformed { name, reason } => {
+                write!(f, "{name}: malformed SPIR-V, {reason}")
+            }
+            ShaderError::MissingEntryPoint { name, entry_point } => {
+                write!(f, "{name}: entry point `{entry_point}` not found")
+            }
+            ShaderError::StageMismatch {
+                name,
+                expected,
+                found,
+            } => write!(f, "{name}: expected a {expected:?} shader, got {found:?}"),
+            ShaderError::PushConstantTooLarge {
+                name,
+                size,
+                max_size,
+            } => write!(
+                f,
+                "{name}: push constant block is {size} bytes, pipeline layout allows {max_size}"
+            ),
+            ShaderError::BindingMismatch {
+                name,
+                set,
+                binding,
+                reason,
+            } => write!(f, "{name}: set {set} binding {binding}, {reason}"),
+            ShaderError::Compile {
+                name,
+                file,
+                line,
+                message,
+            } => write!(f, "{name}: {}:{line}: {message}", file.display()),
+            ShaderError::Vulkan(result) => write!(f, "{result}"),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+impl From<vk::Result> for ShaderError {
+    fn from(value: vk::Result) -> Self {
+        ShaderError::Vulkan(value)
+    }
+}
+
+pub enum ShaderSource<'a> {
+    Bytes(&'a [u8]),
+    Path(&'a Path),
+    #[cfg(feature = "shader-compiler")]
+    Compile(&'a ShaderCompileDesc),
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderEntryPoint {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+    pub workgroup_size: Option<[u32; 3]>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShaderBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+}
+
+#[derive(Clone, Default)]
+pub struct Shader {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+    pub entry_point: String,
+    pub workgroup_size: Option<[u32; 3]>,
+    // Reflected from the module, shared by all entry points
+    pub entry_points: Vec<ShaderEntryPoint>,
+    pub push_constant_size: u32,
+    pub bindings: Vec<ShaderBinding>,
+    // Set when compiled from source, `dependencies` are the canonical paths
+    // of the source file and everything it includes
+    #[cfg(feature = "shader-compiler")]
+    pub source: Option<ShaderCompileDesc>,
+    #[cfg(feature = "shader-compiler")]
+    pub dependencies: Vec<PathBuf>,
+
+    pub code: Vec<u32>,
+}
+
+impl Shader {
+    pub fn from_spirv(name: &str, bytes: &[u8]) -> Result<Self, ShaderError> {
+        if bytes.len() < 20 || !bytes.len().is_multiple_of(4) {
+            return Err(ShaderError::Malformed {
+                name: name.to_owned(),
+                reason: format!("invalid module size {}", bytes.len()),
+            });
+        }
+
+        let code: Vec<u32> = bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+
+        Self::from_words(name, code)
+    }
+
+    pub fn from_words(name: &str, mut code: Vec<u32>) -> Result<Self, ShaderError> {
+        if code.len() < 5 {
+            return Err(ShaderError::Malformed {
+                name: name.to_owned(),
+                reason: format!("invalid module size {}", code.len() * 4),
+            });
+        }
+
+        if code[0] == SPIRV_MAGIC.swap_bytes() {
+            code.iter_mut().for_each(|word| *word = word.swap_bytes());
+        }
+        if code[0] != SPIRV_MAGIC {
+            return Err(ShaderError::InvalidMagic {
+                name: name.to_owned(),
+            });
+        }
+
+        let reflection = Reflection::parse(name, &code)?;
+        let entry_point = reflection.entry_points.first().cloned().ok_or_else(|| {
+            ShaderError::MissingEntryPoint {
+                name: name.to_owned(),
+                entry_point: String::from("<any>"),
+            }
+        })?;
+
+        Ok(Self {
+            name: name.to_owned(),
+            stage: entry_point.stage,
+            entry_point: entry_point.name,
+            workgroup_size: entry_point.workgroup_size,
+            entry_points: reflection.entry_points,
+            push_constant_size: reflection.push_constant_size,
+            bindings: reflection.bindings,
+            #[cfg(feature = "shader-compiler")]
+            source: None,
+            #[cfg(feature = "shader-compiler")]
+            dependencies: Vec::new(),
+            code,
+        })
+    }
+
+    pub fn from_path(name: &str, path: &Path) -> Result<Self, ShaderError> {
+        let bytes = std::fs::read(path).map_err(|error| ShaderError::Io {
+            name: name.to_owned(),
+            error,
+        })?;
+
+        Self::from_spirv(name, &bytes)
+    }
+
+    // Selects another entry point of the same module
+    pub fn with_entry_point(mut self, entry_point: &str) -> Result<Self, ShaderError> {
+        let found = self
+            .entry_points
+            .iter()
+            .find(|e| e.name == entry_point)
+            .cloned()
+            .ok_or_else(|| ShaderError::MissingEntryPoint {
+                name: self.name.clone(),
+                entry_point: entry_point.to_owned(),
+            })?;
+
+        self.stage = found.stage;
+        self.entry_point = found.name;
+        self.workgroup_size = found.workgroup_size;
+        Ok(self)
+    }
+}
+
+fn execution_model_stage(execution_model: u32) -> Option<vk::ShaderStageFlags> {
+    let stage = match execution_model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        5267 | 5364 => vk::ShaderStageFlags::TASK_EXT,
+        5268 | 5365 => vk::ShaderStageFlags::MESH_EXT,
+        5313 => vk::ShaderStageFlags::RAYGEN_KHR,
+        5314 => vk::ShaderStageFlags::INTERSECTION_KHR,
+        5315 => vk::ShaderStageFlags::ANY_HIT_KHR,
+        5316 => vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+        5317 => vk::ShaderStageFlags::MISS_KHR,
+        5318 => vk::ShaderStageFlags::CALLABLE_KHR,
+        _ => return None,
+    };
+
+    Some(stage)
+}
+
+fn parse_string(words: &[u32]) -> (String, usize) {
+    let mut bytes = Vec::new();
+    for (i, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes() {
+            if byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), i + 1);
+            }
+            bytes.push(byte);
+        }
+    }
+
+    (String::from_utf8_lossy(&bytes).into_owned(), words.len())
+}
+
+enum SpirvType {
+    Scalar(u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Image { sampled: u32, dim: u32 },
+    Sampler,
+    SampledImage,
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Struct(Vec<u32>),
+    Pointer(u32, u32),
+    AccelerationStructure,
+}
+
+#[derive(Default)]
+struct Reflection {
+    entry_points: Vec<ShaderEntryPoint>,
+    push_constant_size: u32,
+    bindings: Vec<ShaderBinding>,
+
+    types: HashMap<u32, SpirvType>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+}
+
+impl Reflection {
+    fn parse(name: &str, code: &[u32]) -> Result<Self, ShaderError> {
+        let malformed = |reason: &str| ShaderError::Malformed {
+            name: name.to_owned(),
+            reason: reason.to_owned(),
+        };
+
+        let mut result = Self::default();
+        let mut entry_point_ids = Vec::new();
+        let mut local_sizes = HashMap::new();
+        let mut variables = Vec::new();
+
+        let mut offset = 5;
+        while offset < code.len() {
+            let word_count = (code[offset] >> 16) as usize;
+            let opcode = code[offset] & 0xffff;
+            if word_count == 0 || offset + word_count > code.len() {
+                return Err(malformed("instruction overruns the module"));
+            }
+
+            let operands = &code[offset + 1..offset + word_count];
+            let operand = |i: usize| {
+                operands
+                    .get(i)
+                    .copied()
+                    .ok_or_else(|| malformed("missing instruction operand"))
+            };
+            match opcode {
+                OP_ENTRY_POINT => {
+                    let name_words = operands
+                        .get(2..)
+                        .filter(|words| !words.is_empty())
+                        .ok_or_else(|| malformed("missing instruction operand"))?;
+                    let (entry_name, _) = parse_string(name_words);
+                    if let Some(stage) = execution_model_stage(operand(0)?) {
+                        entry_point_ids.push((operand(1)?, entry_name, stage));
+                    }
+                }
+                OP_EXECUTION_MODE if operand(1)? == EXECUTION_MODE_LOCAL_SIZE => {
+                    local_sizes.insert(operand(0)?, [operand(2)?, operand(3)?, operand(4)?]);
+                }
+                OP_TYPE_BOOL => {
+                    result.types.insert(operand(0)?, SpirvType::Scalar(4));
+                }
+                OP_TYPE_INT | OP_TYPE_FLOAT => {
+                    result
+                        .types
+                        .insert(operand(0)?, SpirvType::Scalar(operand(1)? / 8));
+                }
+                OP_TYPE_VECTOR => {
+                    result
+                        .types
+                        .insert(operand(0)?, SpirvType::Vector(operand(1)?, operand(2)?));
+                }
+                OP_TYPE_MATRIX => {
+                    result
+                        .types
+                        .insert(operand(0)?, SpirvType::Matrix(operand(1)?, operand(2)?));
+                }
+                OP_TYPE_IMAGE => {
+                    result.types.insert(
+                        operand(0)?,
+                        SpirvType::Image {
+                            dim: operand(2)?,
+                            sampled: operand(6)?,
+                        },
+                    );
+                }
+                OP_TYPE_SAMPLER => {
+                    result.types.insert(operand(0)?, SpirvType::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    result.types.insert(operand(0)?, SpirvType::SampledImage);
+                }
+                OP_TYPE_ARRAY => {
+                    result
+                        .types
+                        .insert(operand(0)?, SpirvType::Array(operand(1)?, operand(2)?));
+                }
+                OP_TYPE_RUNTIME_ARRAY => {
+                    result
+                        .types
+                        .insert(operand(0)?, SpirvType::RuntimeArray(operand(1)?));
+                }
+                OP_TYPE_STRUCT => {
+                    result
+                        .types
+                        .insert(operand(0)?, SpirvType::Struct(operands[1..].to_vec()));
+                }
+                OP_TYPE_POINTER => {
+                    result
+                        .types
+                        .insert(operand(0)?, SpirvType::Pointer(operand(1)?, operand(2)?));
+                }
+                OP_TYPE_ACCELERATION_STRUCTURE => {
+                    result
+                        .types
+                        .insert(operand(0)?, SpirvType::AccelerationStructure);
+                }
+                OP_CONSTANT => {
+                    result.constants.insert(operand(1)?, operand(2)?);
+                }
+                OP_VARIABLE => {
+                    variables.push((operand(0)?, operand(1)?, operand(2)?));
+                }
+                OP_DECORATE => {
+                    let value = operands.get(2).copied().unwrap_or(0);
+                    result.decorations.insert((operand(0)?, operand(1)?), value);
+                }
+                OP_MEMBER_DECORATE => {
+                    let value = operands.get(3).copied().unwrap_or(0);
+                    result
+                        .member_decorations
+                        .insert((operand(0)?, operand(1)?, operand(2)?), value);
+                }
+                _ => {}
+            }
+
+            offset += word_count;
+        }
+
+        result.entry_points = entry_point_ids
+            .into_iter()
+            .map(|(id, name, stage)| ShaderEntryPoint {
+                name,
+                stage,
+                workgroup_size: local_sizes.get(&id).copied(),
+            })
+            .collect();
+
+        for (pointer_type, variable, storage_class) in variables {
+            let Some(&SpirvType::Pointer(_, pointee)) = result.types.get(&pointer_type) else {
+                continue;
+            };
+
+            if storage_class == STORAGE_CLASS_PUSH_CONSTANT {
+                result.push_constant_size =
+                    result.push_constant_size.max(result.type_size(pointee, 0));
+                continue;
+            }
+
+            let set = result
+                .decorations
+                .get(&(variable, DECORATION_DESCRIPTOR_SET));
+            let binding = result.decorations.get(&(variable, DECORATION_BINDING));
+            if let (Some(&set), Some(&binding)) = (set, binding) {
+                result.bindings.push(ShaderBinding {
+                    set,
+                    binding,
+                    descriptor_type: result.descriptor_type(pointee, storage_class),
+                });
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn descriptor_type(&self, type_id: u32, storage_class: u32) -> vk::DescriptorType {
+        match self.types.get(&type_id) {
+            Some(SpirvType::Array(element, _)) | Some(SpirvType::RuntimeArray(element)) => {
+                self.descriptor_type(*element, storage_class)
+            }
+            Some(SpirvType::Sampler) => vk::DescriptorType::SAMPLER,
+            Some(SpirvType::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            // Dim::Buffer
+            Some(SpirvType::Image { dim: 5, sampled }) => match sampled {
+                2 => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                _ => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            },
+            Some(SpirvType::Image { sampled, .. }) => match sampled {
+                2 => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            Some(SpirvType::AccelerationStructure) => {
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR
+            }
+            _ if storage_class == STORAGE_CLASS_STORAGE_BUFFER => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            _ if storage_class == STORAGE_CLASS_UNIFORM
+                && self
+                    .decorations
+                    .contains_key(&(type_id, DECORATION_BUFFER_BLOCK)) =>
+            {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            _ => vk::DescriptorType::UNIFORM_BUFFER,
+        }
+    }
+
+    fn type_size(&self, type_id: u32, depth: u32) -> u32 {
+        // Guards against self referencing structs through pointers
+        if depth > 32 {
+            return 0;
+        }
+
+        match self.types.get(&type_id) {
+            Some(SpirvType::Scalar(size)) => *size,
+            Some(SpirvType::Vector(component, count)) => {
+                self.type_size(*component, depth + 1) * count
+            }
+            Some(SpirvType::Matrix(column, count)) => self.type_size(*column, depth + 1) * count,
+            Some(SpirvType::Array(element, length_id)) => {
+                let length = self.constants.get(length_id).copied().unwrap_or(0);
+                let stride = self
+                    .decorations
+                    .get(&(type_id, DECORATION_ARRAY_STRIDE))
+                    .copied()
+                    .unwrap_or_else(|| self.type_size(*element, depth + 1));
+                stride * length
+            }
+            Some(SpirvType::Struct(members)) => members
+                .iter()
+                .enumerate()
+                .map(|(i, &member)| {
+                    let member_key = (type_id, i as u32, DECORATION_OFFSET);
+                    let offset = self
+                        .member_decorations
+                        .get(&member_key)
+                        .copied()
+                        .unwrap_or(0);
+                    let size = match self.types.get(&member) {
+                        Some(SpirvType::Matrix(_, count)) => self
+                            .member_decorations
+                            .get(&(type_id, i as u32, DECORATION_MATRIX_STRIDE))
+                            .map(|stride| stride * count)
+                            .unwrap_or_else(|| self.type_size(member, depth + 1)),
+                        _ => self.type_size(member, depth + 1),
+                    };
+                    offset + size
+                })
+                .max()
+                .unwrap_or(0),
+            Some(SpirvType::Pointer(STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER, _)) => 8,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    // Null terminated and padded to a whole word
+    fn string(value: &str) -> Vec<u32> {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.resize((bytes.len() / 4 + 1) * 4, 0);
+        bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+
+    fn module(instructions: &[Vec<u32>]) -> Vec<u32> {
+        let mut code = vec![SPIRV_MAGIC, 0x0001_0500, 0, 100, 0];
+        instructions
+            .iter()
+            .for_each(|instruction| code.extend_from_slice(instruction));
+        code
+    }
+
+    fn entry_point(execution_model: u32, id: u32, name: &str) -> Vec<u32> {
+        let mut operands = vec![execution_model, id];
+        operands.extend(string(name));
+        instruction(OP_ENTRY_POINT, &operands)
+    }
+
+    // Compute shader with a push constant block, a storage buffer and an array of
+    // sampled images
+    fn compute_module() -> Vec<u32> {
+        module(&[
+            entry_point(5, 1, "main"),
+            instruction(OP_EXECUTION_MODE, &[1, EXECUTION_MODE_LOCAL_SIZE, 8, 4, 1]),
+            instruction(OP_DECORATE, &[11, DECORATION_DESCRIPTOR_SET, 0]),
+            instruction(OP_DECORATE, &[11, DECORATION_BINDING, 2]),
+            instruction(OP_DECORATE, &[16, DECORATION_DESCRIPTOR_SET, 1]),
+            instruction(OP_DECORATE, &[16, DECORATION_BINDING, 0]),
+            instruction(OP_MEMBER_DECORATE, &[4, 0, DECORATION_OFFSET, 0]),
+            instruction(OP_MEMBER_DECORATE, &[4, 1, DECORATION_OFFSET, 16]),
+            instruction(OP_TYPE_FLOAT, &[2, 32]),
+            instruction(OP_TYPE_VECTOR, &[3, 2, 4]),
+            instruction(OP_TYPE_STRUCT, &[4, 3, 2]),
+            instruction(OP_TYPE_POINTER, &[5, STORAGE_CLASS_PUSH_CONSTANT, 4]),
+            instruction(OP_VARIABLE, &[5, 6, STORAGE_CLASS_PUSH_CONSTANT]),
+            instruction(OP_TYPE_INT, &[7, 32, 0]),
+            instruction(OP_TYPE_RUNTIME_ARRAY, &[8, 7]),
+            instruction(OP_TYPE_STRUCT, &[9, 8]),
+            instruction(OP_TYPE_POINTER, &[10, STORAGE_CLASS_STORAGE_BUFFER, 9]),
+            instruction(OP_VARIABLE, &[10, 11, STORAGE_CLASS_STORAGE_BUFFER]),
+            // 2D, sampled
+            instruction(OP_TYPE_IMAGE, &[12, 2, 1, 0, 0, 0, 1, 0]),
+            instruction(OP_CONSTANT, &[7, 13, 4]),
+            instruction(OP_TYPE_ARRAY, &[14, 12, 13]),
+            instruction(OP_TYPE_POINTER, &[15, 0, 14]),
+            instruction(OP_VARIABLE, &[15, 16, 0]),
+        ])
+    }
+
+    #[test]
+    fn reflects_compute_shader() {
+        let shader = Shader::from_words("compute", compute_module()).unwrap();
+
+        assert_eq!(shader.stage, vk::ShaderStageFlags::COMPUTE);
+        assert_eq!(shader.entry_point, "main");
+        assert_eq!(shader.workgroup_size, Some([8, 4, 1]));
+        // vec4 at 0, float at 16
+        assert_eq!(shader.push_constant_size, 20);
+
+        let bindings: Vec<(u32, u32, vk::DescriptorType)> = shader
+            .bindings
+            .iter()
+            .map(|binding| (binding.set, binding.binding, binding.descriptor_type))
+            .collect();
+        assert_eq!(
+            bindings,
+            [
+                (0, 2, vk::DescriptorType::STORAGE_BUFFER),
+                (1, 0, vk::DescriptorType::SAMPLED_IMAGE),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_byte_swapped_modules() {
+        let code: Vec<u32> = compute_module().into_iter().map(u32::swap_bytes).collect();
+        let shader = Shader::from_words("swapped", code).unwrap();
+
+        assert_eq!(shader.workgroup_size, Some([8, 4, 1]));
+        assert_eq!(shader.push_constant_size, 20);
+    }
+
+    #[test]
+    fn selects_entry_points() {
+        let code = module(&[entry_point(0, 1, "vs_main"), entry_point(4, 2, "fs_main")]);
+        let shader = Shader::from_words("graphics", code).unwrap();
+        assert_eq!(shader.entry_points.len(), 2);
+        assert_eq!(shader.stage, vk::ShaderStageFlags::VERTEX);
+
+        let shader = shader.with_entry_point("fs_main").unwrap();
+        assert_eq!(shader.stage, vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(shader.entry_point, "fs_main");
+        assert!(matches!(
+            shader.with_entry_point("cs_main"),
+            Err(ShaderError::MissingEntryPoint { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_modules() {
+        let mut code = compute_module();
+        code[0] = 0;
+        assert!(matches!(
+            Shader::from_words("magic", code),
+            Err(ShaderError::InvalidMagic { .. })
+        ));
+
+        // `OpEntryPoint` without a name or ID
+        for operands in [&[5, 1][..], &[5]] {
+            let code = module(&[instruction(OP_ENTRY_POINT, operands)]);
+            assert!(matches!(
+                Shader::from_words("truncated", code),
+                Err(ShaderError::Malformed { .. })
+            ));
+        }
+
+        // Word count past the end of the module
+        let mut code = module(&[instruction(OP_TYPE_FLOAT, &[2, 32])]);
+        code.truncate(code.len() - 1);
+        assert!(matches!(
+            Shader::from_words("overrun", code),
+            Err(ShaderError::Malformed { .. })
+        ));
+
+        assert!(matches!(
+            Shader::from_spirv("size", &[0; 22]),
+            Err(ShaderError::Malformed { .. })
+        ));
+    }
+}
@@ -0,0 +1,550 @@
+use ash::vk;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use super::{Shader, ShaderError};
+
+#[derive(Clone, Debug)]
+pub struct ShaderCompileDesc {
+    // `.wgsl` files are compiled as WGSL, everything else as GLSL
+    pub path: PathBuf,
+    // Searched for `#include`s after the including file's directory
+    pub include_dir: PathBuf,
+    pub stage: vk::ShaderStageFlags,
+    // GLSL entry points are always `main`
+    pub entry_point: String,
+    pub defines: Vec<(String, String)>,
+}
+
+impl ShaderCompileDesc {
+    fn is_wgsl(&self) -> bool {
+        self.path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("wgsl"))
+    }
+}
+
+// Source with all includes expanded, `line_map[i]` is the origin of line `i`
+#[derive(Default)]
+struct PreprocessedSource {
+    lines: Vec<String>,
+    line_map: Vec<(PathBuf, u32)>,
+    included_files: HashSet<PathBuf>,
+}
+
+impl PreprocessedSource {
+    fn source(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    fn compile_error(&self, name: &str, line_number: Option<u32>, message: String) -> ShaderError {
+        let (file, line) = line_number
+            .and_then(|line_number| self.line_map.get(line_number.saturating_sub(1) as usize))
+            .cloned()
+            .unwrap_or_default();
+
+        ShaderError::Compile {
+            name: name.to_owned(),
+            file,
+            line,
+            message,
+        }
+    }
+}
+
+fn parse_include(line: &str) -> Option<&str> {
+    let target = line.trim().strip_prefix("#include")?.trim();
+    let target = target
+        .strip_prefix('"')
+        .and_then(|target| target.strip_suffix('"'))
+        .or_else(|| {
+            target
+                .strip_prefix('<')
+                .and_then(|target| target.strip_suffix('>'))
+        })?;
+
+    Some(target)
+}
+
+fn expand_includes(
+    name: &str,
+    path: &Path,
+    include_dir: &Path,
+    include_stack: &mut Vec<PathBuf>,
+    result: &mut PreprocessedSource,
+) -> Result<(), ShaderError> {
+    let io_error = |error| ShaderError::Io {
+        name: format!("{name} ({})", path.display()),
+        error,
+    };
+    let canonical_path = path.canonicalize().map_err(io_error)?;
+    let source = std::fs::read_to_string(path).map_err(io_error)?;
+
+    include_stack.push(canonical_path.clone());
+    result.included_files.insert(canonical_path);
+
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i as u32 + 1;
+        let trimmed = line.trim();
+        if trimmed.starts_with("#extension GL_GOOGLE_include_directive")
+            || trimmed == "#pragma once"
+        {
+            continue;
+        }
+
+        if trimmed.starts_with("#include") {
+            let error = |message: String| ShaderError::Compile {
+                name: name.to_owned(),
+                file: path.to_owned(),
+                line: line_number,
+                message,
+            };
+
+            let target = parse_include(line)
+                .ok_or_else(|| error(String::from("malformed #include directive")))?;
+            let local_path = path.parent().unwrap_or(Path::new("")).join(target);
+            let include_path = if local_path.exists() {
+                local_path
+            } else {
+                include_dir.join(target)
+            };
+            let canonical_include = include_path
+                .canonicalize()
+                .map_err(|_| error(format!("cannot find include file `{target}`")))?;
+
+            if include_stack.contains(&canonical_include) {
+                return Err(error(format!("`{target}` includes itself")));
+            }
+
+            // Every file is only included once, same as `#pragma once`
+            if !result.included_files.contains(&canonical_include) {
+                expand_includes(name, &include_path, include_dir, include_stack, result)?;
+            }
+            continue;
+        }
+
+        result.lines.push(line.to_owned());
+        result.line_map.push((path.to_owned(), line_number));
+    }
+
+    include_stack.pop();
+    Ok(())
+}
+
+fn substitute_defines(line: &str, defines: &HashMap<String, String>) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut identifier = String::new();
+    let flush = |identifier: &mut String, result: &mut String| {
+        match defines.get(identifier.as_str()) {
+            Some(value) if !value.is_empty() => result.push_str(value),
+            _ => result.push_str(identifier),
+        }
+        identifier.clear();
+    };
+
+    for c in line.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            identifier.push(c);
+        } else {
+            flush(&mut identifier, &mut result);
+            result.push(c);
+        }
+    }
+    flush(&mut identifier, &mut result);
+
+    result
+}
+
+// WGSL has no preprocessor, so `#define`, `#undef`, `#ifdef`, `#ifndef`, `#else`
+// and `#endif` are handled here. GLSL leaves these to naga.
+fn apply_wgsl_defines(
+    name: &str,
+    source: PreprocessedSource,
+    defines: &[(String, String)],
+) -> Result<PreprocessedSource, ShaderError> {
+    let mut defines: HashMap<String, String> = defines.iter().cloned().collect();
+    // Each entry is (branch active, parent active)
+    let mut conditions: Vec<(bool, bool)> = Vec::new();
+    let mut result = PreprocessedSource {
+        included_files: source.included_files,
+        ..Default::default()
+    };
+
+    for (line, (file, line_number)) in source.lines.into_iter().zip(source.line_map) {
+        let error = |message: &str| ShaderError::Compile {
+            name: name.to_owned(),
+            file: file.clone(),
+            line: line_number,
+            message: message.to_owned(),
+        };
+
+        let active = conditions.last().is_none_or(|&(active, _)| active);
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some(directive @ ("#ifdef" | "#ifndef")) => {
+                let define = tokens.next().ok_or_else(|| error("missing macro name"))?;
+                let defined = defines.contains_key(define);
+                let taken = if directive == "#ifdef" {
+                    defined
+                } else {
+                    !defined
+                };
+                conditions.push((active && taken, active));
+            }
+            Some("#else") => {
+                let (taken, parent_active) = conditions
+                    .pop()
+                    .ok_or_else(|| error("#else without #ifdef"))?;
+                conditions.push((parent_active && !taken, parent_active));
+            }
+            Some("#endif") => {
+                conditions
+                    .pop()
+                    .ok_or_else(|| error("#endif without #ifdef"))?;
+            }
+            Some("#define") if active => {
+                let define = tokens.next().ok_or_else(|| error("missing macro name"))?;
+                let value = tokens.collect::<Vec<_>>().join(" ");
+                defines.insert(define.to_owned(), value);
+            }
+            Some("#undef") if active => {
+                let define = tokens.next().ok_or_else(|| error("missing macro name"))?;
+                defines.remove(define);
+            }
+            _ if active => {
+                result.lines.push(substitute_defines(&line, &defines));
+                result.line_map.push((file, line_number));
+            }
+            _ => {}
+        }
+    }
+
+    if !conditions.is_empty() {
+        return Err(result.compile_error(
+            name,
+            Some(result.lines.len() as u32),
+            String::from("unterminated #ifdef block"),
+        ));
+    }
+
+    Ok(result)
+}
+
+fn naga_stage(stage: vk::ShaderStageFlags) -> Option<naga::ShaderStage> {
+    let stage = match stage {
+        vk::ShaderStageFlags::VERTEX => naga::ShaderStage::Vertex,
+        vk::ShaderStageFlags::FRAGMENT => naga::ShaderStage::Fragment,
+        vk::ShaderStageFlags::COMPUTE => naga::ShaderStage::Compute,
+        vk::ShaderStageFlags::TASK_EXT => naga::ShaderStage::Task,
+        vk::ShaderStageFlags::MESH_EXT => naga::ShaderStage::Mesh,
+        _ => return None,
+    };
+
+    Some(stage)
+}
+
+pub fn compile_shader(name: &str, desc: &ShaderCompileDesc) -> Result<Shader, ShaderError> {
+    let naga_stage = naga_stage(desc.stage).ok_or_else(|| ShaderError::Compile {
+        name: name.to_owned(),
+        file: desc.path.clone(),
+        line: 0,
+        message: format!("stage {:?} cannot be compiled from source", desc.stage),
+    })?;
+
+    let mut preprocessed = PreprocessedSource::default();
+    expand_includes(
+        name,
+        &desc.path,
+        &desc.include_dir,
+        &mut Vec::new(),
+        &mut preprocessed,
+    )?;
+
+    let (module, preprocessed, entry_point) = if desc.is_wgsl() {
+        let preprocessed = apply_wgsl_defines(name, preprocessed, &desc.defines)?;
+        let source = preprocessed.source();
+        let module = naga::front::wgsl::parse_str(&source).map_err(|error| {
+            preprocessed.compile_error(
+                name,
+                error.location(&source).map(|location| location.line_number),
+                error.message().to_owned(),
+            )
+        })?;
+
+        (module, preprocessed, desc.entry_point.clone())
+    } else {
+        let source = preprocessed.source();
+        let options = naga::front::glsl::Options {
+            stage: naga_stage,
+            defines: desc.defines.iter().cloned().collect(),
+        };
+        let module = naga::front::glsl::Frontend::default()
+            .parse(&options, &source)
+            .map_err(|errors| match errors.errors.first() {
+                Some(error) => preprocessed.compile_error(
+                    name,
+                    error.location(&source).map(|location| location.line_number),
+                    error.kind.to_string(),
+                ),
+                None => preprocessed.compile_error(
+                    name,
+                    None,
+                    String::from("GLSL parsing failed without a diagnostic"),
+                ),
+            })?;
+
+        (module, preprocessed, String::from("main"))
+    };
+
+    let source = preprocessed.source();
+    let module_info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::back::spv::supported_capabilities(),
+    )
+    .validate(&module)
+    .map_err(|error| {
+        preprocessed.compile_error(
+            name,
+            error.location(&source).map(|location| location.line_number),
+            error.to_string(),
+        )
+    })?;
+
+    let mut options = naga::back::spv::Options {
+        lang_version: (1, 5),
+        ..Default::default()
+    };
+    // GLSL is already written against Vulkan's coordinate space
+    if !desc.is_wgsl() {
+        options
+            .flags
+            .remove(naga::back::spv::WriterFlags::ADJUST_COORDINATE_SPACE);
+    }
+    let pipeline_options = naga::back::spv::PipelineOptions {
+        shader_stage: naga_stage,
+        entry_point,
+    };
+    let code = naga::back::spv::write_vec(&module, &module_info, &options, Some(&pipeline_options))
+        .map_err(|error| preprocessed.compile_error(name, None, error.to_string()))?;
+
+    let mut shader = Shader::from_words(name, code)?;
+    shader.source = Some(desc.clone());
+    shader.dependencies = preprocessed.included_files.into_iter().collect();
+
+    Ok(shader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::test_util::TempDir;
+
+    fn preprocess(dir: &Path, file: &str) -> Result<PreprocessedSource, ShaderError> {
+        let mut result = PreprocessedSource::default();
+        expand_includes(
+            "test",
+            &dir.join(file),
+            &dir.join("include"),
+            &mut Vec::new(),
+            &mut result,
+        )?;
+
+        Ok(result)
+    }
+
+    fn wgsl_defines(lines: &[&str], defines: &[(&str, &str)]) -> Result<Vec<String>, ShaderError> {
+        let source = PreprocessedSource {
+            lines: lines.iter().map(|&line| line.to_owned()).collect(),
+            line_map: (1..=lines.len() as u32)
+                .map(|line| (PathBuf::from("test.wgsl"), line))
+                .collect(),
+            included_files: HashSet::new(),
+        };
+        let defines: Vec<(String, String)> = defines
+            .iter()
+            .map(|&(define, value)| (define.to_owned(), value.to_owned()))
+            .collect();
+
+        apply_wgsl_defines("test", source, &defines).map(|result| result.lines)
+    }
+
+    #[test]
+    fn parses_include_directives() {
+        assert_eq!(
+            parse_include("#include \"common.glsl\""),
+            Some("common.glsl")
+        );
+        assert_eq!(
+            parse_include("  #include <lib/math.glsl>"),
+            Some("lib/math.glsl")
+        );
+        assert_eq!(parse_include("#include common.glsl"), None);
+        assert_eq!(parse_include("#include \"common.glsl"), None);
+    }
+
+    #[test]
+    fn expands_includes_once() {
+        let dir = TempDir::new(
+            "expands_includes_once",
+            &[
+                (
+                    "main.glsl",
+                    "#include \"a.glsl\"\n#include <b.glsl>\nvoid main() {}",
+                ),
+                ("a.glsl", "#pragma once\nint a;"),
+                // Found through the include directory
+                ("include/b.glsl", "#include \"../a.glsl\"\nint b;"),
+            ],
+        );
+        let result = preprocess(&dir, "main.glsl").unwrap();
+
+        assert_eq!(result.lines, ["int a;", "int b;", "void main() {}"]);
+        let origins: Vec<(String, u32)> = result
+            .line_map
+            .iter()
+            .map(|(file, line)| (file.file_name().unwrap().to_string_lossy().into(), *line))
+            .collect();
+        assert_eq!(
+            origins,
+            [
+                (String::from("a.glsl"), 2),
+                (String::from("b.glsl"), 2),
+                (String::from("main.glsl"), 3),
+            ]
+        );
+        assert_eq!(result.included_files.len(), 3);
+    }
+
+    #[test]
+    fn reports_include_errors() {
+        let dir = TempDir::new(
+            "reports_include_errors",
+            &[
+                ("cycle.glsl", "#include \"cycle_inner.glsl\""),
+                ("cycle_inner.glsl", "\n#include \"cycle.glsl\""),
+                ("missing.glsl", "int a;\n#include \"missing_file.glsl\""),
+            ],
+        );
+
+        match preprocess(&dir, "cycle.glsl") {
+            Err(ShaderError::Compile { file, line, .. }) => {
+                assert!(file.ends_with("cycle_inner.glsl"));
+                assert_eq!(line, 2);
+            }
+            _ => panic!("include cycle wasn't reported"),
+        }
+        match preprocess(&dir, "missing.glsl") {
+            Err(ShaderError::Compile { line, message, .. }) => {
+                assert_eq!(line, 2);
+                assert!(message.contains("missing_file.glsl"));
+            }
+            _ => panic!("missing include wasn't reported"),
+        }
+    }
+
+    #[test]
+    fn substitutes_whole_identifiers() {
+        let defines = HashMap::from([
+            (String::from("SIZE"), String::from("64")),
+            (String::from("FLAG"), String::new()),
+        ]);
+
+        assert_eq!(
+            substitute_defines("let a = SIZE * SIZE_2 + FLAG;", &defines),
+            "let a = 64 * SIZE_2 + FLAG;"
+        );
+    }
+
+    #[test]
+    fn applies_wgsl_conditionals() {
+        let lines = [
+            "#ifdef A",
+            "a",
+            "#ifndef B",
+            "not b",
+            "#else",
+            "b",
+            "#endif",
+            "#else",
+            "not a",
+            "#endif",
+        ];
+        assert_eq!(wgsl_defines(&lines, &[("A", "")]).unwrap(), ["a", "not b"]);
+        assert_eq!(
+            wgsl_defines(&lines, &[("A", ""), ("B", "")]).unwrap(),
+            ["a", "b"]
+        );
+        assert_eq!(wgsl_defines(&lines, &[("B", "")]).unwrap(), ["not a"]);
+
+        let lines = [
+            "#define COUNT 4",
+            "COUNT",
+            "#undef COUNT",
+            "COUNT",
+            "#ifdef COUNT",
+            "#define COUNT 8",
+            "#endif",
+        ];
+        assert_eq!(wgsl_defines(&lines, &[]).unwrap(), ["4", "COUNT"]);
+
+        assert!(wgsl_defines(&["#ifdef A"], &[]).is_err());
+        assert!(wgsl_defines(&["#endif"], &[]).is_err());
+        assert!(wgsl_defines(&["#else"], &[]).is_err());
+    }
+
+    #[test]
+    fn compiles_wgsl_with_includes_and_defines() {
+        let dir = TempDir::new(
+            "compiles_wgsl_with_includes_and_defines",
+            &[
+                (
+                    "compute.wgsl",
+                    "#include \"size.wgsl\"\n@compute @workgroup_size(SIZE)\nfn main() {}",
+                ),
+                ("size.wgsl", "#ifndef SIZE\n#define SIZE 32\n#endif"),
+                (
+                    "broken.wgsl",
+                    "#include \"size.wgsl\"\nfn main() {\n    let a = ;\n}",
+                ),
+            ],
+        );
+        let desc = ShaderCompileDesc {
+            path: dir.join("compute.wgsl"),
+            include_dir: dir.to_path_buf(),
+            stage: vk::ShaderStageFlags::COMPUTE,
+            entry_point: String::from("main"),
+            defines: vec![(String::from("SIZE"), String::from("16"))],
+        };
+
+        let shader = compile_shader("compute", &desc).unwrap();
+        assert_eq!(shader.workgroup_size, Some([16, 1, 1]));
+        assert_eq!(shader.dependencies.len(), 2);
+
+        let shader = compile_shader(
+            "compute",
+            &ShaderCompileDesc {
+                defines: Vec::new(),
+                ..desc.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(shader.workgroup_size, Some([32, 1, 1]));
+
+        // Lines of the expanded source map back to the file they came from
+        match compile_shader(
+            "broken",
+            &ShaderCompileDesc {
+                path: dir.join("broken.wgsl"),
+                ..desc
+            },
+        ) {
+            Err(ShaderError::Compile { file, line, .. }) => {
+                assert!(file.ends_with("broken.wgsl"));
+                assert_eq!(line, 3);
+            }
+            _ => panic!("syntax error wasn't reported"),
+        }
+    }
+}
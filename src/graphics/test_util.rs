@@ -0,0 +1,45 @@
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+// Directory of test files under the system temp dir, removed again on drop so
+// failed asserts don't leave it behind.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    // `files` are (relative path, contents), parent directories are created
+    pub fn new(test: &str, files: &[(&str, impl AsRef<[u8]>)]) -> Self {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "lorr_{test}_{}_{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        for (file, contents) in files {
+            let file_path = path.join(file);
+            std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            std::fs::write(file_path, contents).unwrap();
+        }
+        std::fs::create_dir_all(&path).unwrap();
+
+        Self { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.path).ok();
+    }
+}
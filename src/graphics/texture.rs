@@ -0,0 +1,293 @@
+use ash::vk;
+use std::{fmt, path::Path};
+
+use super::{
+    bc_fallback_format, is_block_compressed, CommandType, Device, ImageDesc, ImageDimension,
+    ImageError, ImageID, ResourceState, TextureData,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextureDesc {
+    // Color textures are sRGB, data like normal maps should be linear
+    pub srgb: bool,
+    pub generate_mips: bool,
+}
+
+impl Default for TextureDesc {
+    fn default() -> Self {
+        Self {
+            srgb: true,
+            generate_mips: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TextureError {
+    Io {
+        name: String,
+        error: std::io::Error,
+    },
+    Decode {
+        name: String,
+        error: image::ImageError,
+    },
+    Malformed {
+        name: String,
+        reason: String,
+    },
+    UnsupportedFormat {
+        name: String,
+        format: vk::Format,
+        reason: String,
+    },
+    Image(ImageError),
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Io { name, error } => write!(f, "{name}: {error}"),
+            TextureError::Decode { name, error } => write!(f, "{name}: {error}"),
+            TextureError::Malformed { name, reason } => write!(f, "{name}: malformed, {reason}"),
+            TextureError::UnsupportedFormat {
+                name,
+                format,
+                reason,
+            } => write!(f, "{name}: unsupported format {format:?}, {reason}"),
+            TextureError::Image(error) => write!(f, "{error}"),
+            TextureError::Vulkan(result) => write!(f, "Vulkan error: {result}"),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+impl From<ImageError> for TextureError {
+    fn from(value: ImageError) -> Self {
+        TextureError::Image(value)
+    }
+}
+
+impl From<vk::Result> for TextureError {
+    fn from(value: vk::Result) -> Self {
+        TextureError::Vulkan(value)
+    }
+}
+
+impl TextureData {
+    // KTX2 and DDS files are kept as stored, anything else is decoded as RGBA8
+    pub fn load(path: impl AsRef<Path>, srgb: bool) -> Result<Self, TextureError> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        let read = || {
+            std::fs::read(path).map_err(|error| TextureError::Io {
+                name: name.clone(),
+                error,
+            })
+        };
+
+        match extension.as_deref() {
+            Some("ktx2") => TextureData::from_ktx2(&name, &read()?),
+            Some("dds") => TextureData::from_dds(&name, &read()?, srgb),
+            _ => {
+                let pixels = image::open(path)
+                    .map_err(|error| TextureError::Decode {
+                        name: name.clone(),
+                        error,
+                    })?
+                    .into_rgba8();
+                Ok(TextureData::rgba8(
+                    pixels.width(),
+                    pixels.height(),
+                    pixels.into_raw(),
+                    srgb,
+                ))
+            }
+        }
+    }
+}
+
+impl Device {
+    pub fn load_texture(
+        &mut self,
+        path: impl AsRef<Path>,
+        desc: &TextureDesc,
+    ) -> Result<ImageID, TextureError> {
+        let path = path.as_ref();
+        let texture = TextureData::load(path, desc.srgb)?;
+
+        self.create_texture(&path.display().to_string(), &texture, desc)
+    }
+
+    // Uploads `texture` into a `GpuOnly` image through a staging buffer, BC formats
+    // the device can't sample are decoded on the CPU. The returned image is in
+    // `SHADER_READ` and already in the bindless table.
+    pub fn create_texture(
+        &mut self,
+        name: &str,
+        texture: &TextureData,
+        desc: &TextureDesc,
+    ) -> Result<ImageID, TextureError> {
+        let format_features = |format| unsafe {
+            self.physical_device
+                .instance
+                .get_physical_device_format_properties(self.physical_device.handle, format)
+                .optimal_tiling_features
+        };
+        let required_features =
+            vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST;
+
+        let decoded_texture;
+        let missing_features = required_features & !format_features(texture.format);
+        let texture = match missing_features.is_empty() {
+            true => texture,
+            false => {
+                // Only BC formats have a CPU fallback
+                if bc_fallback_format(texture.format).is_none() {
+                    return Err(TextureError::UnsupportedFormat {
+                        name: name.to_owned(),
+                        format: texture.format,
+                        reason: format!("device doesn't support {missing_features:?}"),
+                    });
+                }
+                decoded_texture = texture
+                    .decode_bc()
+                    .expect("BC formats with a fallback can be decoded");
+                println!(
+                    "{name}: {:?} isn't supported, decoded to {:?}",
+                    texture.format, decoded_texture.format
+                );
+                &decoded_texture
+            }
+        };
+
+        let format = texture.format;
+        let format_features = format_features(format);
+        let generate_mips = desc.generate_mips && texture.levels.len() == 1;
+        let can_blit = !is_block_compressed(format)
+            && format_features
+                .contains(vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST);
+        if generate_mips && !can_blit {
+            println!("{name}: {format:?} doesn't support blits, skipping mip generation");
+        }
+        // Linear downsampling is only allowed when the format can be filtered
+        let mip_filter =
+            match format_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR) {
+                true => vk::Filter::LINEAR,
+                false => vk::Filter::NEAREST,
+            };
+
+        let mut image_desc = ImageDesc {
+            name: Some(name.to_owned()),
+            dimension: match (texture.is_cube, texture.extent.depth) {
+                (true, _) => ImageDimension::Cube,
+                (false, 1) => ImageDimension::D2,
+                (false, _) => ImageDimension::D3,
+            },
+            format,
+            extent: texture.extent,
+            array_layers: texture.array_layers,
+            mip_levels: texture.levels.len() as u32,
+            usage: vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::TRANSFER_DST,
+            ..Default::default()
+        };
+        if generate_mips && can_blit {
+            image_desc = image_desc.full_mip_chain();
+        }
+        self.check_bindless_image_capacity()?;
+        let image = self.create_image(&image_desc)?;
+        let image_id = self.register_image(image);
+
+        // Offsets must be a multiple of the texel block size, 16 covers every format
+        let mut staging_data = Vec::new();
+        let mut copies = Vec::with_capacity(texture.levels.len());
+        for (level, data) in texture.levels.iter().enumerate() {
+            staging_data.resize(staging_data.len().next_multiple_of(16), 0);
+            copies.push(
+                vk::BufferImageCopy::default()
+                    .buffer_offset(staging_data.len() as u64)
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: level as u32,
+                        base_array_layer: 0,
+                        layer_count: texture.array_layers,
+                    })
+                    .image_extent(texture.level_extent(level as u32)),
+            );
+            staging_data.extend_from_slice(data);
+        }
+
+        let staging_buffer_id = match self.create_staging_buffer(name, &staging_data) {
+            Ok(staging_buffer_id) => staging_buffer_id,
+            Err(error) => {
+                self.destroy_image(image_id);
+                return Err(error.into());
+            }
+        };
+        let result = self.immediate_submit(CommandType::Graphics, |device, command_list| {
+            let image = device.image_at(image_id);
+            let staging_buffer = device.buffer_at(staging_buffer_id);
+            let level_range = |level| vk::ImageSubresourceRange {
+                base_mip_level: level,
+                level_count: 1,
+                ..image.full_range()
+            };
+            let level_layers = |level| vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level,
+                base_array_layer: 0,
+                layer_count: image.slices,
+            };
+            let level_end = |level| {
+                let extent = texture.level_extent(level);
+                vk::Offset3D {
+                    x: extent.width as i32,
+                    y: extent.height as i32,
+                    z: extent.depth as i32,
+                }
+            };
+
+            command_list.transition(image, ResourceState::TRANSFER_DST);
+            command_list.copy_buffer_to_image(staging_buffer, image, &copies);
+
+            // Each generated level is downsampled from the previous one
+            for level in copies.len() as u32..image.levels {
+                command_list.transition_range(
+                    image,
+                    level_range(level - 1),
+                    ResourceState::TRANSFER_SRC,
+                );
+                let blit = vk::ImageBlit::default()
+                    .src_subresource(level_layers(level - 1))
+                    .src_offsets([vk::Offset3D::default(), level_end(level - 1)])
+                    .dst_subresource(level_layers(level))
+                    .dst_offsets([vk::Offset3D::default(), level_end(level)]);
+                command_list.blit_image(image, image, &[blit], mip_filter);
+            }
+
+            command_list.transition(image, ResourceState::SHADER_READ);
+        });
+        // The copy and blits may still be running after a timeout, leak both
+        // like `immediate_submit` leaks its pool
+        if result == Err(vk::Result::TIMEOUT) {
+            return Err(vk::Result::TIMEOUT.into());
+        }
+        self.destroy_buffer(staging_buffer_id);
+        if let Err(error) = result {
+            self.destroy_image(image_id);
+            return Err(error.into());
+        }
+
+        self.write_sampled_image_descriptor(image_id);
+
+        Ok(image_id)
+    }
+}
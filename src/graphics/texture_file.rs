@@ -0,0 +1,833 @@
+use ash::vk;
+
+use super::{mip_level_count, TextureError};
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const KTX2_HEADER_SIZE: usize = 80;
+const DDS_MAGIC: [u8; 4] = *b"DDS ";
+const DDS_HEADER_SIZE: usize = 128;
+const DDS_DX10_HEADER_SIZE: usize = 20;
+
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+// (block width, block height, bytes per block), uncompressed formats are 1x1 blocks
+pub fn format_block_info(format: vk::Format) -> Option<(u32, u32, u32)> {
+    // ASTC formats are contiguous UNORM/SRGB pairs ordered by block size
+    const ASTC_BLOCK_SIZES: [(u32, u32); 14] = [
+        (4, 4),
+        (5, 4),
+        (5, 5),
+        (6, 5),
+        (6, 6),
+        (8, 5),
+        (8, 6),
+        (8, 8),
+        (10, 5),
+        (10, 6),
+        (10, 8),
+        (10, 10),
+        (12, 10),
+        (12, 12),
+    ];
+    let astc_range =
+        vk::Format::ASTC_4X4_UNORM_BLOCK.as_raw()..=vk::Format::ASTC_12X12_SRGB_BLOCK.as_raw();
+    if astc_range.contains(&format.as_raw()) {
+        let (width, height) = ASTC_BLOCK_SIZES
+            [((format.as_raw() - vk::Format::ASTC_4X4_UNORM_BLOCK.as_raw()) / 2) as usize];
+        return Some((width, height, 16));
+    }
+
+    let info = match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SNORM | vk::Format::R8_SRGB => (1, 1, 1),
+        vk::Format::R8G8_UNORM | vk::Format::R8G8_SNORM | vk::Format::R16_SFLOAT => (1, 1, 2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_SFLOAT => (1, 1, 4),
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R32G32_SFLOAT => (1, 1, 8),
+        vk::Format::R32G32B32A32_SFLOAT => (1, 1, 16),
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK
+        | vk::Format::EAC_R11_UNORM_BLOCK
+        | vk::Format::EAC_R11_SNORM_BLOCK => (4, 4, 8),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK
+        | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK
+        | vk::Format::EAC_R11G11_UNORM_BLOCK
+        | vk::Format::EAC_R11G11_SNORM_BLOCK => (4, 4, 16),
+        _ => return None,
+    };
+
+    Some(info)
+}
+
+pub fn is_block_compressed(format: vk::Format) -> bool {
+    format_block_info(format).is_some_and(|(width, height, _)| width > 1 || height > 1)
+}
+
+// Uncompressed format BC data gets decoded to when the device can't sample it
+pub fn bc_fallback_format(format: vk::Format) -> Option<vk::Format> {
+    let fallback_format = match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK => vk::Format::R8G8B8A8_UNORM,
+        vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => vk::Format::R8G8B8A8_SRGB,
+        vk::Format::BC4_UNORM_BLOCK => vk::Format::R8_UNORM,
+        vk::Format::BC4_SNORM_BLOCK => vk::Format::R8_SNORM,
+        vk::Format::BC5_UNORM_BLOCK => vk::Format::R8G8_UNORM,
+        vk::Format::BC5_SNORM_BLOCK => vk::Format::R8G8_SNORM,
+        vk::Format::BC6H_UFLOAT_BLOCK | vk::Format::BC6H_SFLOAT_BLOCK => {
+            vk::Format::R16G16B16A16_SFLOAT
+        }
+        _ => return None,
+    };
+
+    Some(fallback_format)
+}
+
+// Decoded texels are written with a pitch of 4 texels
+fn decode_bc_block(format: vk::Format, block: &[u8], texels: &mut [u8]) {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK => bcdec_rs::bc1(block, texels, 16),
+        vk::Format::BC2_UNORM_BLOCK | vk::Format::BC2_SRGB_BLOCK => {
+            bcdec_rs::bc2(block, texels, 16)
+        }
+        vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK => {
+            bcdec_rs::bc3(block, texels, 16)
+        }
+        vk::Format::BC7_UNORM_BLOCK | vk::Format::BC7_SRGB_BLOCK => {
+            bcdec_rs::bc7(block, texels, 16)
+        }
+        vk::Format::BC4_UNORM_BLOCK => bcdec_rs::bc4(block, texels, 4, false),
+        vk::Format::BC4_SNORM_BLOCK => bcdec_rs::bc4(block, texels, 4, true),
+        vk::Format::BC5_UNORM_BLOCK => bcdec_rs::bc5(block, texels, 8, false),
+        vk::Format::BC5_SNORM_BLOCK => bcdec_rs::bc5(block, texels, 8, true),
+        vk::Format::BC6H_UFLOAT_BLOCK | vk::Format::BC6H_SFLOAT_BLOCK => {
+            let mut rgb = [0u16; 4 * 4 * 3];
+            let is_signed = format == vk::Format::BC6H_SFLOAT_BLOCK;
+            bcdec_rs::bc6h_half(block, &mut rgb, 4 * 3, is_signed);
+            // Alpha is 1.0 as a half float
+            for (texel, rgb) in texels.chunks_exact_mut(8).zip(rgb.chunks_exact(3)) {
+                for (channel, value) in texel
+                    .chunks_exact_mut(2)
+                    .zip([rgb[0], rgb[1], rgb[2], 0x3C00])
+                {
+                    channel.copy_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        _ => unreachable!("{:?} is not a BC format", format),
+    }
+}
+
+// Texel data of a texture file, laid out like `vkCmdCopyBufferToImage` expects
+#[derive(Clone, Debug)]
+pub struct TextureData {
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    // Includes cube faces, layer `n` is face `n % 6` of cube `n / 6`
+    pub array_layers: u32,
+    pub is_cube: bool,
+    // One entry per mip level, each holding every layer in order
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    // A single level of tightly packed RGBA8 texels
+    pub fn rgba8(width: u32, height: u32, texels: Vec<u8>, srgb: bool) -> Self {
+        assert_eq!(
+            texels.len(),
+            (width * height * 4) as usize,
+            "Texel data doesn't match the extent"
+        );
+
+        Self {
+            format: match srgb {
+                true => vk::Format::R8G8B8A8_SRGB,
+                false => vk::Format::R8G8B8A8_UNORM,
+            },
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            array_layers: 1,
+            is_cube: false,
+            levels: vec![texels],
+        }
+    }
+
+    pub fn level_extent(&self, level: u32) -> vk::Extent3D {
+        vk::Extent3D {
+            width: (self.extent.width >> level).max(1),
+            height: (self.extent.height >> level).max(1),
+            depth: (self.extent.depth >> level).max(1),
+        }
+    }
+
+    // None for formats without block info or sizes that overflow
+    fn level_size(format: vk::Format, extent: vk::Extent3D) -> Option<usize> {
+        let (block_width, block_height, block_size) = format_block_info(format)?;
+        (extent.width.div_ceil(block_width) as usize)
+            .checked_mul(extent.height.div_ceil(block_height) as usize)?
+            .checked_mul(extent.depth as usize)?
+            .checked_mul(block_size as usize)
+    }
+
+    pub fn from_ktx2(name: &str, bytes: &[u8]) -> Result<Self, TextureError> {
+        let malformed = |reason: &str| TextureError::Malformed {
+            name: name.to_owned(),
+            reason: reason.to_owned(),
+        };
+        if bytes.len() < KTX2_HEADER_SIZE || bytes[..12] != KTX2_IDENTIFIER {
+            return Err(malformed("not a KTX2 file"));
+        }
+
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let format = vk::Format::from_raw(u32_at(12) as i32);
+        let extent = vk::Extent3D {
+            width: u32_at(20),
+            height: u32_at(24).max(1),
+            depth: u32_at(28).max(1),
+        };
+        let layer_count = u32_at(32).max(1);
+        let face_count = u32_at(36);
+        // 0 asks the loader to generate the mip chain
+        let level_count = u32_at(40).max(1);
+        let supercompression_scheme = u32_at(44);
+
+        if format == vk::Format::UNDEFINED {
+            return Err(TextureError::UnsupportedFormat {
+                name: name.to_owned(),
+                format,
+                reason: String::from("Basis Universal textures must be transcoded first"),
+            });
+        }
+        if supercompression_scheme != 0 {
+            return Err(TextureError::UnsupportedFormat {
+                name: name.to_owned(),
+                format,
+                reason: format!("supercompression scheme {supercompression_scheme}"),
+            });
+        }
+        if format_block_info(format).is_none() {
+            return Err(TextureError::UnsupportedFormat {
+                name: name.to_owned(),
+                format,
+                reason: String::from("unknown texel block size"),
+            });
+        }
+        if face_count != 1 && face_count != 6 {
+            return Err(malformed("face count must be 1 or 6"));
+        }
+        if extent.width == 0 {
+            return Err(malformed("width is zero"));
+        }
+        if level_count > mip_level_count(extent) {
+            return Err(malformed("more levels than the extent allows"));
+        }
+        let array_layers = layer_count
+            .checked_mul(face_count)
+            .ok_or_else(|| malformed("too many array layers"))?;
+
+        let level_index_end = KTX2_HEADER_SIZE + level_count as usize * 24;
+        if bytes.len() < level_index_end {
+            return Err(malformed("truncated level index"));
+        }
+        let mut texture = Self {
+            format,
+            extent,
+            array_layers,
+            is_cube: face_count == 6,
+            levels: Vec::with_capacity(level_count as usize),
+        };
+        for level in 0..level_count {
+            let entry = KTX2_HEADER_SIZE + level as usize * 24;
+            let offset = u64_at(entry) as usize;
+            let length = u64_at(entry + 8) as usize;
+            // Short levels would make the upload read past the staging buffer
+            let expected_length = Self::level_size(format, texture.level_extent(level))
+                .and_then(|size| size.checked_mul(array_layers as usize))
+                .ok_or_else(|| malformed("level size overflows"))?;
+            if length != expected_length {
+                return Err(malformed("level size doesn't match the extent"));
+            }
+
+            let data = offset
+                .checked_add(length)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or_else(|| malformed("level data is out of bounds"))?;
+            texture.levels.push(data.to_vec());
+        }
+
+        Ok(texture)
+    }
+
+    // Legacy DDS files don't say whether they're sRGB, `srgb` decides for them
+    pub fn from_dds(name: &str, bytes: &[u8], srgb: bool) -> Result<Self, TextureError> {
+        let malformed = |reason: &str| TextureError::Malformed {
+            name: name.to_owned(),
+            reason: reason.to_owned(),
+        };
+        if bytes.len() < DDS_HEADER_SIZE || bytes[..4] != DDS_MAGIC {
+            return Err(malformed("not a DDS file"));
+        }
+
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let caps2 = u32_at(112);
+        let extent = vk::Extent3D {
+            width: u32_at(16),
+            height: u32_at(12).max(1),
+            depth: match caps2 & DDSCAPS2_VOLUME {
+                0 => 1,
+                _ => u32_at(24).max(1),
+            },
+        };
+        let level_count = u32_at(28).max(1);
+        if extent.width == 0 {
+            return Err(malformed("width is zero"));
+        }
+        if level_count > mip_level_count(extent) {
+            return Err(malformed("more levels than the extent allows"));
+        }
+        let pixel_format_flags = u32_at(80);
+        let four_cc: [u8; 4] = bytes[84..88].try_into().unwrap();
+
+        let (format, array_layers, is_cube, data_offset) = if &four_cc == b"DX10" {
+            if bytes.len() < DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE {
+                return Err(malformed("truncated DX10 header"));
+            }
+
+            let dxgi_format = u32_at(128);
+            let is_cube = u32_at(136) & DDS_RESOURCE_MISC_TEXTURECUBE != 0;
+            let array_size = u32_at(140).max(1);
+            let format =
+                dxgi_to_vk_format(dxgi_format).ok_or_else(|| TextureError::UnsupportedFormat {
+                    name: name.to_owned(),
+                    format: vk::Format::UNDEFINED,
+                    reason: format!("DXGI format {dxgi_format}"),
+                })?;
+            let faces = if is_cube { 6 } else { 1 };
+            let array_layers = array_size
+                .checked_mul(faces)
+                .ok_or_else(|| malformed("too many array layers"))?;
+
+            (
+                format,
+                array_layers,
+                is_cube,
+                DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE,
+            )
+        } else {
+            let format = match &four_cc {
+                b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+                b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+                b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+                b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+                b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+                b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+                b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+                _ if pixel_format_flags & DDPF_RGB != 0 && u32_at(88) == 32 => match u32_at(92) {
+                    0x0000_00FF => vk::Format::R8G8B8A8_UNORM,
+                    0x00FF_0000 => vk::Format::B8G8R8A8_UNORM,
+                    _ => return Err(malformed("unsupported RGB channel masks")),
+                },
+                _ => {
+                    return Err(TextureError::UnsupportedFormat {
+                        name: name.to_owned(),
+                        format: vk::Format::UNDEFINED,
+                        reason: format!("FourCC {:?}", String::from_utf8_lossy(&four_cc)),
+                    })
+                }
+            };
+            let format = match srgb {
+                true => srgb_format(format),
+                false => format,
+            };
+            let is_cube = caps2 & DDSCAPS2_CUBEMAP != 0;
+            let array_layers = if is_cube { 6 } else { 1 };
+
+            (format, array_layers, is_cube, DDS_HEADER_SIZE)
+        };
+
+        let mut texture = Self {
+            format,
+            extent,
+            array_layers,
+            is_cube,
+            levels: vec![Vec::new(); level_count as usize],
+        };
+
+        // DDS stores every level of a layer before the next layer
+        let mut offset = data_offset;
+        for _ in 0..array_layers {
+            for level in 0..level_count {
+                let size = Self::level_size(format, texture.level_extent(level))
+                    .ok_or_else(|| malformed("level size overflows"))?;
+                let end = offset
+                    .checked_add(size)
+                    .ok_or_else(|| malformed("truncated texel data"))?;
+                let data = bytes
+                    .get(offset..end)
+                    .ok_or_else(|| malformed("truncated texel data"))?;
+                texture.levels[level as usize].extend_from_slice(data);
+                offset = end;
+            }
+        }
+
+        Ok(texture)
+    }
+
+    // CPU decode of BC formats for devices without BC support, e.g. lavapipe
+    pub fn decode_bc(&self) -> Option<Self> {
+        let fallback_format = bc_fallback_format(self.format)?;
+        let (_, _, block_size) = format_block_info(self.format)?;
+        let (_, _, texel_size) = format_block_info(fallback_format)?;
+        let block_size = block_size as usize;
+        let texel_size = texel_size as usize;
+
+        let levels = self
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let extent = self.level_extent(level as u32);
+                let (width, height) = (extent.width as usize, extent.height as usize);
+                let blocks_x = width.div_ceil(4);
+                let blocks_y = height.div_ceil(4);
+                let row_pitch = width * texel_size;
+                let slice_size = row_pitch * height;
+                let slice_count = extent.depth as usize * self.array_layers as usize;
+
+                let mut texels = vec![0u8; slice_size * slice_count];
+                let mut block_texels = [0u8; 4 * 4 * 8];
+                for (block_index, block) in data.chunks_exact(block_size).enumerate() {
+                    let slice = block_index / (blocks_x * blocks_y);
+                    let block_x = block_index % blocks_x;
+                    let block_y = block_index / blocks_x % blocks_y;
+                    if slice >= slice_count {
+                        break;
+                    }
+
+                    decode_bc_block(self.format, block, &mut block_texels);
+                    // Blocks on the right and bottom edges can hang over the level
+                    let columns = (width - block_x * 4).min(4) * texel_size;
+                    for row in 0..(height - block_y * 4).min(4) {
+                        let src = row * 4 * texel_size;
+                        let dst = slice * slice_size
+                            + (block_y * 4 + row) * row_pitch
+                            + block_x * 4 * texel_size;
+                        texels[dst..dst + columns]
+                            .copy_from_slice(&block_texels[src..src + columns]);
+                    }
+                }
+
+                texels
+            })
+            .collect();
+
+        Some(Self {
+            format: fallback_format,
+            levels,
+            ..self.clone()
+        })
+    }
+}
+
+fn srgb_format(format: vk::Format) -> vk::Format {
+    match format {
+        vk::Format::BC1_RGBA_UNORM_BLOCK => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        vk::Format::BC2_UNORM_BLOCK => vk::Format::BC2_SRGB_BLOCK,
+        vk::Format::BC3_UNORM_BLOCK => vk::Format::BC3_SRGB_BLOCK,
+        vk::Format::BC7_UNORM_BLOCK => vk::Format::BC7_SRGB_BLOCK,
+        vk::Format::R8G8B8A8_UNORM => vk::Format::R8G8B8A8_SRGB,
+        vk::Format::B8G8R8A8_UNORM => vk::Format::B8G8R8A8_SRGB,
+        format => format,
+    }
+}
+
+fn dxgi_to_vk_format(dxgi_format: u32) -> Option<vk::Format> {
+    let format = match dxgi_format {
+        2 => vk::Format::R32G32B32A32_SFLOAT,
+        10 => vk::Format::R16G16B16A16_SFLOAT,
+        28 => vk::Format::R8G8B8A8_UNORM,
+        29 => vk::Format::R8G8B8A8_SRGB,
+        71 => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        72 => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        74 => vk::Format::BC2_UNORM_BLOCK,
+        75 => vk::Format::BC2_SRGB_BLOCK,
+        77 => vk::Format::BC3_UNORM_BLOCK,
+        78 => vk::Format::BC3_SRGB_BLOCK,
+        80 => vk::Format::BC4_UNORM_BLOCK,
+        81 => vk::Format::BC4_SNORM_BLOCK,
+        83 => vk::Format::BC5_UNORM_BLOCK,
+        84 => vk::Format::BC5_SNORM_BLOCK,
+        87 => vk::Format::B8G8R8A8_UNORM,
+        91 => vk::Format::B8G8R8A8_SRGB,
+        95 => vk::Format::BC6H_UFLOAT_BLOCK,
+        96 => vk::Format::BC6H_SFLOAT_BLOCK,
+        98 => vk::Format::BC7_UNORM_BLOCK,
+        99 => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_u32s(bytes: &mut [u8], offset: usize, values: &[u32]) {
+        for (index, value) in values.iter().enumerate() {
+            let start = offset + index * 4;
+            bytes[start..start + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // Levels are stored back to back after the level index
+    fn ktx2(
+        format: vk::Format,
+        extent: [u32; 3],
+        layers: u32,
+        faces: u32,
+        levels: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut bytes = vec![0u8; KTX2_HEADER_SIZE + levels.len() * 24];
+        bytes[..12].copy_from_slice(&KTX2_IDENTIFIER);
+        write_u32s(&mut bytes, 12, &[format.as_raw() as u32, 1]);
+        write_u32s(&mut bytes, 20, &extent);
+        write_u32s(&mut bytes, 32, &[layers, faces, levels.len() as u32, 0]);
+        for (level, data) in levels.iter().enumerate() {
+            let entry = KTX2_HEADER_SIZE + level * 24;
+            let offset = bytes.len() as u64;
+            bytes[entry..entry + 8].copy_from_slice(&offset.to_le_bytes());
+            bytes[entry + 8..entry + 16].copy_from_slice(&(data.len() as u64).to_le_bytes());
+            bytes[entry + 16..entry + 24].copy_from_slice(&(data.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+
+        bytes
+    }
+
+    // Legacy header, `dx10` is (DXGI format, misc flags, array size)
+    fn dds(
+        four_cc: &[u8; 4],
+        extent: [u32; 2],
+        level_count: u32,
+        dx10: Option<[u32; 3]>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = vec![0u8; DDS_HEADER_SIZE];
+        bytes[..4].copy_from_slice(&DDS_MAGIC);
+        write_u32s(
+            &mut bytes,
+            4,
+            &[124, 0, extent[1], extent[0], 0, 0, level_count],
+        );
+        write_u32s(&mut bytes, 76, &[32, 0x4]);
+        bytes[84..88].copy_from_slice(four_cc);
+        if let Some([dxgi_format, misc_flags, array_size]) = dx10 {
+            bytes.resize(DDS_HEADER_SIZE + DDS_DX10_HEADER_SIZE, 0);
+            write_u32s(
+                &mut bytes,
+                128,
+                &[dxgi_format, 3, misc_flags, array_size, 0],
+            );
+        }
+        bytes.extend_from_slice(data);
+
+        bytes
+    }
+
+    fn malformed_reason(result: Result<TextureData, TextureError>) -> String {
+        match result {
+            Err(TextureError::Malformed { reason, .. }) => reason,
+            Err(error) => panic!("unexpected error: {error}"),
+            Ok(_) => panic!("malformed file was accepted"),
+        }
+    }
+
+    // BC1 block where every texel has the RGB565 color `color`
+    fn bc1_block(color: u16) -> [u8; 8] {
+        let mut block = [0u8; 8];
+        block[..2].copy_from_slice(&color.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn parses_ktx2_mip_chains() {
+        let levels = vec![vec![1u8; 4 * 2 * 4], vec![2; 2 * 4], vec![3; 4]];
+        let bytes = ktx2(vk::Format::R8G8B8A8_UNORM, [4, 2, 0], 0, 1, &levels);
+        let texture = TextureData::from_ktx2("test", &bytes).unwrap();
+
+        assert_eq!(texture.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!(
+            (
+                texture.extent.width,
+                texture.extent.height,
+                texture.extent.depth
+            ),
+            (4, 2, 1)
+        );
+        assert_eq!((texture.array_layers, texture.is_cube), (1, false));
+        assert_eq!(texture.levels, levels);
+
+        let bytes = ktx2(
+            vk::Format::BC1_RGBA_SRGB_BLOCK,
+            [1, 1, 0],
+            2,
+            6,
+            &[vec![0; 12 * 8]],
+        );
+        let texture = TextureData::from_ktx2("test", &bytes).unwrap();
+        assert_eq!((texture.array_layers, texture.is_cube), (12, true));
+    }
+
+    #[test]
+    fn rejects_malformed_ktx2() {
+        let format = vk::Format::R8G8B8A8_UNORM;
+        let rgba = |texels: usize| vec![0u8; texels * 4];
+
+        let mut bytes = ktx2(format, [1, 1, 0], 0, 1, &[rgba(1)]);
+        bytes[1] = b'X';
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "not a KTX2 file"
+        );
+
+        let bytes = ktx2(format, [2, 2, 0], 0, 1, &[rgba(4), rgba(1), rgba(1)]);
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "more levels than the extent allows"
+        );
+        let bytes = ktx2(format, [0, 2, 0], 0, 1, &[rgba(0)]);
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "width is zero"
+        );
+        let bytes = ktx2(format, [2, 2, 0], 0, 4, &[rgba(4)]);
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "face count must be 1 or 6"
+        );
+        let bytes = ktx2(format, [2, 2, 0], 0, 1, &[rgba(3)]);
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "level size doesn't match the extent"
+        );
+
+        let mut bytes = ktx2(format, [1, 1, 0], 0, 1, &[rgba(1)]);
+        bytes.truncate(bytes.len() - 1);
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "level data is out of bounds"
+        );
+        let mut bytes = ktx2(format, [1, 1, 0], 0, 1, &[rgba(1)]);
+        bytes[KTX2_HEADER_SIZE..KTX2_HEADER_SIZE + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "level data is out of bounds"
+        );
+        let mut bytes = ktx2(format, [2, 2, 0], 0, 1, &[rgba(4)]);
+        write_u32s(&mut bytes, 40, &[2]);
+        bytes.truncate(KTX2_HEADER_SIZE + 24);
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "truncated level index"
+        );
+
+        let bytes = ktx2(format, [1, 1, 0], u32::MAX, 6, &[rgba(1)]);
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "too many array layers"
+        );
+        let bytes = ktx2(format, [u32::MAX, u32::MAX, u32::MAX], 0, 1, &[rgba(1)]);
+        assert_eq!(
+            malformed_reason(TextureData::from_ktx2("test", &bytes)),
+            "level size overflows"
+        );
+
+        for format in [vk::Format::UNDEFINED, vk::Format::from_raw(0x7FFF_0000)] {
+            let bytes = ktx2(format, [1, 1, 0], 0, 1, &[rgba(1)]);
+            assert!(matches!(
+                TextureData::from_ktx2("test", &bytes),
+                Err(TextureError::UnsupportedFormat { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn parses_dds_layouts() {
+        // 8x8 with two levels, 2x2 and 1x1 blocks
+        let bytes = dds(b"DXT1", [8, 8], 2, None, &[7; 5 * 8]);
+        let texture = TextureData::from_dds("test", &bytes, true).unwrap();
+        assert_eq!(texture.format, vk::Format::BC1_RGBA_SRGB_BLOCK);
+        assert_eq!(
+            texture.levels.iter().map(Vec::len).collect::<Vec<_>>(),
+            [4 * 8, 8]
+        );
+        let texture = TextureData::from_dds("test", &bytes, false).unwrap();
+        assert_eq!(texture.format, vk::Format::BC1_RGBA_UNORM_BLOCK);
+
+        // Every level of a layer is stored before the next layer, texture data
+        // is regrouped by level
+        let mut data = Vec::new();
+        for layer in 0..12u8 {
+            data.extend_from_slice(&[layer; 2 * 2 * 4]);
+            data.extend_from_slice(&[layer + 100; 4]);
+        }
+        let bytes = dds(
+            b"DX10",
+            [2, 2],
+            2,
+            Some([28, DDS_RESOURCE_MISC_TEXTURECUBE, 2]),
+            &data,
+        );
+        let texture = TextureData::from_dds("test", &bytes, true).unwrap();
+        assert_eq!(texture.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!((texture.array_layers, texture.is_cube), (12, true));
+        assert_eq!(texture.levels[0][16..32], [1; 16]);
+        assert_eq!(
+            texture.levels[1][..8],
+            [100, 100, 100, 100, 101, 101, 101, 101]
+        );
+
+        let mut bytes = dds(&[0; 4], [1, 1], 1, None, &[0; 4]);
+        write_u32s(&mut bytes, 80, &[DDPF_RGB, 0, 32, 0x00FF_0000]);
+        let texture = TextureData::from_dds("test", &bytes, false).unwrap();
+        assert_eq!(texture.format, vk::Format::B8G8R8A8_UNORM);
+    }
+
+    #[test]
+    fn rejects_malformed_dds() {
+        let bytes = dds(b"DXT1", [8, 8], 2, None, &[0; 4 * 8]);
+        assert_eq!(
+            malformed_reason(TextureData::from_dds("test", &bytes, true)),
+            "truncated texel data"
+        );
+        let bytes = dds(b"DXT1", [0, 8], 1, None, &[0; 8]);
+        assert_eq!(
+            malformed_reason(TextureData::from_dds("test", &bytes, true)),
+            "width is zero"
+        );
+        let bytes = dds(b"DXT1", [4, 4], 4, None, &[0; 4 * 8]);
+        assert_eq!(
+            malformed_reason(TextureData::from_dds("test", &bytes, true)),
+            "more levels than the extent allows"
+        );
+        let bytes = dds(
+            b"DX10",
+            [1, 1],
+            1,
+            Some([28, DDS_RESOURCE_MISC_TEXTURECUBE, u32::MAX]),
+            &[],
+        );
+        assert_eq!(
+            malformed_reason(TextureData::from_dds("test", &bytes, true)),
+            "too many array layers"
+        );
+        let mut bytes = dds(b"DX10", [1, 1], 1, Some([28, 0, 1]), &[]);
+        bytes.truncate(DDS_HEADER_SIZE + 4);
+        assert_eq!(
+            malformed_reason(TextureData::from_dds("test", &bytes, true)),
+            "truncated DX10 header"
+        );
+
+        for bytes in [
+            dds(b"DX10", [1, 1], 1, Some([1, 0, 1]), &[0; 16]),
+            dds(b"ETC2", [1, 1], 1, None, &[0; 8]),
+        ] {
+            assert!(matches!(
+                TextureData::from_dds("test", &bytes, true),
+                Err(TextureError::UnsupportedFormat { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn decodes_partial_bc_blocks() {
+        // 6x5 is covered by 2x2 blocks that hang over the right and bottom edges
+        let colors = [0xF800, 0x07E0, 0x001F, 0xFFFF];
+        let expected = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [255; 4],
+        ];
+        let bytes = dds(
+            b"DXT1",
+            [6, 5],
+            2,
+            None,
+            &[colors.map(bc1_block).concat(), bc1_block(0xFFFF).to_vec()].concat(),
+        );
+        let texture = TextureData::from_dds("test", &bytes, false).unwrap();
+        let decoded = texture.decode_bc().unwrap();
+
+        assert_eq!(decoded.format, vk::Format::R8G8B8A8_UNORM);
+        assert_eq!(decoded.levels[0].len(), 6 * 5 * 4);
+        for (index, texel) in decoded.levels[0].chunks_exact(4).enumerate() {
+            let (x, y) = (index % 6, index / 6);
+            assert_eq!(texel, expected[y / 4 * 2 + x / 4], "texel ({x}, {y})");
+        }
+        assert_eq!(decoded.levels[1], [255; 3 * 2 * 4]);
+
+        // Single channel formats keep their channel count
+        let texture = TextureData {
+            format: vk::Format::BC4_UNORM_BLOCK,
+            extent: vk::Extent3D {
+                width: 2,
+                height: 3,
+                depth: 1,
+            },
+            array_layers: 2,
+            is_cube: false,
+            levels: vec![[[255, 255, 0, 0, 0, 0, 0, 0], [0; 8]].concat()],
+        };
+        let decoded = texture.decode_bc().unwrap();
+        assert_eq!(decoded.format, vk::Format::R8_UNORM);
+        assert_eq!(decoded.levels[0], [[255; 6], [0; 6]].concat());
+
+        let rgba = TextureData::rgba8(1, 1, vec![0; 4], true);
+        assert!(rgba.decode_bc().is_none());
+    }
+}
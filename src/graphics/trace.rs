@@ -0,0 +1,214 @@
+use std::{
+    fmt::Write,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use super::{CommandType, Device, GpuTiming};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TraceTrack {
+    MainThread,
+    Queue(CommandType),
+    // Time spent blocked in swapchain acquire/present
+    Swapchain,
+}
+
+impl TraceTrack {
+    const ALL: [TraceTrack; 5] = [
+        TraceTrack::MainThread,
+        TraceTrack::Queue(CommandType::Graphics),
+        TraceTrack::Queue(CommandType::Compute),
+        TraceTrack::Queue(CommandType::Transfer),
+        TraceTrack::Swapchain,
+    ];
+
+    fn thread_id(self) -> u32 {
+        match self {
+            TraceTrack::MainThread => 1,
+            TraceTrack::Queue(CommandType::Graphics) => 2,
+            TraceTrack::Queue(CommandType::Compute) => 3,
+            TraceTrack::Queue(CommandType::Transfer) => 4,
+            TraceTrack::Swapchain => 5,
+        }
+    }
+
+    fn name(self) -> String {
+        match self {
+            TraceTrack::MainThread => String::from("Main thread"),
+            TraceTrack::Queue(command_type) => format!("{:?} queue", command_type),
+            TraceTrack::Swapchain => String::from("Swapchain"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceEvent {
+    pub name: String,
+    pub track: TraceTrack,
+    pub start: Instant,
+    pub duration: Duration,
+}
+
+// Records `frame_count` frames starting with the next `Device::new_frame` and
+// writes them as Chrome trace-event JSON once their GPU timings are resolved.
+pub struct TraceRecorder {
+    pub path: PathBuf,
+    pub frame_count: u32,
+    // Set when recording starts
+    pub frames: Option<(u64, u64)>,
+    pub start: Instant,
+    pub events: Vec<TraceEvent>,
+    current_frame: Option<(u64, Instant)>,
+}
+
+impl TraceRecorder {
+    pub fn new(path: PathBuf, frame_count: u32) -> Self {
+        Self {
+            path,
+            frame_count: frame_count.max(1),
+            frames: None,
+            start: Instant::now(),
+            events: Vec::new(),
+            current_frame: None,
+        }
+    }
+
+    fn is_recording(&self, frame: u64) -> bool {
+        self.frames
+            .is_some_and(|(first, last)| (first..=last).contains(&frame))
+    }
+
+    // Closes the main thread event of the previous frame
+    pub fn begin_frame(&mut self, frame: u64) {
+        let now = Instant::now();
+        if self.frames.is_none() {
+            self.start = now;
+            self.frames = Some((frame, frame + self.frame_count as u64 - 1));
+        }
+
+        if let Some((previous_frame, start)) = self.current_frame.take() {
+            self.events.push(TraceEvent {
+                name: format!("Frame {}", previous_frame),
+                track: TraceTrack::MainThread,
+                start,
+                duration: now.duration_since(start),
+            });
+        }
+        if self.is_recording(frame) {
+            self.current_frame = Some((frame, now));
+        }
+    }
+
+    pub fn record(&mut self, name: &str, track: TraceTrack, start: Instant) {
+        if self.current_frame.is_none() {
+            return;
+        }
+
+        self.events.push(TraceEvent {
+            name: name.to_owned(),
+            track,
+            start,
+            duration: start.elapsed(),
+        });
+    }
+
+    // GPU timestamps can't be compared with `Instant`s, so a frame's scopes are
+    // placed relative to its first submission, each queue on its own track. GPU
+    // work never starts earlier.
+    pub fn record_gpu_timings(&mut self, frame: u64, submit_time: Instant, timings: &[GpuTiming]) {
+        if !self.is_recording(frame) {
+            return;
+        }
+
+        for timing in timings {
+            self.events.push(TraceEvent {
+                name: timing.name.clone(),
+                track: TraceTrack::Queue(timing.command_type),
+                start: submit_time + Duration::from_secs_f64(timing.start_ms / 1000.0),
+                duration: Duration::from_secs_f64(timing.duration_ms / 1000.0),
+            });
+            self.record_gpu_timings(frame, submit_time, &timing.children);
+        }
+    }
+
+    // Every recorded frame has been resolved once `frame` is this far ahead
+    pub fn is_complete(&self, frame: u64, frames_in_flight: u32) -> bool {
+        self.frames
+            .is_some_and(|(_, last)| frame >= last + frames_in_flight as u64)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"displayTimeUnit\":\"ms\",\"traceEvents\":[\n");
+        for track in TraceTrack::ALL {
+            writeln!(
+                json,
+                "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}},",
+                track.thread_id(),
+                track.name()
+            )
+            .unwrap();
+        }
+
+        let events: Vec<String> = self
+            .events
+            .iter()
+            .map(|event| {
+                format!(
+                    "{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+                    escape_json(&event.name),
+                    event.track.thread_id(),
+                    event.start.saturating_duration_since(self.start).as_secs_f64() * 1_000_000.0,
+                    event.duration.as_secs_f64() * 1_000_000.0
+                )
+            })
+            .collect();
+        json.push_str(&events.join(",\n"));
+        json.push_str("\n]}\n");
+
+        json
+    }
+
+    pub fn write(&self) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        std::fs::write(&self.path, self.to_json())
+    }
+}
+
+fn escape_json(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if c.is_control() => write!(result, "\\u{:04x}", c as u32).unwrap(),
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+// Times the enclosing block on the main thread track while a trace is recording
+#[must_use]
+pub struct CpuScope<'a> {
+    pub device: &'a Device,
+    pub name: String,
+    pub start: Instant,
+}
+
+impl CpuScope<'_> {
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+impl Drop for CpuScope<'_> {
+    fn drop(&mut self) {
+        self.device
+            .trace_event(&self.name, TraceTrack::MainThread, self.start);
+    }
+}
@@ -1,5 +1,5 @@
 use ash::vk;
-use graphics::CommandType;
+use lr_rs::graphics::{self, CommandType, RenderingAttachment, ResourceState};
 #[cfg(target_os = "linux")]
 use winit::platform::x11::WindowAttributesExtX11;
 use winit::{
@@ -11,15 +11,12 @@ use winit::{
 
 use std::{default::Default, error::Error};
 
-mod graphics;
-
 struct Renderer {
     device: graphics::Device,
     swapchain: graphics::SwapChain,
-    swapchain_images: Vec<graphics::Image>,
+    swapchain_images: Vec<graphics::ImageID>,
     swapchain_image_views: Vec<graphics::ImageView>,
-    command_allocators: Vec<graphics::CommandAllocator>,
-    command_lists: Vec<graphics::CommandList>,
+    render_graph: graphics::RenderGraph,
 }
 
 #[derive(Default)]
@@ -31,56 +28,52 @@ struct Application {
 impl Application {
     fn draw(&mut self) {
         let renderer = self.renderer.as_mut().unwrap();
-        let sema_index = renderer.device.new_frame();
-        let frame_sema = &renderer.device.frame_sema;
-        let (acquire_sema, present_sema) = renderer.swapchain.frame_semas(sema_index as u64);
+        let frame_index = renderer.device.new_frame();
+        let (acquire_sema, present_sema) = renderer.swapchain.frame_semas(frame_index as u64);
         let image_index = renderer
             .device
             .acquire_next_image(&renderer.swapchain, acquire_sema)
             .unwrap();
-        let image = &renderer.swapchain_images[image_index as usize];
-        let image_view = &renderer.swapchain_image_views[image_index as usize];
-        let command_queue = &renderer.device.queue_at(CommandType::Graphics);
-        let command_allocator = &renderer.command_allocators[image_index as usize];
-        let command_list = &renderer.command_lists[image_index as usize];
-
-        renderer.device.reset_command_allocator(command_allocator);
-        renderer.device.begin_command_list(command_list);
-
-        let transition_barrier = vk::ImageMemoryBarrier2::default()
-            .src_stage_mask(vk::PipelineStageFlags2::TOP_OF_PIPE)
-            .src_access_mask(vk::AccessFlags2::NONE)
-            .dst_stage_mask(vk::PipelineStageFlags2::BOTTOM_OF_PIPE)
-            .dst_access_mask(vk::AccessFlags2::NONE)
-            .old_layout(vk::ImageLayout::UNDEFINED)
-            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-            .subresource_range(image_view.subresource_range)
-            .image(image.into());
-        command_list.image_barrier(transition_barrier);
-
-        renderer.device.end_command_list(command_list);
+        let image_id = renderer.swapchain_images[image_index as usize];
+        let image_view = renderer.swapchain_image_views[image_index as usize];
+        let render_area = vk::Rect2D::default().extent(renderer.swapchain.extent);
+
+        let render_graph = &mut renderer.render_graph;
+        render_graph.begin(&mut renderer.device, frame_index);
+        render_graph
+            .add_pass("clear", CommandType::Graphics)
+            .write_image(image_id, ResourceState::COLOR_ATTACHMENT)
+            .execute(move |_, command_list| {
+                let color_attachments = [RenderingAttachment {
+                    image_view: &image_view,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                    clear_value: vk::ClearValue {
+                        color: vk::ClearColorValue {
+                            float32: [0.1, 0.1, 0.1, 1.0],
+                        },
+                    },
+                }];
+                command_list
+                    .begin_rendering(&color_attachments, None, None, render_area, 1)
+                    .end_rendering();
+            });
+        render_graph.output_image(image_id, Some(ResourceState::PRESENT));
+        render_graph.compile(&mut renderer.device).unwrap();
 
-        let command_list_infos =
-            [vk::CommandBufferSubmitInfo::default().command_buffer(command_list.into())];
         let wait_sema_infos = [vk::SemaphoreSubmitInfo::default()
             .semaphore(acquire_sema.into())
-            .stage_mask(vk::PipelineStageFlags2::TOP_OF_PIPE)];
+            .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)];
         let signal_sema_infos = [
             vk::SemaphoreSubmitInfo::default()
                 .semaphore(present_sema.into())
                 .stage_mask(vk::PipelineStageFlags2::BOTTOM_OF_PIPE),
-            vk::SemaphoreSubmitInfo::default()
-                .semaphore(frame_sema.into())
-                .value(frame_sema.counter + 1)
-                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS),
+            renderer.device.frame_signal_info(),
         ];
 
-        let submit_info = vk::SubmitInfo2::default()
-            .wait_semaphore_infos(&wait_sema_infos)
-            .signal_semaphore_infos(&signal_sema_infos)
-            .command_buffer_infos(&command_list_infos);
-        renderer.device.submit(command_queue, submit_info).unwrap();
-        renderer.device.end_frame();
+        render_graph
+            .execute(&mut renderer.device, &wait_sema_infos, &signal_sema_infos)
+            .unwrap();
         renderer
             .device
             .present(&renderer.swapchain, present_sema, image_index)
@@ -102,30 +95,15 @@ impl ApplicationHandler for Application {
         let window = event_loop
             .create_window(window_attributes)
             .expect("Failed to create window");
-        let device = graphics::Device::new(3).unwrap();
+        let mut device = graphics::Device::new(3).unwrap();
         let swapchain = device.create_swapchain(&window).unwrap();
         let (swapchain_images, swapchain_image_views) =
             device.get_swapchain_images(&swapchain).unwrap();
-        let mut command_allocators = Vec::new();
-        (0..device.frame_count).for_each(|_| {
-            command_allocators.push(
-                device
-                    .create_command_allocator(
-                        CommandType::Graphics,
-                        vk::CommandPoolCreateFlags::default(),
-                    )
-                    .unwrap(),
-            );
-        });
-
-        let mut command_lists = Vec::new();
-        (0..device.frame_count).for_each(|i| {
-            command_lists.push(
-                device
-                    .create_command_list(&command_allocators[i as usize])
-                    .unwrap(),
-            );
-        });
+        let swapchain_images = swapchain_images
+            .into_iter()
+            .map(|image| device.register_image(image))
+            .collect();
+        let render_graph = graphics::RenderGraph::new(device.frame_count);
 
         self.window = Some(window);
         self.renderer = Some(Renderer {
@@ -133,8 +111,7 @@ impl ApplicationHandler for Application {
             swapchain,
             swapchain_images,
             swapchain_image_views,
-            command_allocators,
-            command_lists,
+            render_graph,
         })
     }
 